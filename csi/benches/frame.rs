@@ -0,0 +1,117 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use csi::{
+    frame::{unpack_complex, unpack_csi, unpack_csi_into, Chip, Frame, FrameRef},
+    ieee80211::{Band, Bandwidth},
+    params::ChanSpec,
+};
+use macaddr::MacAddr6;
+use num_complex::Complex;
+
+fn packet(channel: u8, bandwidth: Bandwidth) -> Vec<u8> {
+    let chan_spec = ChanSpec::new(channel, Band::Band5G, bandwidth).unwrap();
+    let n = bandwidth.nsub_pow2() as u32;
+
+    Frame {
+        rssi: -51,
+        source_mac: MacAddr6::new(0x50, 0xed, 0x3c, 0x2e, 0x04, 0x00),
+        seq_cnt: 0,
+        core: 0,
+        spatial: 0,
+        chan_spec,
+        chip: Chip::Bcm4366c0,
+        csi: (0..n)
+            .map(|i| unpack_complex(i.wrapping_mul(0x9e37_79b9)))
+            .collect(),
+    }
+    .to_bytes()
+    .unwrap()
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+
+    for (channel, bandwidth) in [
+        (36, Bandwidth::Bw20),
+        (36, Bandwidth::Bw40),
+        (36, Bandwidth::Bw80),
+        (36, Bandwidth::Bw160),
+    ] {
+        let packet = packet(channel, bandwidth);
+        let mhz = bandwidth.mhz();
+
+        group.bench_with_input(BenchmarkId::new("Frame", mhz), &packet, |b, packet| {
+            b.iter(|| Frame::from_slice(black_box(packet)).unwrap())
+        });
+
+        group.bench_with_input(
+            BenchmarkId::new("FrameRef/f64", mhz),
+            &packet,
+            |b, packet| {
+                let mut buf = vec![Complex::<f64>::default(); bandwidth.nsub_pow2()];
+                b.iter(|| {
+                    let frame = FrameRef::from_slice(black_box(packet)).unwrap();
+                    frame.decode_into(&mut buf);
+                    black_box(&buf);
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("FrameRef/f32", mhz),
+            &packet,
+            |b, packet| {
+                let mut buf = vec![Complex::<f32>::default(); bandwidth.nsub_pow2()];
+                b.iter(|| {
+                    let frame = FrameRef::from_slice(black_box(packet)).unwrap();
+                    frame.decode_into(&mut buf);
+                    black_box(&buf);
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+fn unpack(c: &mut Criterion) {
+    let mut group = c.benchmark_group("unpack");
+
+    for bandwidth in [
+        Bandwidth::Bw20,
+        Bandwidth::Bw40,
+        Bandwidth::Bw80,
+        Bandwidth::Bw160,
+    ] {
+        let n = bandwidth.nsub_pow2() as u32;
+        let words = (0..n)
+            .flat_map(|i| i.wrapping_mul(0x9e37_79b9).to_le_bytes())
+            .collect::<Vec<_>>();
+        let mhz = bandwidth.mhz();
+        let mut buf = vec![Complex::<f64>::default(); n as usize];
+
+        group.bench_with_input(BenchmarkId::new("unpack_csi", mhz), &words, |b, words| {
+            b.iter(|| {
+                for (dst, z) in buf.iter_mut().zip(unpack_csi(black_box(words))) {
+                    *dst = z;
+                }
+                black_box(&buf);
+            })
+        });
+
+        group.bench_with_input(
+            BenchmarkId::new("unpack_csi_into", mhz),
+            &words,
+            |b, words| {
+                b.iter(|| {
+                    unpack_csi_into(black_box(words), &mut buf);
+                    black_box(&buf);
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, parse, unpack);
+criterion_main!(benches);
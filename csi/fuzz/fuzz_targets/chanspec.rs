@@ -1,9 +1,11 @@
 #![no_main]
 
+use csi::params::ChanSpec;
 use libfuzzer_sys::fuzz_target;
 
 fuzz_target!(|data: u16| {
-    if let Ok(chan_spec) = csi::params::ChanSpec::try_from(data) {
-        let _ = chan_spec.bandwidth();
+    if let Ok(chan_spec) = ChanSpec::try_from(data) {
+        assert_eq!(u16::from(chan_spec), data);
+        assert_eq!(chan_spec.to_string().parse::<ChanSpec>(), Ok(chan_spec));
     }
 });
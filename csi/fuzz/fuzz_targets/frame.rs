@@ -0,0 +1,16 @@
+#![no_main]
+
+use csi::{frame::Frame, params::ChanSpec};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(frame) = Frame::from_slice(data) {
+        let chan_spec = frame.chan_spec;
+        assert_eq!(chan_spec.to_string().parse::<ChanSpec>(), Ok(chan_spec));
+
+        let bytes = frame
+            .to_bytes()
+            .expect("decoded frames should be encodable");
+        assert_eq!(Frame::from_slice(&bytes).unwrap(), frame);
+    }
+});
@@ -0,0 +1,49 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use csi::{
+    frame::{unpack_complex, Chip, Frame},
+    params::ChanSpec,
+};
+use libfuzzer_sys::fuzz_target;
+use macaddr::MacAddr6;
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    rssi: i8,
+    source_mac: [u8; 6],
+    seq_cnt: u16,
+    core: u8,
+    spatial: u8,
+    chan_spec: ChanSpec,
+    chip: Chip,
+    words: Vec<u32>,
+}
+
+fuzz_target!(|input: Input| {
+    let chan_spec = input.chan_spec;
+    assert_eq!(chan_spec.to_string().parse::<ChanSpec>(), Ok(chan_spec));
+
+    let frame = Frame {
+        rssi: input.rssi,
+        source_mac: MacAddr6::from(input.source_mac),
+        seq_cnt: input.seq_cnt,
+        core: input.core & 0b111,
+        spatial: input.spatial & 0b111,
+        chan_spec: input.chan_spec,
+        chip: input.chip,
+        csi: input.words.into_iter().map(unpack_complex).collect(),
+    };
+
+    let bytes = frame
+        .to_bytes()
+        .expect("unpacked values should be packable");
+
+    match Frame::from_slice(&bytes) {
+        Ok(decoded) => assert_eq!(decoded, frame),
+        Err(csi::frame::Error::NotEnoughBytes) => {
+            assert!(frame.csi.len() < frame.chan_spec.bandwidth().nsub_pow2())
+        }
+        Err(e) => panic!("{e}"),
+    }
+});
@@ -0,0 +1,1073 @@
+//! Compact, indexed archive of CSI recordings.
+//!
+//! Unlike a pcap, an archive can be read without a linear scan: records are
+//! stored in compressed chunks, and an index of the chunks (with their time
+//! and sequence number ranges) lets [`Archive`] decode only the chunks that
+//! are needed. Recordings can be appended to, also while they are being
+//! read, which makes the format suitable for live capture.
+//!
+//! # Layout
+//!
+//! All integers are little-endian.
+//!
+//! | Part | Contents |
+//! |------|----------|
+//! | Header | `CSIARC\0` and a version byte, the [`Metadata`] length (`u32`) and the metadata |
+//! | Chunk | `CHNK`, the chunk header and the payload, possibly DEFLATE compressed |
+//! | ... | more chunks |
+//! | Index | `CIDX`, the number of chunks (`u32`) and an entry per chunk |
+//! | Trailer | the offset of the index (`u64`) and `CSIAEND\0` |
+//!
+//! The index is only written by [`ArchiveWriter::finish`]. Archives without
+//! one, such as those of an ongoing (or interrupted) capture, are indexed by
+//! reading the chunk headers instead.
+//!
+//! Chunk payloads are stored column by column, with the CSI as `f32`, which is
+//! lossless for the values reported by the firmware.
+
+use std::{
+    io::{self, Read, Seek, SeekFrom, Write},
+    ops::{Bound, RangeBounds},
+    time::Duration,
+};
+
+use macaddr::MacAddr6;
+use ndarray::Array1;
+use num_complex::Complex;
+
+use crate::{frame::Scalar, params::ChanSpec, proc::WifiCsi};
+
+const MAGIC: [u8; 7] = *b"CSIARC\0";
+const VERSION: u8 = 1;
+const CHUNK_MAGIC: [u8; 4] = *b"CHNK";
+const INDEX_MAGIC: [u8; 4] = *b"CIDX";
+const TRAILER_MAGIC: [u8; 8] = *b"CSIAEND\0";
+
+const CHUNK_HEADER_LEN: usize = 41;
+const INDEX_ENTRY_LEN: usize = 32;
+const TRAILER_LEN: usize = 16;
+
+// payload compression
+const STORED: u8 = 0;
+const DEFLATE: u8 = 1;
+const DEFLATE_LEVEL: u8 = 6;
+
+/// Default number of records per chunk.
+pub const DEFAULT_CHUNK_LEN: usize = 256;
+
+/// Error returned when reading an archive.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Reading from the underlying reader failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The file is not a CSI archive.
+    #[error("not a CSI archive")]
+    NotAnArchive,
+    /// The archive was written by a newer version of this crate.
+    #[error("unsupported archive version {0}")]
+    UnsupportedVersion(u8),
+    /// A chunk or the metadata is malformed or fails its checksum.
+    #[error("corrupt archive")]
+    Corrupt,
+}
+
+/// Information about the recording as a whole.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    /// Capturing device, such as `"RT-AC86U"`.
+    pub device: String,
+    /// Antenna positions (m), indexed by core.
+    pub antennas: Vec<[f64; 3]>,
+    /// Factors that the CSI of each core should be multiplied by to
+    /// compensate for differences between the RF chains, indexed by core.
+    pub calibration: Vec<Complex<f64>>,
+    /// Any other information, as key-value pairs.
+    pub extra: Vec<(String, String)>,
+}
+
+impl Metadata {
+    fn encode(&self) -> Vec<u8> {
+        fn put_str(b: &mut Vec<u8>, s: &str) {
+            b.extend((s.len() as u32).to_le_bytes());
+            b.extend(s.as_bytes());
+        }
+
+        let mut b = vec![];
+        put_str(&mut b, &self.device);
+        b.extend((self.antennas.len() as u32).to_le_bytes());
+        for x in self.antennas.iter().flatten() {
+            b.extend(x.to_le_bytes());
+        }
+        b.extend((self.calibration.len() as u32).to_le_bytes());
+        for z in &self.calibration {
+            b.extend(z.re.to_le_bytes());
+            b.extend(z.im.to_le_bytes());
+        }
+        b.extend((self.extra.len() as u32).to_le_bytes());
+        for (key, value) in &self.extra {
+            put_str(&mut b, key);
+            put_str(&mut b, value);
+        }
+        b
+    }
+
+    fn decode(b: &[u8]) -> Result<Self, Error> {
+        fn string(r: &mut Reader) -> Result<String, Error> {
+            let len = r.u32()? as usize;
+            String::from_utf8(r.take(len)?.to_vec()).map_err(|_| Error::Corrupt)
+        }
+
+        let mut r = Reader(b);
+        let device = string(&mut r)?;
+        let antennas = (0..r.u32()?)
+            .map(|_| Ok([r.f64()?, r.f64()?, r.f64()?]))
+            .collect::<Result<_, Error>>()?;
+        let calibration = (0..r.u32()?)
+            .map(|_| Ok(Complex::new(r.f64()?, r.f64()?)))
+            .collect::<Result<_, Error>>()?;
+        let extra = (0..r.u32()?)
+            .map(|_| Ok((string(&mut r)?, string(&mut r)?)))
+            .collect::<Result<_, Error>>()?;
+
+        Ok(Self {
+            device,
+            antennas,
+            calibration,
+            extra,
+        })
+    }
+}
+
+/// A [`WifiCsi`] with the metadata of the frames it was assembled from.
+#[derive(Debug, Clone)]
+pub struct Record<T = f64> {
+    /// Capture time, relative to the Unix epoch.
+    pub timestamp: Duration,
+    /// MAC address of the transmitter.
+    pub source_mac: MacAddr6,
+    /// 802.11 sequence number.
+    pub seq_cnt: u16,
+    /// The CSI.
+    pub csi: WifiCsi<T>,
+}
+
+/// Location and contents of a chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkInfo {
+    /// Position of the chunk in the file.
+    pub offset: u64,
+    /// Index of the first record.
+    pub first: usize,
+    /// Number of records.
+    pub len: usize,
+    /// Earliest timestamp.
+    pub start: Duration,
+    /// Latest timestamp.
+    pub end: Duration,
+    /// Sequence number of the first record.
+    pub first_seq: u16,
+    /// Sequence number of the last record.
+    pub last_seq: u16,
+}
+
+impl ChunkInfo {
+    fn contains_seq(&self, seq: u16) -> bool {
+        // sequence numbers wrap around
+        if self.first_seq <= self.last_seq {
+            (self.first_seq..=self.last_seq).contains(&seq)
+        } else {
+            seq >= self.first_seq || seq <= self.last_seq
+        }
+    }
+
+    fn overlaps(&self, range: &impl RangeBounds<Duration>) -> bool {
+        let after_start = match range.start_bound() {
+            Bound::Included(start) => self.end >= *start,
+            Bound::Excluded(start) => self.end > *start,
+            Bound::Unbounded => true,
+        };
+        let before_end = match range.end_bound() {
+            Bound::Included(end) => self.start <= *end,
+            Bound::Excluded(end) => self.start < *end,
+            Bound::Unbounded => true,
+        };
+        after_start && before_end
+    }
+
+    fn write_index_entry(&self, b: &mut Vec<u8>) {
+        b.extend(self.offset.to_le_bytes());
+        b.extend((self.len as u32).to_le_bytes());
+        b.extend(nanos(self.start).to_le_bytes());
+        b.extend(nanos(self.end).to_le_bytes());
+        b.extend(self.first_seq.to_le_bytes());
+        b.extend(self.last_seq.to_le_bytes());
+    }
+
+    fn read_index_entry(r: &mut Reader, first: usize) -> Result<Self, Error> {
+        Ok(Self {
+            offset: r.u64()?,
+            first,
+            len: r.u32()? as usize,
+            start: Duration::from_nanos(r.u64()?),
+            end: Duration::from_nanos(r.u64()?),
+            first_seq: r.u16()?,
+            last_seq: r.u16()?,
+        })
+    }
+}
+
+fn nanos(t: Duration) -> u64 {
+    t.as_nanos().try_into().unwrap_or(u64::MAX)
+}
+
+/// Reads little-endian values from a byte slice.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if n > self.0.len() {
+            return Err(Error::Corrupt);
+        }
+        let (b, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(b)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    fn f64(&mut self) -> Result<f64, Error> {
+        self.array().map(f64::from_le_bytes)
+    }
+}
+
+/// Records of a chunk that has not been written yet, stored column by column.
+#[derive(Default)]
+struct Columns {
+    timestamps: Vec<u64>,
+    macs: Vec<u8>,
+    seqs: Vec<u16>,
+    chan_specs: Vec<u16>,
+    rssi: Vec<i8>,
+    /// Bit `4 * core + spatial` is set if the stream is present.
+    masks: Vec<u16>,
+    /// Number of subcarriers of each present stream.
+    nsubs: Vec<u16>,
+    /// Real and imaginary parts of the present streams.
+    values: Vec<f32>,
+}
+
+impl Columns {
+    fn len(&self) -> usize {
+        self.timestamps.len()
+    }
+
+    fn push<T: Scalar>(&mut self, record: &Record<T>) -> io::Result<()> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidInput, msg);
+
+        let timestamp = u64::try_from(record.timestamp.as_nanos())
+            .map_err(|_| invalid("timestamp out of range"))?;
+        let streams: Vec<_> = (0..16)
+            .filter_map(|i| Some((i, record.csi.get(i / 4, i % 4)?)))
+            .collect();
+        if streams
+            .iter()
+            .any(|(_, frame)| frame.len() > u16::MAX.into())
+        {
+            return Err(invalid("too many subcarriers"));
+        }
+
+        self.timestamps.push(timestamp);
+        self.macs.extend(record.source_mac.as_bytes());
+        self.seqs.push(record.seq_cnt);
+        self.chan_specs.push(record.csi.chan_spec.into());
+        self.rssi.push(record.csi.rssi);
+        self.masks
+            .push(streams.iter().fold(0, |mask, (i, _)| mask | 1 << i));
+        for (_, frame) in streams {
+            self.nsubs.push(frame.len() as u16);
+            self.values.extend(
+                frame
+                    .iter()
+                    .flat_map(|z| [z.re.to_f64() as f32, z.im.to_f64() as f32]),
+            );
+        }
+
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut b = vec![];
+        b.extend(self.timestamps.iter().flat_map(|x| x.to_le_bytes()));
+        b.extend(&self.macs);
+        b.extend(self.seqs.iter().flat_map(|x| x.to_le_bytes()));
+        b.extend(self.chan_specs.iter().flat_map(|x| x.to_le_bytes()));
+        b.extend(self.rssi.iter().map(|&x| x as u8));
+        b.extend(self.masks.iter().flat_map(|x| x.to_le_bytes()));
+        b.extend(self.nsubs.iter().flat_map(|x| x.to_le_bytes()));
+        // store each byte of the floats separately, so that the similar
+        // sign and exponent bytes end up next to each other
+        for lane in 0..4 {
+            b.extend(self.values.iter().map(|x| x.to_le_bytes()[lane]));
+        }
+        b
+    }
+}
+
+fn decode(b: &[u8], len: usize) -> Result<Vec<Record>, Error> {
+    let mut r = Reader(b);
+    let timestamps = (0..len).map(|_| r.u64()).collect::<Result<Vec<_>, _>>()?;
+    let macs = r.take(6 * len)?;
+    let seqs = (0..len).map(|_| r.u16()).collect::<Result<Vec<_>, _>>()?;
+    let chan_specs = (0..len).map(|_| r.u16()).collect::<Result<Vec<_>, _>>()?;
+    let rssi = r.take(len)?;
+    let masks = (0..len).map(|_| r.u16()).collect::<Result<Vec<_>, _>>()?;
+    let nstreams = masks.iter().map(|mask| mask.count_ones() as usize).sum();
+    let nsubs = (0..nstreams)
+        .map(|_| r.u16())
+        .collect::<Result<Vec<_>, _>>()?;
+    let nvalues = 2 * nsubs.iter().map(|&n| n as usize).sum::<usize>();
+    let lanes = r.take(4 * nvalues)?;
+    if !r.0.is_empty() {
+        return Err(Error::Corrupt);
+    }
+
+    let mut values = (0..nvalues).map(|i| {
+        f64::from(f32::from_le_bytes([
+            lanes[i],
+            lanes[nvalues + i],
+            lanes[2 * nvalues + i],
+            lanes[3 * nvalues + i],
+        ]))
+    });
+    let mut nsubs = nsubs.into_iter();
+
+    (0..len)
+        .map(|i| {
+            let chan_spec = ChanSpec::try_from(chan_specs[i]).map_err(|_| Error::Corrupt)?;
+            let mut csi = WifiCsi::new(chan_spec, rssi[i] as i8);
+            for stream in (0..16).filter(|stream| masks[i] & 1 << stream != 0) {
+                let nsub = nsubs.next().unwrap();
+                let frame = (0..nsub)
+                    .map(|_| Complex::new(values.next().unwrap(), values.next().unwrap()))
+                    .collect::<Array1<_>>();
+                csi.insert(stream / 4, stream % 4, frame);
+            }
+
+            Ok(Record {
+                timestamp: Duration::from_nanos(timestamps[i]),
+                source_mac: MacAddr6::from(<[u8; 6]>::try_from(&macs[6 * i..6 * i + 6]).unwrap()),
+                seq_cnt: seqs[i],
+                csi,
+            })
+        })
+        .collect()
+}
+
+/// Fixed-size part of a chunk.
+struct ChunkHeader {
+    /// Length of the payload as stored.
+    stored_len: u32,
+    /// Length of the uncompressed payload.
+    raw_len: u32,
+    crc: u32,
+    compression: u8,
+    len: u32,
+    start: u64,
+    end: u64,
+    first_seq: u16,
+    last_seq: u16,
+}
+
+impl ChunkHeader {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut b = Vec::with_capacity(CHUNK_HEADER_LEN);
+        b.extend(CHUNK_MAGIC);
+        b.extend(self.stored_len.to_le_bytes());
+        b.extend(self.raw_len.to_le_bytes());
+        b.extend(self.crc.to_le_bytes());
+        b.push(self.compression);
+        b.extend(self.len.to_le_bytes());
+        b.extend(self.start.to_le_bytes());
+        b.extend(self.end.to_le_bytes());
+        b.extend(self.first_seq.to_le_bytes());
+        b.extend(self.last_seq.to_le_bytes());
+        b
+    }
+
+    /// Parses a chunk header, or returns `None` if `b` is not one.
+    fn from_bytes(b: &[u8; CHUNK_HEADER_LEN]) -> Option<Self> {
+        let mut r = Reader(b);
+        if r.array().ok()? != CHUNK_MAGIC {
+            return None;
+        }
+
+        Some(Self {
+            stored_len: r.u32().ok()?,
+            raw_len: r.u32().ok()?,
+            crc: r.u32().ok()?,
+            compression: r.take(1).ok()?[0],
+            len: r.u32().ok()?,
+            start: r.u64().ok()?,
+            end: r.u64().ok()?,
+            first_seq: r.u16().ok()?,
+            last_seq: r.u16().ok()?,
+        })
+    }
+
+    fn info(&self, offset: u64, first: usize) -> ChunkInfo {
+        ChunkInfo {
+            offset,
+            first,
+            len: self.len as usize,
+            start: Duration::from_nanos(self.start),
+            end: Duration::from_nanos(self.end),
+            first_seq: self.first_seq,
+            last_seq: self.last_seq,
+        }
+    }
+}
+
+/// Writes an archive.
+///
+/// Records are buffered until a chunk is full; call [`flush`](Self::flush)
+/// to make them visible to readers earlier, and [`finish`](Self::finish) to
+/// write the index.
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::{io::Cursor, time::Duration};
+///
+/// use csi::{
+///     archive::{Archive, ArchiveWriter, Metadata, Record},
+///     ieee80211::{Band, Bandwidth},
+///     params::ChanSpec,
+///     proc::WifiCsi,
+/// };
+/// use macaddr::MacAddr6;
+///
+/// let metadata = Metadata {
+///     device: "RT-AC86U".into(),
+///     ..Default::default()
+/// };
+/// let chan_spec = ChanSpec::new(36, Band::Band5G, Bandwidth::Bw20).unwrap();
+/// let mut writer = ArchiveWriter::new(Cursor::new(vec![]), &metadata)?;
+/// for i in 0..1000 {
+///     writer.push(&Record {
+///         timestamp: Duration::from_millis(i),
+///         source_mac: MacAddr6::nil(),
+///         seq_cnt: i as u16,
+///         csi: WifiCsi::<f64>::new(chan_spec, -40),
+///     })?;
+/// }
+/// let file = writer.finish()?;
+///
+/// let mut archive = Archive::open(file)?;
+/// assert_eq!(archive.metadata().device, "RT-AC86U");
+/// assert_eq!(archive.len(), 1000);
+/// assert_eq!(archive.get(500)?.unwrap().seq_cnt, 500);
+///
+/// let second = archive.time_range(Duration::from_secs(1)..Duration::from_secs(2));
+/// assert_eq!(second.count(), 0);
+/// # Ok(())
+/// # }
+/// ```
+pub struct ArchiveWriter<W> {
+    w: W,
+    /// Position of the end of the last chunk.
+    pos: u64,
+    chunks: Vec<ChunkInfo>,
+    columns: Columns,
+    chunk_len: usize,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    /// Writes the header of a new archive. The archive starts at the
+    /// current position of `w`, which should be the start of the file.
+    pub fn new(mut w: W, metadata: &Metadata) -> io::Result<Self> {
+        let metadata = metadata.encode();
+        let mut header = MAGIC.to_vec();
+        header.push(VERSION);
+        header.extend((metadata.len() as u32).to_le_bytes());
+        header.extend(metadata);
+        w.write_all(&header)?;
+
+        Ok(Self {
+            w,
+            pos: header.len() as u64,
+            chunks: vec![],
+            columns: Columns::default(),
+            chunk_len: DEFAULT_CHUNK_LEN,
+        })
+    }
+
+    /// Sets the number of records per chunk. Smaller chunks make random
+    /// access faster but compress worse.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_len` is zero.
+    pub fn chunk_len(mut self, chunk_len: usize) -> Self {
+        assert!(chunk_len > 0, "chunk length must be positive");
+        self.chunk_len = chunk_len;
+        self
+    }
+
+    /// Returns the number of records written so far.
+    pub fn len(&self) -> usize {
+        self.chunks
+            .last()
+            .map_or(0, |chunk| chunk.first + chunk.len)
+            + self.columns.len()
+    }
+
+    /// Returns `true` if no records have been written.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Appends a record.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if the
+    /// record cannot be represented, such as a timestamp after the year 2554.
+    pub fn push<T: Scalar>(&mut self, record: &Record<T>) -> io::Result<()> {
+        self.columns.push(record)?;
+        if self.columns.len() >= self.chunk_len {
+            self.write_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn write_chunk(&mut self) -> io::Result<()> {
+        let columns = &self.columns;
+        if columns.len() == 0 {
+            return Ok(());
+        }
+
+        let raw = columns.encode();
+        let compressed = miniz_oxide::deflate::compress_to_vec(&raw, DEFLATE_LEVEL);
+        let (compression, payload) = if compressed.len() < raw.len() {
+            (DEFLATE, &compressed)
+        } else {
+            (STORED, &raw)
+        };
+
+        let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "chunk too large");
+        let header = ChunkHeader {
+            stored_len: payload.len().try_into().map_err(|_| too_large())?,
+            raw_len: raw.len().try_into().map_err(|_| too_large())?,
+            crc: crc32fast::hash(&raw),
+            compression,
+            len: columns.len() as u32,
+            start: *columns.timestamps.iter().min().unwrap(),
+            end: *columns.timestamps.iter().max().unwrap(),
+            first_seq: columns.seqs[0],
+            last_seq: *columns.seqs.last().unwrap(),
+        };
+        self.w.write_all(&header.to_bytes())?;
+        self.w.write_all(payload)?;
+
+        let info = header.info(self.pos, self.len() - columns.len());
+        self.chunks.push(info);
+        self.pos += (CHUNK_HEADER_LEN + payload.len()) as u64;
+        self.columns.clear();
+
+        Ok(())
+    }
+
+    /// Writes the buffered records as a (possibly short) chunk and flushes
+    /// the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.write_chunk()?;
+        self.w.flush()
+    }
+
+    /// Writes the buffered records and the index, returning the underlying
+    /// writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_chunk()?;
+
+        let mut b = INDEX_MAGIC.to_vec();
+        b.extend((self.chunks.len() as u32).to_le_bytes());
+        for chunk in &self.chunks {
+            chunk.write_index_entry(&mut b);
+        }
+        b.extend(self.pos.to_le_bytes());
+        b.extend(TRAILER_MAGIC);
+        self.w.write_all(&b)?;
+        self.w.flush()?;
+
+        Ok(self.w)
+    }
+}
+
+impl<W: Read + Write + Seek> ArchiveWriter<W> {
+    /// Opens an existing archive for appending. The metadata is kept; any
+    /// index is overwritten by the new chunks and rewritten by
+    /// [`finish`](Self::finish).
+    pub fn append(mut w: W) -> Result<Self, Error> {
+        let (pos, chunks) = {
+            let archive = Archive::open(&mut w)?;
+            (archive.end, archive.chunks)
+        };
+        w.seek(SeekFrom::Start(pos))?;
+
+        Ok(Self {
+            w,
+            pos,
+            chunks,
+            columns: Columns::default(),
+            chunk_len: DEFAULT_CHUNK_LEN,
+        })
+    }
+}
+
+/// Reads an archive with random access.
+pub struct Archive<R> {
+    r: R,
+    metadata: Metadata,
+    chunks: Vec<ChunkInfo>,
+    /// Position of the end of the last chunk.
+    end: u64,
+    /// The most recently decoded chunk.
+    cache: Option<(usize, Vec<Record>)>,
+}
+
+impl<R: Read + Seek> Archive<R> {
+    /// Opens an archive, reading its metadata and index.
+    pub fn open(mut r: R) -> Result<Self, Error> {
+        r.seek(SeekFrom::Start(0))?;
+        let mut header = [0; 12];
+        r.read_exact(&mut header).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => Error::NotAnArchive,
+            _ => e.into(),
+        })?;
+        if header[..7] != MAGIC {
+            return Err(Error::NotAnArchive);
+        }
+        if header[7] != VERSION {
+            return Err(Error::UnsupportedVersion(header[7]));
+        }
+
+        let len = u32::from_le_bytes(header[8..].try_into().unwrap());
+        let file_len = r.seek(SeekFrom::End(0))?;
+        if 12 + u64::from(len) > file_len {
+            return Err(Error::Corrupt);
+        }
+        r.seek(SeekFrom::Start(12))?;
+        let mut metadata = vec![0; len as usize];
+        r.read_exact(&mut metadata)?;
+        let metadata = Metadata::decode(&metadata)?;
+        let start = 12 + u64::from(len);
+
+        let mut archive = Self {
+            r,
+            metadata,
+            chunks: vec![],
+            end: start,
+            cache: None,
+        };
+        if !archive.read_index(start)? {
+            archive.refresh()?;
+        }
+        Ok(archive)
+    }
+
+    /// Reads the index, returning `false` if there is none.
+    fn read_index(&mut self, start: u64) -> Result<bool, Error> {
+        let file_len = self.r.seek(SeekFrom::End(0))?;
+        if file_len < start + (INDEX_MAGIC.len() + 4 + TRAILER_LEN) as u64 {
+            return Ok(false);
+        }
+
+        self.r.seek(SeekFrom::End(-(TRAILER_LEN as i64)))?;
+        let mut trailer = [0; TRAILER_LEN];
+        self.r.read_exact(&mut trailer)?;
+        let offset = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+        if trailer[8..] != TRAILER_MAGIC {
+            return Ok(false);
+        }
+        if offset < start
+            || offset
+                .checked_add(TRAILER_LEN as u64)
+                .is_none_or(|end| end > file_len)
+        {
+            return Err(Error::Corrupt);
+        }
+
+        let mut index = vec![0; (file_len - TRAILER_LEN as u64 - offset) as usize];
+        self.r.seek(SeekFrom::Start(offset))?;
+        self.r.read_exact(&mut index)?;
+        let mut r = Reader(&index);
+        if r.array().ok() != Some(INDEX_MAGIC) {
+            return Ok(false);
+        }
+
+        let n = r.u32()? as usize;
+        if r.0.len() != n * INDEX_ENTRY_LEN {
+            return Ok(false);
+        }
+        let mut chunks = Vec::with_capacity(n);
+        let mut first = 0;
+        for _ in 0..n {
+            let chunk = ChunkInfo::read_index_entry(&mut r, first)?;
+            first += chunk.len;
+            chunks.push(chunk);
+        }
+
+        self.chunks = chunks;
+        self.end = offset;
+        Ok(true)
+    }
+
+    /// Picks up chunks that were appended since the archive was opened, such
+    /// as during a live capture.
+    pub fn refresh(&mut self) -> Result<(), Error> {
+        let file_len = self.r.seek(SeekFrom::End(0))?;
+        self.r.seek(SeekFrom::Start(self.end))?;
+
+        while self.end + CHUNK_HEADER_LEN as u64 <= file_len {
+            let mut b = [0; CHUNK_HEADER_LEN];
+            self.r.read_exact(&mut b)?;
+            let Some(header) = ChunkHeader::from_bytes(&b) else {
+                break;
+            };
+            let chunk_end = self.end + (CHUNK_HEADER_LEN as u64) + u64::from(header.stored_len);
+            // the chunk is still being written
+            if chunk_end > file_len {
+                break;
+            }
+
+            self.chunks.push(header.info(self.end, self.len()));
+            self.end = chunk_end;
+            self.r.seek(SeekFrom::Start(chunk_end))?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the metadata.
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Returns the chunks.
+    pub fn chunks(&self) -> &[ChunkInfo] {
+        &self.chunks
+    }
+
+    /// Returns the number of records.
+    pub fn len(&self) -> usize {
+        self.chunks
+            .last()
+            .map_or(0, |chunk| chunk.first + chunk.len)
+    }
+
+    /// Returns `true` if there are no records.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Decodes a chunk, reusing the previous result if possible.
+    fn chunk(&mut self, i: usize) -> Result<&[Record], Error> {
+        if !matches!(self.cache, Some((cached, _)) if cached == i) {
+            let info = self.chunks[i];
+            let file_len = self.r.seek(SeekFrom::End(0))?;
+            self.r.seek(SeekFrom::Start(info.offset))?;
+            let mut b = [0; CHUNK_HEADER_LEN];
+            self.r.read_exact(&mut b)?;
+            let header = ChunkHeader::from_bytes(&b).ok_or(Error::Corrupt)?;
+            // the index entry may be stale or corrupt
+            if header.len as usize != info.len
+                || info.offset + (CHUNK_HEADER_LEN as u64) + u64::from(header.stored_len) > file_len
+            {
+                return Err(Error::Corrupt);
+            }
+            let mut payload = vec![0; header.stored_len as usize];
+            self.r.read_exact(&mut payload)?;
+
+            let raw = match header.compression {
+                STORED => payload,
+                DEFLATE => miniz_oxide::inflate::decompress_to_vec_with_limit(
+                    &payload,
+                    header.raw_len as usize,
+                )
+                .map_err(|_| Error::Corrupt)?,
+                _ => return Err(Error::Corrupt),
+            };
+            if raw.len() != header.raw_len as usize || crc32fast::hash(&raw) != header.crc {
+                return Err(Error::Corrupt);
+            }
+
+            self.cache = Some((i, decode(&raw, header.len as usize)?));
+        }
+
+        Ok(&self.cache.as_ref().unwrap().1)
+    }
+
+    /// Returns the record at index `i`, or `None` if it is out of bounds.
+    pub fn get(&mut self, i: usize) -> Result<Option<Record>, Error> {
+        let chunk = self
+            .chunks
+            .partition_point(|chunk| chunk.first + chunk.len <= i);
+        if chunk == self.chunks.len() {
+            return Ok(None);
+        }
+        let first = self.chunks[chunk].first;
+        Ok(self.chunk(chunk)?.get(i - first).cloned())
+    }
+
+    /// Returns the index of the first record with the sequence number `seq`.
+    pub fn position_of_seq(&mut self, seq: u16) -> Result<Option<usize>, Error> {
+        for i in 0..self.chunks.len() {
+            if !self.chunks[i].contains_seq(seq) {
+                continue;
+            }
+            let first = self.chunks[i].first;
+            if let Some(j) = self.chunk(i)?.iter().position(|r| r.seq_cnt == seq) {
+                return Ok(Some(first + j));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns an iterator over the records with timestamps in `range`,
+    /// decoding only the chunks that overlap it.
+    pub fn time_range(&mut self, range: impl RangeBounds<Duration>) -> TimeRange<'_, R> {
+        TimeRange {
+            archive: self,
+            range: (range.start_bound().cloned(), range.end_bound().cloned()),
+            chunk: 0,
+            i: 0,
+        }
+    }
+}
+
+/// Iterator returned by [`Archive::time_range`].
+pub struct TimeRange<'a, R> {
+    archive: &'a mut Archive<R>,
+    range: (Bound<Duration>, Bound<Duration>),
+    chunk: usize,
+    i: usize,
+}
+
+impl<R: Read + Seek> Iterator for TimeRange<'_, R> {
+    type Item = Result<Record, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(info) = self.archive.chunks.get(self.chunk) {
+            if self.i >= info.len || !info.overlaps(&self.range) {
+                self.chunk += 1;
+                self.i = 0;
+                continue;
+            }
+
+            let record = match self.archive.chunk(self.chunk) {
+                Ok(records) => &records[self.i],
+                Err(e) => {
+                    self.chunk = usize::MAX;
+                    return Some(Err(e));
+                }
+            };
+            self.i += 1;
+            if self.range.contains(&record.timestamp) {
+                return Some(Ok(record.clone()));
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::ieee80211::{Band, Bandwidth};
+
+    fn record(i: u64) -> Record {
+        let chan_spec = ChanSpec::new(36, Band::Band5G, Bandwidth::Bw20).unwrap();
+        let mut csi = WifiCsi::new(chan_spec, -40 - (i % 20) as i8);
+        for core in 0..2 {
+            let frame = (0..128)
+                .map(|k| Complex::new((i + k) as f64, core as f64 - k as f64))
+                .collect();
+            csi.insert(core, (i % 2) as usize, frame);
+        }
+
+        Record {
+            timestamp: Duration::from_millis(1_700_000_000_000 + 10 * i),
+            source_mac: MacAddr6::new(0x50, 0xed, 0x3c, 0x2e, 0x04, i as u8),
+            seq_cnt: (i % 4096) as u16,
+            csi,
+        }
+    }
+
+    fn assert_record_eq(a: &Record, b: &Record) {
+        assert_eq!(a.timestamp, b.timestamp);
+        assert_eq!(a.source_mac, b.source_mac);
+        assert_eq!(a.seq_cnt, b.seq_cnt);
+        assert_eq!(a.csi.chan_spec, b.csi.chan_spec);
+        assert_eq!(a.csi.rssi, b.csi.rssi);
+        for core in 0..4 {
+            for spatial in 0..4 {
+                assert_eq!(a.csi.get(core, spatial), b.csi.get(core, spatial));
+            }
+        }
+    }
+
+    #[test]
+    fn random_access() {
+        let metadata = Metadata {
+            device: "RT-AC86U".into(),
+            antennas: vec![[0., 0., 0.], [0.088, 0., 0.]],
+            calibration: vec![Complex::new(1., 0.), Complex::new(0., 1.)],
+            extra: vec![("location".into(), "lab".into())],
+        };
+        let mut writer = ArchiveWriter::new(Cursor::new(vec![]), &metadata)
+            .unwrap()
+            .chunk_len(100);
+        for i in 0..1000 {
+            writer.push(&record(i)).unwrap();
+        }
+        let file = writer.finish().unwrap();
+
+        let raw_len = 1000 * 2 * 128 * 8;
+        assert!(file.get_ref().len() < raw_len / 2);
+
+        let mut archive = Archive::open(file).unwrap();
+        assert_eq!(archive.metadata(), &metadata);
+        assert_eq!(archive.len(), 1000);
+        assert_eq!(archive.chunks().len(), 10);
+
+        for i in [999, 0, 500, 501, 250] {
+            assert_record_eq(&archive.get(i).unwrap().unwrap(), &record(i as u64));
+        }
+        assert!(archive.get(1000).unwrap().is_none());
+        assert_eq!(archive.position_of_seq(742).unwrap(), Some(742));
+        assert_eq!(archive.position_of_seq(4000).unwrap(), None);
+
+        let start = record(195).timestamp;
+        let end = record(305).timestamp;
+        let range: Vec<_> = archive
+            .time_range(start..end)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(range.len(), 110);
+        assert_record_eq(&range[0], &record(195));
+        assert_record_eq(&range[109], &record(304));
+    }
+
+    #[test]
+    fn append() {
+        let mut writer = ArchiveWriter::new(Cursor::new(vec![]), &Metadata::default())
+            .unwrap()
+            .chunk_len(64);
+        for i in 0..100 {
+            writer.push(&record(i)).unwrap();
+        }
+        let file = writer.finish().unwrap();
+
+        let mut writer = ArchiveWriter::append(file).unwrap();
+        assert_eq!(writer.len(), 100);
+        for i in 100..150 {
+            writer.push(&record(i)).unwrap();
+        }
+        // not finished, so the stale index is ignored
+        writer.flush().unwrap();
+        let file = writer.w;
+
+        let mut archive = Archive::open(file.clone()).unwrap();
+        assert_eq!(archive.len(), 150);
+        assert_record_eq(&archive.get(120).unwrap().unwrap(), &record(120));
+
+        // a partially written chunk is ignored until it is complete
+        let mut writer = ArchiveWriter::append(file).unwrap();
+        for i in 150..200 {
+            writer.push(&record(i)).unwrap();
+        }
+        writer.flush().unwrap();
+        let complete = writer.w.into_inner();
+        let mut archive =
+            Archive::open(Cursor::new(complete[..complete.len() - 10].to_vec())).unwrap();
+        assert_eq!(archive.len(), 150);
+
+        archive.r.get_mut().extend(&complete[complete.len() - 10..]);
+        archive.refresh().unwrap();
+        assert_eq!(archive.len(), 200);
+        assert_record_eq(&archive.get(199).unwrap().unwrap(), &record(199));
+
+        let mut corrupt = complete.clone();
+        let last = archive.chunks()[archive.chunks().len() - 1].offset as usize;
+        corrupt[last + CHUNK_HEADER_LEN] ^= 0xff;
+        let mut archive = Archive::open(Cursor::new(corrupt)).unwrap();
+        assert!(matches!(archive.get(199), Err(Error::Corrupt)));
+    }
+
+    #[test]
+    fn corrupt_lengths() {
+        let mut writer = ArchiveWriter::new(Cursor::new(vec![]), &Metadata::default()).unwrap();
+        for i in 0..10 {
+            writer.push(&record(i)).unwrap();
+        }
+        let file = writer.finish().unwrap().into_inner();
+
+        let mut metadata_len = file.clone();
+        metadata_len[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            Archive::open(Cursor::new(metadata_len)),
+            Err(Error::Corrupt)
+        ));
+
+        for offset in [file.len() as u64 - 8, u64::MAX, 0] {
+            let mut index_offset = file.clone();
+            let trailer = file.len() - TRAILER_LEN;
+            index_offset[trailer..trailer + 8].copy_from_slice(&offset.to_le_bytes());
+            assert!(
+                matches!(
+                    Archive::open(Cursor::new(index_offset)),
+                    Err(Error::Corrupt)
+                ),
+                "{offset}"
+            );
+        }
+
+        let archive = Archive::open(Cursor::new(file.clone())).unwrap();
+        let chunk = archive.chunks()[0].offset as usize;
+        let index = u64::from_le_bytes(file[file.len() - TRAILER_LEN..][..8].try_into().unwrap());
+        let entry = index as usize + INDEX_MAGIC.len() + 4;
+
+        let mut record_count = file.clone();
+        record_count[entry + 8..entry + 12].copy_from_slice(&1000u32.to_le_bytes());
+        let mut archive = Archive::open(Cursor::new(record_count)).unwrap();
+        assert!(matches!(archive.get(999), Err(Error::Corrupt)));
+        assert!(matches!(
+            archive.time_range(..).collect::<Result<Vec<_>, _>>(),
+            Err(Error::Corrupt)
+        ));
+
+        let mut stored_len = file.clone();
+        stored_len[chunk + CHUNK_MAGIC.len()..][..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut archive = Archive::open(Cursor::new(stored_len)).unwrap();
+        assert!(matches!(archive.get(0), Err(Error::Corrupt)));
+    }
+}
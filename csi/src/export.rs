@@ -0,0 +1,5 @@
+//! Exporters for use with other tools.
+
+pub mod mat;
+pub mod npy;
+mod zip;
@@ -0,0 +1,519 @@
+//! Reader and writer for MATLAB
+//! [Level 5 MAT-files](https://www.mathworks.com/help/pdf_doc/matlab/matfile_format.pdf),
+//! as read and written by `load` and `save`.
+//!
+//! Only numeric arrays are supported; other variables (structs, cells,
+//! strings and sparse arrays) are skipped when reading. Arrays are written
+//! uncompressed as `double`, but compressed variables of any numeric class
+//! can be read, such as the test vectors of the Hands-on Wireless Sensing
+//! scripts.
+//!
+//! Arrays keep their shape: an ndarray of shape `[T, S, A, L]` is a MATLAB
+//! array of size `[T S A L]`, even though MATLAB stores it in column-major
+//! order.
+
+use std::io::{self, Read, Write};
+
+use ndarray::{Array4, ArrayBase, ArrayD, Data, Dimension, IxDyn, ShapeBuilder};
+use num_complex::Complex;
+
+use crate::{frame::Scalar, proc::WifiCsi};
+
+const HEADER_LEN: usize = 128;
+const HEADER_TEXT_LEN: usize = 116;
+
+// data types
+const MI_INT8: u32 = 1;
+const MI_UINT8: u32 = 2;
+const MI_INT16: u32 = 3;
+const MI_UINT16: u32 = 4;
+const MI_INT32: u32 = 5;
+const MI_UINT32: u32 = 6;
+const MI_SINGLE: u32 = 7;
+const MI_DOUBLE: u32 = 9;
+const MI_INT64: u32 = 12;
+const MI_UINT64: u32 = 13;
+const MI_MATRIX: u32 = 14;
+const MI_COMPRESSED: u32 = 15;
+
+// array classes
+const MX_DOUBLE_CLASS: u32 = 6;
+const MX_UINT64_CLASS: u32 = 15;
+
+/// Array flag of complex arrays.
+const COMPLEX: u32 = 0x0800;
+
+/// Compressed data elements that would decompress to more bytes than this
+/// are rejected.
+const MAX_DECOMPRESSED_LEN: usize = 1 << 30;
+
+/// Error returned when reading a MAT-file.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Reading from the underlying reader failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The file does not have a Level 5 MAT-file header.
+    #[error("not a Level 5 MAT-file")]
+    NotAMatFile,
+    /// The file was written on a big-endian machine, which is not supported.
+    #[error("big-endian MAT-files are not supported")]
+    BigEndian,
+    /// A data element is truncated or malformed.
+    #[error("invalid data element")]
+    Invalid,
+    /// A compressed data element could not be decompressed.
+    #[error("invalid compressed data element")]
+    Decompress,
+}
+
+/// A numeric array, converted to `double`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix {
+    /// Real part.
+    pub real: ArrayD<f64>,
+    /// Imaginary part, if the array is complex.
+    pub imag: Option<ArrayD<f64>>,
+}
+
+impl Matrix {
+    /// Returns the array as complex numbers.
+    pub fn to_complex(&self) -> ArrayD<Complex<f64>> {
+        match &self.imag {
+            Some(imag) => ndarray::Zip::from(&self.real)
+                .and(imag)
+                .map_collect(|&re, &im| Complex::new(re, im)),
+            None => self.real.mapv(|re| Complex::new(re, 0.)),
+        }
+    }
+}
+
+/// A data element.
+struct Element<'a> {
+    ty: u32,
+    data: &'a [u8],
+}
+
+/// Splits the next data element off `b`. Unless `packed`, the element is
+/// padded to a multiple of 8 bytes.
+fn element<'a>(b: &mut &'a [u8], packed: bool) -> Result<Element<'a>, Error> {
+    let word = |i: usize| -> Result<u32, Error> {
+        let w = b.get(i..i + 4).ok_or(Error::Invalid)?;
+        Ok(u32::from_le_bytes(w.try_into().unwrap()))
+    };
+
+    let first = word(0)?;
+    let (ty, start, len, total) = if first >> 16 != 0 {
+        // small data element format
+        let len = (first >> 16) as usize;
+        if len > 4 {
+            return Err(Error::Invalid);
+        }
+        (first & 0xffff, 4, len, 8)
+    } else {
+        let len = word(4)? as usize;
+        let total = 8 + if packed { len } else { len.next_multiple_of(8) };
+        (first, 8, len, total)
+    };
+
+    let data = b.get(start..start + len).ok_or(Error::Invalid)?;
+    *b = b.get(total..).unwrap_or_default();
+    Ok(Element { ty, data })
+}
+
+/// Converts numeric data to `f64`.
+fn numeric(e: &Element) -> Result<Vec<f64>, Error> {
+    macro_rules! convert {
+        ($t:ty) => {
+            e.data
+                .chunks_exact(std::mem::size_of::<$t>())
+                .map(|c| <$t>::from_le_bytes(c.try_into().unwrap()) as f64)
+                .collect()
+        };
+    }
+
+    Ok(match e.ty {
+        MI_INT8 => convert!(i8),
+        MI_UINT8 => convert!(u8),
+        MI_INT16 => convert!(i16),
+        MI_UINT16 => convert!(u16),
+        MI_INT32 => convert!(i32),
+        MI_UINT32 => convert!(u32),
+        MI_SINGLE => convert!(f32),
+        MI_DOUBLE => convert!(f64),
+        MI_INT64 => convert!(i64),
+        MI_UINT64 => convert!(u64),
+        _ => return Err(Error::Invalid),
+    })
+}
+
+/// Parses the contents of an `miMATRIX` element, returning `None` for
+/// non-numeric arrays.
+fn matrix(mut b: &[u8]) -> Result<Option<(String, Matrix)>, Error> {
+    let flags = element(&mut b, false)?;
+    if flags.ty != MI_UINT32 || flags.data.len() != 8 {
+        return Err(Error::Invalid);
+    }
+    let flags = u32::from_le_bytes(flags.data[..4].try_into().unwrap());
+    if !(MX_DOUBLE_CLASS..=MX_UINT64_CLASS).contains(&(flags & 0xff)) {
+        return Ok(None);
+    }
+
+    let dims = element(&mut b, false)?;
+    if dims.ty != MI_INT32 {
+        return Err(Error::Invalid);
+    }
+    let dims = dims
+        .data
+        .chunks_exact(4)
+        .map(|c| usize::try_from(i32::from_le_bytes(c.try_into().unwrap())))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| Error::Invalid)?;
+
+    let name = element(&mut b, false)?;
+    let name = String::from_utf8(name.data.to_vec()).map_err(|_| Error::Invalid)?;
+
+    let array =
+        |data: Vec<f64>| ArrayD::from_shape_vec(IxDyn(&dims).f(), data).map_err(|_| Error::Invalid);
+    let real = array(numeric(&element(&mut b, false)?)?)?;
+    let imag = if flags & COMPLEX != 0 {
+        Some(array(numeric(&element(&mut b, false)?)?)?)
+    } else {
+        None
+    };
+
+    Ok(Some((name, Matrix { real, imag })))
+}
+
+/// Parses data elements, appending numeric arrays to `vars`.
+fn elements(mut b: &[u8], vars: &mut Vec<(String, Matrix)>) -> Result<(), Error> {
+    while !b.is_empty() {
+        let e = element(&mut b, true)?;
+        match e.ty {
+            MI_COMPRESSED => {
+                let data = miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(
+                    e.data,
+                    MAX_DECOMPRESSED_LEN,
+                )
+                .map_err(|_| Error::Decompress)?;
+                elements(&data, vars)?;
+            }
+            MI_MATRIX => vars.extend(matrix(e.data)?),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Reads the numeric variables of a MAT-file, in the order they are stored.
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let file = std::fs::File::open("csi_src_test.mat")?;
+/// let vars = csi::export::mat::read(file)?;
+/// let (_, csi) = vars.iter().find(|(name, _)| name == "csi").unwrap();
+/// println!("{:?}", csi.to_complex().shape());
+/// # Ok(())
+/// # }
+/// ```
+pub fn read(mut r: impl Read) -> Result<Vec<(String, Matrix)>, Error> {
+    let mut b = vec![];
+    r.read_to_end(&mut b)?;
+
+    let header = b.get(..HEADER_LEN).ok_or(Error::NotAMatFile)?;
+    match &header[126..] {
+        b"IM" => {}
+        b"MI" => return Err(Error::BigEndian),
+        _ => return Err(Error::NotAMatFile),
+    }
+
+    let mut vars = vec![];
+    elements(&b[HEADER_LEN..], &mut vars)?;
+    Ok(vars)
+}
+
+/// Appends a data element, padded to a multiple of 8 bytes.
+fn write_element(buf: &mut Vec<u8>, ty: u32, data: &[u8]) {
+    buf.extend(ty.to_le_bytes());
+    buf.extend((data.len() as u32).to_le_bytes());
+    buf.extend(data);
+    buf.resize(buf.len().next_multiple_of(8), 0);
+}
+
+/// Writes numeric arrays to a MAT-file.
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use csi::export::mat::{read, MatWriter};
+/// use ndarray::{arr1, arr2};
+/// use num_complex::Complex;
+///
+/// let mut mat = MatWriter::new(vec![])?;
+/// mat.write_real("x", &arr2(&[[1., 2., 3.], [4., 5., 6.]]))?;
+/// mat.write_complex("z", &arr1(&[Complex::new(1., -1.)]))?;
+/// let mat = mat.into_inner();
+///
+/// let vars = read(&mat[..])?;
+/// assert_eq!(vars[0].0, "x");
+/// assert_eq!(vars[0].1.real.shape(), [2, 3]);
+/// assert_eq!(vars[1].1.to_complex()[[0, 0]], Complex::new(1., -1.));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MatWriter<W> {
+    w: W,
+}
+
+impl<W: Write> MatWriter<W> {
+    /// Writes the file header.
+    pub fn new(mut w: W) -> io::Result<Self> {
+        let mut header = format!(
+            "MATLAB 5.0 MAT-file, Created by: csi {}",
+            env!("CARGO_PKG_VERSION")
+        )
+        .into_bytes();
+        header.resize(HEADER_TEXT_LEN, b' ');
+        header.extend([0; 8]); // no subsystem data
+        header.extend(0x0100u16.to_le_bytes());
+        header.extend(b"IM");
+        w.write_all(&header)?;
+
+        Ok(Self { w })
+    }
+
+    fn write(
+        &mut self,
+        name: &str,
+        shape: &[usize],
+        real: impl Iterator<Item = f64>,
+        imag: Option<impl Iterator<Item = f64>>,
+    ) -> io::Result<()> {
+        // MATLAB arrays have at least two dimensions
+        let mut dims = shape.to_vec();
+        dims.resize(dims.len().max(2), 1);
+        let dims = dims
+            .iter()
+            .map(|&d| i32::try_from(d).map(i32::to_le_bytes))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "array too large"))?
+            .concat();
+
+        let mut flags = MX_DOUBLE_CLASS;
+        if imag.is_some() {
+            flags |= COMPLEX;
+        }
+
+        let mut buf = vec![];
+        write_element(&mut buf, MI_UINT32, &[flags.to_le_bytes(), [0; 4]].concat());
+        write_element(&mut buf, MI_INT32, &dims);
+        write_element(&mut buf, MI_INT8, name.as_bytes());
+        let data = real.flat_map(f64::to_le_bytes).collect::<Vec<_>>();
+        write_element(&mut buf, MI_DOUBLE, &data);
+        if let Some(imag) = imag {
+            let data = imag.flat_map(f64::to_le_bytes).collect::<Vec<_>>();
+            write_element(&mut buf, MI_DOUBLE, &data);
+        }
+
+        let len = u32::try_from(buf.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "array too large"))?;
+        self.w.write_all(&MI_MATRIX.to_le_bytes())?;
+        self.w.write_all(&len.to_le_bytes())?;
+        self.w.write_all(&buf)
+    }
+
+    /// Writes a real `double` array.
+    pub fn write_real<S: Data<Elem = f64>, D: Dimension>(
+        &mut self,
+        name: &str,
+        array: &ArrayBase<S, D>,
+    ) -> io::Result<()> {
+        // the transpose iterates in column-major order
+        let t = array.t();
+        self.write(
+            name,
+            array.shape(),
+            t.iter().copied(),
+            None::<std::iter::Empty<_>>,
+        )
+    }
+
+    /// Writes a complex `double` array.
+    pub fn write_complex<S: Data<Elem = Complex<f64>>, D: Dimension>(
+        &mut self,
+        name: &str,
+        array: &ArrayBase<S, D>,
+    ) -> io::Result<()> {
+        let t = array.t();
+        self.write(
+            name,
+            array.shape(),
+            t.iter().map(|z| z.re),
+            Some(t.iter().map(|z| z.im)),
+        )
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
+
+/// Stacks CSI into a tensor of size `[T S A L]`, as expected by the
+/// Hands-on Wireless Sensing scripts: packets, subcarriers, antennas
+/// (cores) and HT-LTFs (spatial streams).
+///
+/// The antennas and HT-LTFs are the cores and spatial streams of the first
+/// packet, which must form a full grid: every combination of those cores
+/// and spatial streams must be present in every packet, with the same
+/// number of subcarriers. Otherwise, `None` is returned, e.g. if the first
+/// packet only has core 0 with spatial stream 0 and core 1 with spatial
+/// stream 1. Other frames of later packets are ignored. The subcarriers are all
+/// [`Bandwidth::nsub_pow2`](crate::ieee80211::Bandwidth::nsub_pow2) of
+/// them, so guard and null subcarriers may have to be removed.
+pub fn tensor<T: Scalar>(csi: &[WifiCsi<T>]) -> Option<Array4<Complex<f64>>> {
+    let first = csi.first()?;
+    let streams = (0..4)
+        .flat_map(|core| (0..4).map(move |spatial| (core, spatial)))
+        .filter(|&(core, spatial)| first.get(core, spatial).is_some());
+    let cores = streams
+        .clone()
+        .map(|(core, _)| core)
+        .fold(vec![], |mut v, c| {
+            if !v.contains(&c) {
+                v.push(c);
+            }
+            v
+        });
+    let spatials = streams
+        .map(|(_, spatial)| spatial)
+        .fold(vec![], |mut v, s| {
+            if !v.contains(&s) {
+                v.push(s);
+            }
+            v
+        });
+    let nsub = first.get(*cores.first()?, *spatials.first()?)?.len();
+
+    let mut tensor = Array4::zeros((csi.len(), nsub, cores.len(), spatials.len()));
+    for (t, csi) in csi.iter().enumerate() {
+        for (a, &core) in cores.iter().enumerate() {
+            for (l, &spatial) in spatials.iter().enumerate() {
+                let frame = csi.get(core, spatial).filter(|f| f.len() == nsub)?;
+                for (s, z) in frame.iter().enumerate() {
+                    tensor[[t, s, a, l]] = Complex::new(z.re.to_f64(), z.im.to_f64());
+                }
+            }
+        }
+    }
+
+    Some(tensor)
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array, Array1};
+
+    use super::*;
+    use crate::{
+        ieee80211::{Band, Bandwidth},
+        params::ChanSpec,
+    };
+
+    #[test]
+    fn round_trip() {
+        let real = Array::from_shape_fn((2, 3, 4), |(i, j, k)| (i * 100 + j * 10 + k) as f64);
+        let complex = Array::from_shape_fn((5, 57, 3, 1), |(t, s, a, _)| {
+            Complex::new(t as f64 + s as f64 / 100., -(a as f64))
+        });
+
+        let mut mat = MatWriter::new(vec![]).unwrap();
+        mat.write_real("real", &real).unwrap();
+        mat.write_real("v", &Array1::from(vec![1., 2.])).unwrap();
+        mat.write_complex("csi", &complex).unwrap();
+        let mat = mat.into_inner();
+        assert!(mat.starts_with(b"MATLAB 5.0 MAT-file"));
+        assert_eq!(mat[126..128], *b"IM");
+
+        let vars = read(&mat[..]).unwrap();
+        assert_eq!(vars.len(), 3);
+        assert_eq!(vars[0].0, "real");
+        assert_eq!(vars[0].1.real, real.into_dyn());
+        assert_eq!(vars[0].1.imag, None);
+        assert_eq!(vars[1].1.real.shape(), [2, 1]);
+        assert_eq!(vars[2].0, "csi");
+        assert_eq!(vars[2].1.to_complex(), complex.into_dyn());
+
+        // compressed elements are not padded
+        let compressed = |data: &[u8]| {
+            let mut b = mat[..HEADER_LEN].to_vec();
+            b.extend(MI_COMPRESSED.to_le_bytes());
+            b.extend((data.len() as u32).to_le_bytes());
+            b.extend(data);
+            b
+        };
+        let data = miniz_oxide::deflate::compress_to_vec_zlib(&mat[HEADER_LEN..], 6);
+        let decompressed = read(&compressed(&data)[..]).unwrap();
+        assert_eq!(decompressed.len(), 3);
+        assert_eq!(decompressed[2].1.to_complex(), vars[2].1.to_complex());
+        assert!(matches!(
+            read(&compressed(&data[..data.len() / 2])[..]),
+            Err(Error::Decompress)
+        ));
+
+        assert!(matches!(read(&mat[..100]), Err(Error::NotAMatFile)));
+        assert!(matches!(read(&mat[..200]), Err(Error::Invalid)));
+    }
+
+    #[test]
+    fn wifi_csi_tensor() {
+        let chan_spec = ChanSpec::new(36, Band::Band5G, Bandwidth::Bw20).unwrap();
+        let csi = (0..3)
+            .map(|t| {
+                let mut csi = WifiCsi::<f64>::new(chan_spec, -40);
+                for core in [0, 1, 3] {
+                    let z = Complex::new(t as f64, core as f64);
+                    csi.insert(core, 0, Array1::from_elem(64, z));
+                }
+                csi
+            })
+            .collect::<Vec<_>>();
+
+        let stacked = tensor(&csi).unwrap();
+        assert_eq!(stacked.dim(), (3, 64, 3, 1));
+        assert_eq!(stacked[[2, 5, 2, 0]], Complex::new(2., 3.));
+        assert_eq!(tensor(&csi[..0]), None);
+
+        let mut missing = csi.clone();
+        missing[1] = WifiCsi::new(chan_spec, -40);
+        missing[1].insert(0, 0, Array1::zeros(64));
+        missing[1].insert(1, 0, Array1::zeros(64));
+        assert_eq!(tensor(&missing), None);
+
+        let mut diagonal = WifiCsi::<f64>::new(chan_spec, -40);
+        diagonal.insert(0, 0, Array1::zeros(64));
+        diagonal.insert(1, 1, Array1::zeros(64));
+        assert_eq!(tensor(&[diagonal]), None);
+
+        let mut extra = csi.clone();
+        extra[2].insert(0, 1, Array1::zeros(64));
+        assert_eq!(tensor(&extra), Some(stacked));
+    }
+
+    #[test]
+    fn hands_on_wireless_sensing() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../Hands-on Wireless Sensing/ch.3. CSI Features/data/csi_src_test.mat"
+        );
+        let Ok(file) = std::fs::File::open(path) else {
+            return;
+        };
+
+        let vars = read(file).unwrap();
+        let (_, csi) = vars.iter().find(|(name, _)| name == "csi").unwrap();
+        let shape = csi.real.shape();
+        assert_eq!(shape[1..3], [57, 3]);
+        assert!(csi.imag.is_some());
+    }
+}
@@ -0,0 +1,467 @@
+//! Writer for NumPy [`.npy`](https://numpy.org/doc/stable/reference/generated/numpy.lib.format.html)
+//! and `.npz` files.
+//!
+//! [`NpzWriter`] streams a sequence of [`WifiCsi`] into an uncompressed
+//! `.npz` archive that can be opened with `numpy.load`, containing
+//!
+//! | Array       | Type        | Shape                                 |
+//! |-------------|-------------|---------------------------------------|
+//! | `csi`       | `complex64` | (time, core, spatial, subcarrier)     |
+//! | `timestamp` | `float64`   | (time,), seconds                      |
+//! | `rssi`      | `int8`      | (time,), dBm                          |
+//! | `seq`       | `uint16`    | (time,)                               |
+//! | `chanspec`  | `uint16`    | (time,), see [`ChanSpec`]             |
+//!
+//! There are always 4 cores and 4 spatial streams; streams that were not
+//! captured are NaN.
+//!
+//! [`ChanSpec`]: crate::params::ChanSpec
+
+use std::{
+    io::{self, Seek, SeekFrom, Write},
+    time::Duration,
+};
+
+use ndarray::{ArrayBase, Data, Dimension};
+use num_complex::Complex;
+
+use super::zip::{self, Entry};
+use crate::{frame::Scalar, proc::WifiCsi};
+
+const MAGIC: &[u8] = b"\x93NUMPY\x01\x00";
+
+/// Length of the header of the streamed `csi` array, which leaves room
+/// for any shape.
+const STREAMED_HEADER_LEN: usize = 128;
+
+/// A type that can be stored in a `.npy` file.
+pub trait Element: Copy {
+    /// NumPy type descriptor, e.g. `<f8`.
+    const DESCR: &'static str;
+
+    /// Appends the little-endian representation to `buf`.
+    fn extend_le(self, buf: &mut Vec<u8>);
+}
+
+macro_rules! impl_element {
+    ($($t:ty => $descr:literal),*) => {
+        $(
+            impl Element for $t {
+                const DESCR: &'static str = $descr;
+
+                fn extend_le(self, buf: &mut Vec<u8>) {
+                    buf.extend(self.to_le_bytes());
+                }
+            }
+        )*
+    };
+}
+
+impl_element!(
+    i8 => "|i1",
+    u8 => "|u1",
+    i16 => "<i2",
+    u16 => "<u2",
+    i32 => "<i4",
+    u32 => "<u4",
+    i64 => "<i8",
+    u64 => "<u8",
+    f32 => "<f4",
+    f64 => "<f8"
+);
+
+impl Element for Complex<f32> {
+    const DESCR: &'static str = "<c8";
+
+    fn extend_le(self, buf: &mut Vec<u8>) {
+        self.re.extend_le(buf);
+        self.im.extend_le(buf);
+    }
+}
+
+impl Element for Complex<f64> {
+    const DESCR: &'static str = "<c16";
+
+    fn extend_le(self, buf: &mut Vec<u8>) {
+        self.re.extend_le(buf);
+        self.im.extend_le(buf);
+    }
+}
+
+/// Returns a version 1.0 header, padded with spaces to a multiple of 64
+/// bytes or to `len` bytes.
+fn header(descr: &str, shape: &[usize], len: Option<usize>) -> Vec<u8> {
+    let shape = match shape {
+        [n] => format!("({n},)"),
+        _ => {
+            let dims = shape.iter().map(usize::to_string).collect::<Vec<_>>();
+            format!("({})", dims.join(", "))
+        }
+    };
+    let dict = format!("{{'descr': '{descr}', 'fortran_order': False, 'shape': {shape}, }}");
+
+    let unpadded = MAGIC.len() + 2 + dict.len() + 1;
+    let len = len.unwrap_or(unpadded.next_multiple_of(64));
+    assert!(unpadded <= len, "header too long");
+
+    let mut b = MAGIC.to_vec();
+    b.extend(((len - MAGIC.len() - 2) as u16).to_le_bytes());
+    b.extend(dict.as_bytes());
+    b.resize(len - 1, b' ');
+    b.push(b'\n');
+    b
+}
+
+/// Encodes an array as a `.npy` file.
+fn to_npy<T: Element, S: Data<Elem = T>, D: Dimension>(array: &ArrayBase<S, D>) -> Vec<u8> {
+    let mut b = header(T::DESCR, array.shape(), None);
+    for &x in array {
+        x.extend_le(&mut b);
+    }
+    b
+}
+
+/// Writes an array to a `.npy` file.
+///
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// let mut npy = vec![];
+/// csi::export::npy::write_npy(&mut npy, &ndarray::arr2(&[[1u16, 2], [3, 4]]))?;
+/// assert!(npy.starts_with(b"\x93NUMPY"));
+/// assert_eq!(npy.len(), 128 + 4 * 2);
+/// # Ok(())
+/// # }
+/// ```
+pub fn write_npy<T: Element, S: Data<Elem = T>, D: Dimension>(
+    mut w: impl Write,
+    array: &ArrayBase<S, D>,
+) -> io::Result<()> {
+    w.write_all(&to_npy(array))
+}
+
+/// Streams [`WifiCsi`] into a `.npz` archive.
+///
+/// The CSI is written as it is pushed, while the other (much smaller)
+/// arrays are kept in memory until [`NpzWriter::finish`] is called.
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::time::Duration;
+///
+/// use csi::{export::npy::NpzWriter, ieee80211::{Band, Bandwidth}, params::ChanSpec, proc::WifiCsi};
+///
+/// let chan_spec = ChanSpec::new(36, Band::Band5G, Bandwidth::Bw20).unwrap();
+/// let mut csi: WifiCsi = WifiCsi::new(chan_spec, -40);
+/// csi.insert(0, 0, ndarray::Array1::zeros(64));
+///
+/// let mut npz = NpzWriter::new(std::io::Cursor::new(vec![]))?;
+/// npz.push(Duration::from_millis(10), 1, &csi)?;
+/// npz.push(Duration::from_millis(20), 2, &csi)?;
+/// let npz = npz.finish()?.into_inner();
+/// # assert!(npz.starts_with(b"PK"));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct NpzWriter<W: Write + Seek> {
+    w: W,
+    /// Start of the archive.
+    start: u64,
+    entry: Entry,
+    crc: crc32fast::Hasher,
+    nsub: Option<usize>,
+    timestamps: Vec<f64>,
+    rssi: Vec<i8>,
+    seq: Vec<u16>,
+    chan_specs: Vec<u16>,
+    buf: Vec<u8>,
+}
+
+impl<W: Write + Seek> NpzWriter<W> {
+    /// Starts an archive at the current position of `w`.
+    pub fn new(mut w: W) -> io::Result<Self> {
+        let start = w.stream_position()?;
+        let entry = Entry {
+            name: "csi.npy".to_owned(),
+            offset: 0,
+            crc: 0,
+            size: 0,
+            zip64: true,
+        };
+        // placeholders, overwritten when finishing
+        entry.write_local_header(&mut w)?;
+        w.write_all(&[0; STREAMED_HEADER_LEN])?;
+
+        Ok(Self {
+            w,
+            start,
+            entry,
+            crc: crc32fast::Hasher::new(),
+            nsub: None,
+            timestamps: Vec::new(),
+            rssi: Vec::new(),
+            seq: Vec::new(),
+            chan_specs: Vec::new(),
+            buf: Vec::new(),
+        })
+    }
+
+    /// Returns the number of samples pushed so far.
+    pub fn len(&self) -> usize {
+        self.timestamps.len()
+    }
+
+    /// Returns `true` if no samples have been pushed.
+    pub fn is_empty(&self) -> bool {
+        self.timestamps.is_empty()
+    }
+
+    /// Appends a sample.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if the
+    /// number of subcarriers differs from that of the first sample with any
+    /// CSI. Samples without CSI are filled with NaN.
+    pub fn push<T: Scalar>(
+        &mut self,
+        timestamp: Duration,
+        seq: u16,
+        csi: &WifiCsi<T>,
+    ) -> io::Result<()> {
+        let first = (0..4)
+            .flat_map(|core| (0..4).map(move |spatial| (core, spatial)))
+            .find_map(|(core, spatial)| csi.get(core, spatial));
+
+        self.buf.clear();
+        let nsub = match (self.nsub, first) {
+            (Some(nsub), _) => nsub,
+            (None, Some(frame)) => {
+                // the samples so far had no CSI, so nothing was written for
+                // them until the number of subcarriers was known
+                for _ in 0..self.len() * 16 * frame.len() {
+                    Complex::new(f32::NAN, f32::NAN).extend_le(&mut self.buf);
+                }
+                *self.nsub.insert(frame.len())
+            }
+            (None, None) => 0,
+        };
+
+        for core in 0..4 {
+            for spatial in 0..4 {
+                match csi.get(core, spatial) {
+                    Some(frame) if frame.len() != nsub => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("expected {nsub} subcarriers, got {}", frame.len()),
+                        ));
+                    }
+                    Some(frame) => {
+                        for z in frame {
+                            Complex::new(z.re.to_f64() as f32, z.im.to_f64() as f32)
+                                .extend_le(&mut self.buf);
+                        }
+                    }
+                    None => {
+                        for _ in 0..nsub {
+                            Complex::new(f32::NAN, f32::NAN).extend_le(&mut self.buf);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.w.write_all(&self.buf)?;
+        self.crc.update(&self.buf);
+        self.entry.size += self.buf.len() as u64;
+
+        self.timestamps.push(timestamp.as_secs_f64());
+        self.rssi.push(csi.rssi);
+        self.seq.push(seq);
+        self.chan_specs.push(csi.chan_spec.into());
+        Ok(())
+    }
+
+    /// Writes the remaining arrays and the central directory, returning
+    /// the underlying writer positioned at the end of the archive.
+    pub fn finish(mut self) -> io::Result<W> {
+        let shape = [self.len(), 4, 4, self.nsub.unwrap_or(0)];
+        let header = header(Complex::<f32>::DESCR, &shape, Some(STREAMED_HEADER_LEN));
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(&header);
+        crc.combine(&self.crc);
+        self.entry.crc = crc.finalize();
+        self.entry.size += header.len() as u64;
+
+        let end = self.w.stream_position()?;
+        self.w.seek(SeekFrom::Start(self.start))?;
+        self.entry.write_local_header(&mut self.w)?;
+        self.w.write_all(&header)?;
+        self.w.seek(SeekFrom::Start(end))?;
+
+        let mut entries = vec![self.entry.clone()];
+        let mut offset = end - self.start;
+        let arrays = [
+            ("timestamp.npy", to_npy(&ndarray::aview1(&self.timestamps))),
+            ("rssi.npy", to_npy(&ndarray::aview1(&self.rssi))),
+            ("seq.npy", to_npy(&ndarray::aview1(&self.seq))),
+            ("chanspec.npy", to_npy(&ndarray::aview1(&self.chan_specs))),
+        ];
+        for (name, data) in arrays {
+            let entry = zip::write_entry(&mut self.w, offset, name, &data)?;
+            offset += entry.header_len() + entry.size;
+            entries.push(entry);
+        }
+
+        zip::write_central_directory(&mut self.w, offset, &entries)?;
+        Ok(self.w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use ndarray::Array1;
+
+    use super::*;
+    use crate::{
+        ieee80211::{Band, Bandwidth},
+        params::ChanSpec,
+    };
+
+    #[test]
+    fn npy_header() {
+        let h = header("<c8", &[3, 4, 4, 64], None);
+        assert_eq!(h.len(), 128);
+        assert_eq!(h.len() % 64, 0);
+        assert_eq!(u16::from_le_bytes([h[8], h[9]]) as usize, h.len() - 10);
+        assert!(h.ends_with(b" \n"));
+        let dict = std::str::from_utf8(&h[10..]).unwrap().trim_end();
+        assert_eq!(
+            dict,
+            "{'descr': '<c8', 'fortran_order': False, 'shape': (3, 4, 4, 64), }"
+        );
+
+        let h = header("<f8", &[5], Some(STREAMED_HEADER_LEN));
+        assert_eq!(h.len(), STREAMED_HEADER_LEN);
+        assert!(h.windows(4).any(|w| w == b"(5,)"));
+    }
+
+    /// Returns the (name, data) pairs of the archive, using its central
+    /// directory.
+    fn entries(zip: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let u16 = |i: usize| u16::from_le_bytes([zip[i], zip[i + 1]]) as usize;
+        let u32 = |i: usize| u32::from_le_bytes(zip[i..i + 4].try_into().unwrap()) as usize;
+
+        let eocd = zip.len() - 22;
+        assert_eq!(u32(eocd), 0x06054b50);
+        let mut p = u32(eocd + 16);
+        (0..u16(eocd + 10))
+            .map(|_| {
+                assert_eq!(u32(p), 0x02014b50);
+                let (crc, size) = (u32(p + 16), u32(p + 20));
+                let (name_len, extra_len) = (u16(p + 28), u16(p + 30));
+                let name = String::from_utf8(zip[p + 46..p + 46 + name_len].to_vec()).unwrap();
+                let offset = u32(p + 42);
+                p += 46 + name_len + extra_len;
+
+                assert_eq!(u32(offset), 0x04034b50);
+                let data = offset + 30 + u16(offset + 26) + u16(offset + 28);
+                let data = zip[data..data + size].to_vec();
+                assert_eq!(crc32fast::hash(&data) as usize, crc, "{name}");
+                (name, data)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn npz() {
+        let chan_spec = ChanSpec::new(36, Band::Band5G, Bandwidth::Bw20).unwrap();
+        let mut w = Cursor::new(b"junk".to_vec());
+        w.set_position(4);
+        let mut npz = NpzWriter::new(w).unwrap();
+
+        for t in 0..3 {
+            let mut csi = WifiCsi::<i32>::new(chan_spec, -40 - t as i8);
+            csi.insert(1, 2, Array1::from_elem(64, Complex::new(t, -t)));
+            npz.push(Duration::from_millis(t as u64 * 500), t as u16, &csi)
+                .unwrap();
+        }
+
+        let mut csi = WifiCsi::new(chan_spec, 0);
+        csi.insert(0, 0, Array1::<Complex<f64>>::zeros(128));
+        assert_eq!(
+            npz.push(Duration::ZERO, 0, &csi).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(npz.len(), 3);
+
+        let zip = npz.finish().unwrap().into_inner();
+        let entries = entries(&zip[4..]);
+        let names = entries.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "csi.npy",
+                "timestamp.npy",
+                "rssi.npy",
+                "seq.npy",
+                "chanspec.npy"
+            ]
+        );
+
+        let csi = &entries[0].1;
+        assert_eq!(csi.len(), 128 + 3 * 16 * 64 * 8);
+        assert!(csi.windows(16).any(|w| w == b"(3, 4, 4, 64), }"));
+        let value = |t: usize, core: usize, spatial: usize, k: usize| {
+            let i = 128 + (((t * 4 + core) * 4 + spatial) * 64 + k) * 8;
+            let f = |i: usize| f32::from_le_bytes(csi[i..i + 4].try_into().unwrap());
+            Complex::new(f(i), f(i + 4))
+        };
+        assert_eq!(value(2, 1, 2, 5), Complex::new(2., -2.));
+        assert!(value(2, 0, 0, 5).re.is_nan());
+
+        // skip the headers
+        let data = |i: usize| {
+            let npy = &entries[i].1;
+            &npy[10 + u16::from_le_bytes([npy[8], npy[9]]) as usize..]
+        };
+        assert_eq!(data(1).len(), 3 * 8);
+        assert_eq!(data(1)[8..16], 0.5f64.to_le_bytes());
+        assert_eq!(data(2), [-40i8 as u8, -41i8 as u8, -42i8 as u8]);
+        assert_eq!(data(3), [0, 0, 1, 0, 2, 0]);
+        assert_eq!(data(4)[..2], u16::from(chan_spec).to_le_bytes());
+    }
+
+    #[test]
+    fn npz_without_csi() {
+        let chan_spec = ChanSpec::new(36, Band::Band5G, Bandwidth::Bw20).unwrap();
+        let empty = WifiCsi::<f64>::new(chan_spec, 0);
+        let mut csi = empty.clone();
+        csi.insert(3, 3, Array1::from_elem(64, Complex::new(1., 2.)));
+
+        let mut npz = NpzWriter::new(Cursor::new(vec![])).unwrap();
+        for sample in [&empty, &empty, &csi, &empty] {
+            npz.push(Duration::ZERO, 0, sample).unwrap();
+        }
+        let zip = npz.finish().unwrap().into_inner();
+
+        let csi = &entries(&zip)[0].1;
+        assert_eq!(csi.len(), 128 + 4 * 16 * 64 * 8);
+        assert!(csi.windows(16).any(|w| w == b"(4, 4, 4, 64), }"));
+        let value = |i: usize| f32::from_le_bytes(csi[128 + i * 4..][..4].try_into().unwrap());
+        let sample = 16 * 64 * 2;
+        assert!(value(0).is_nan());
+        assert!(value(sample + 15 * 64 * 2 - 1).is_nan());
+        assert_eq!(value(2 * sample + 15 * 64 * 2), 1.);
+        assert_eq!(value(2 * sample + 15 * 64 * 2 + 1), 2.);
+        assert!(value(3 * sample).is_nan());
+
+        let mut npz = NpzWriter::new(Cursor::new(vec![])).unwrap();
+        npz.push(Duration::ZERO, 0, &empty).unwrap();
+        let zip = npz.finish().unwrap().into_inner();
+        assert!(entries(&zip)[0]
+            .1
+            .windows(15)
+            .any(|w| w == b"(1, 4, 4, 0), }"));
+    }
+}
@@ -0,0 +1,175 @@
+//! Minimal writer for uncompressed ZIP archives, with ZIP64 support for
+//! large entries.
+
+use std::io::{self, Write};
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY: u32 = 0x06064b50;
+const ZIP64_LOCATOR: u32 = 0x07064b50;
+
+/// Version needed to extract: 2.0, or 4.5 for ZIP64.
+const VERSION: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+
+/// Modification date (1980-01-01) and time (00:00) of all entries.
+const DATE: u16 = 0x21;
+const TIME: u16 = 0;
+
+/// An entry that has been written to an archive.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub name: String,
+    /// Offset of the local header.
+    pub offset: u64,
+    pub crc: u32,
+    pub size: u64,
+    /// Whether the local header has a ZIP64 extra field.
+    pub zip64: bool,
+}
+
+impl Entry {
+    /// Length of the local header.
+    pub fn header_len(&self) -> u64 {
+        30 + self.name.len() as u64 + if self.zip64 { 20 } else { 0 }
+    }
+
+    /// Writes the local header. The sizes are only written to the ZIP64
+    /// extra field if there is one.
+    pub fn write_local_header(&self, mut w: impl Write) -> io::Result<()> {
+        let size = if self.zip64 {
+            u32::MAX
+        } else {
+            self.size as u32
+        };
+
+        w.write_all(&LOCAL_HEADER.to_le_bytes())?;
+        w.write_all(&self.version().to_le_bytes())?;
+        w.write_all(&[0; 4])?; // flags, stored
+        w.write_all(&TIME.to_le_bytes())?;
+        w.write_all(&DATE.to_le_bytes())?;
+        w.write_all(&self.crc.to_le_bytes())?;
+        w.write_all(&size.to_le_bytes())?;
+        w.write_all(&size.to_le_bytes())?;
+        w.write_all(&(self.name.len() as u16).to_le_bytes())?;
+        w.write_all(&(if self.zip64 { 20u16 } else { 0 }).to_le_bytes())?;
+        w.write_all(self.name.as_bytes())?;
+        if self.zip64 {
+            w.write_all(&1u16.to_le_bytes())?;
+            w.write_all(&16u16.to_le_bytes())?;
+            w.write_all(&self.size.to_le_bytes())?;
+            w.write_all(&self.size.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn version(&self) -> u16 {
+        if self.zip64 {
+            VERSION_ZIP64
+        } else {
+            VERSION
+        }
+    }
+
+    fn write_central_header(&self, mut w: impl Write) -> io::Result<()> {
+        let mut extra = vec![];
+        let size = if self.size >= u32::MAX as u64 {
+            extra.extend(self.size.to_le_bytes());
+            extra.extend(self.size.to_le_bytes());
+            u32::MAX
+        } else {
+            self.size as u32
+        };
+        let offset = if self.offset >= u32::MAX as u64 {
+            extra.extend(self.offset.to_le_bytes());
+            u32::MAX
+        } else {
+            self.offset as u32
+        };
+        if !extra.is_empty() {
+            let len = extra.len() as u16;
+            extra.splice(0..0, [1u16.to_le_bytes(), len.to_le_bytes()].concat());
+        }
+        let version = if extra.is_empty() {
+            self.version()
+        } else {
+            VERSION_ZIP64
+        };
+
+        w.write_all(&CENTRAL_HEADER.to_le_bytes())?;
+        w.write_all(&VERSION_ZIP64.to_le_bytes())?; // made by
+        w.write_all(&version.to_le_bytes())?;
+        w.write_all(&[0; 4])?; // flags, stored
+        w.write_all(&TIME.to_le_bytes())?;
+        w.write_all(&DATE.to_le_bytes())?;
+        w.write_all(&self.crc.to_le_bytes())?;
+        w.write_all(&size.to_le_bytes())?;
+        w.write_all(&size.to_le_bytes())?;
+        w.write_all(&(self.name.len() as u16).to_le_bytes())?;
+        w.write_all(&(extra.len() as u16).to_le_bytes())?;
+        w.write_all(&[0; 10])?; // comment length, disk, attributes
+        w.write_all(&offset.to_le_bytes())?;
+        w.write_all(self.name.as_bytes())?;
+        w.write_all(&extra)
+    }
+}
+
+/// Writes a complete entry at `offset`, which must be the current position
+/// of `w`.
+pub fn write_entry(mut w: impl Write, offset: u64, name: &str, data: &[u8]) -> io::Result<Entry> {
+    let entry = Entry {
+        name: name.to_owned(),
+        offset,
+        crc: crc32fast::hash(data),
+        size: data.len() as u64,
+        zip64: data.len() as u64 >= u32::MAX as u64,
+    };
+    entry.write_local_header(&mut w)?;
+    w.write_all(data)?;
+    Ok(entry)
+}
+
+/// Writes the central directory at `offset`, which must be the current
+/// position of `w`, finishing the archive.
+pub fn write_central_directory(
+    mut w: impl Write,
+    offset: u64,
+    entries: &[Entry],
+) -> io::Result<()> {
+    let mut dir = vec![];
+    for entry in entries {
+        entry.write_central_header(&mut dir)?;
+    }
+    w.write_all(&dir)?;
+
+    let len = entries.len() as u64;
+    let size = dir.len() as u64;
+    let zip64 = offset >= u32::MAX as u64 || len >= u16::MAX as u64;
+    if zip64 {
+        let end = offset + size;
+        w.write_all(&ZIP64_END_OF_CENTRAL_DIRECTORY.to_le_bytes())?;
+        w.write_all(&44u64.to_le_bytes())?;
+        w.write_all(&VERSION_ZIP64.to_le_bytes())?;
+        w.write_all(&VERSION_ZIP64.to_le_bytes())?;
+        w.write_all(&[0; 8])?; // disks
+        w.write_all(&len.to_le_bytes())?;
+        w.write_all(&len.to_le_bytes())?;
+        w.write_all(&size.to_le_bytes())?;
+        w.write_all(&offset.to_le_bytes())?;
+
+        w.write_all(&ZIP64_LOCATOR.to_le_bytes())?;
+        w.write_all(&0u32.to_le_bytes())?;
+        w.write_all(&end.to_le_bytes())?;
+        w.write_all(&1u32.to_le_bytes())?;
+    }
+
+    w.write_all(&END_OF_CENTRAL_DIRECTORY.to_le_bytes())?;
+    w.write_all(&[0; 4])?; // disks
+    let len = len.min(u16::MAX as u64) as u16;
+    w.write_all(&len.to_le_bytes())?;
+    w.write_all(&len.to_le_bytes())?;
+    w.write_all(&(size as u32).to_le_bytes())?;
+    w.write_all(&(offset.min(u32::MAX as u64) as u32).to_le_bytes())?;
+    w.write_all(&0u16.to_le_bytes()) // comment length
+}
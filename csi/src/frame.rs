@@ -21,6 +21,9 @@
 //!
 //! [GitHub source](https://github.com/seemoo-lab/nexmon_csi/blob/fdb25ef0e4e1402e968bb644d4914ad1a3d0a84d/src/csi_extractor.c#L135-L146)
 
+use alloc::vec::Vec;
+use core::fmt;
+
 use macaddr::MacAddr6;
 use ndarray::Array1;
 use num_complex::Complex;
@@ -30,7 +33,7 @@ use crate::params::ChanSpec;
 
 /// Error returned when the chip ID does not correspond to any of
 /// the [`Chip`] variants.
-#[derive(Debug, Clone, thiserror::Error, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
 #[error("unknown chip")]
 pub struct UnknownChip;
 
@@ -39,11 +42,12 @@ pub struct UnknownChip;
 /// `TryFrom<u16>` is implemented to convert a two-byte sequence into
 /// a `Chip` variant:
 /// ```
-/// # use std::convert::TryFrom;
 /// # use csi::frame::Chip;
 /// assert_eq!(Chip::try_from(0x006a), Ok(Chip::Bcm4366c0));
 /// ```
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Chip {
     /// Broadcom BCM4366c0, used in the Asus RT-AC86U router. This is represented
     /// by the two-byte sequence `0x006a`.
@@ -61,12 +65,81 @@ impl TryFrom<u16> for Chip {
     }
 }
 
+impl From<Chip> for u16 {
+    fn from(value: Chip) -> Self {
+        match value {
+            Chip::Bcm4366c0 => 106,
+        }
+    }
+}
+
+/// Scalar type of decoded CSI values.
+///
+/// Implemented for `f64` (the default), `f32` and `i32`. The firmware packs
+/// every value as an 11-bit mantissa and an exponent, so all three are
+/// lossless; `f32` and `i32` just use half the memory of `f64`.
+pub trait Scalar: Copy + Default + PartialEq + fmt::Debug + Zero + 'static {
+    /// Converts from the integer form returned by [`unpack_complex_i32`].
+    fn from_i32(x: i32) -> Self;
+
+    /// Converts to `f64` for processing.
+    fn to_f64(self) -> f64;
+}
+
+impl Scalar for f64 {
+    fn from_i32(x: i32) -> Self {
+        x.into()
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+}
+
+impl Scalar for f32 {
+    fn from_i32(x: i32) -> Self {
+        x as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self.into()
+    }
+}
+
+impl Scalar for i32 {
+    fn from_i32(x: i32) -> Self {
+        x
+    }
+
+    fn to_f64(self) -> f64 {
+        self.into()
+    }
+}
+
+/// UDP port that the firmware sends CSI frames to.
+pub const UDP_PORT: u16 = 5500;
+
+/// Offset of the UDP payload in the Ethernet frames sent by the firmware.
+const UDP_PAYLOAD_OFFSET: usize = 42;
+
 /// A reported CSI frame.
-#[derive(Debug, Clone)]
-pub struct Frame {
+///
+/// The CSI values are `Complex<f64>` by default. See [`Scalar`] and
+/// [`FrameRef::to_frame`] for more compact alternatives.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T: serde::Serialize",
+        deserialize = "T: serde::Deserialize<'de> + num_traits::Num + Clone"
+    ))
+)]
+pub struct Frame<T = f64> {
     /// Received signal strength indicator (dBi).
     pub rssi: i8,
     /// Transmitter MAC address.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_impls::mac_addr"))]
     pub source_mac: MacAddr6,
     /// The two byte sequence number of the Wi-Fi frame that triggered
     /// the collection of the CSI contained in this packet.
@@ -80,7 +153,7 @@ pub struct Frame {
     /// Chip that generated the CSI frame.
     pub chip: Chip,
     /// Complex CSI values.
-    pub csi: Array1<Complex<f64>>,
+    pub csi: Array1<Complex<T>>,
 }
 
 /// Error returned when parsing a CSI frame.
@@ -92,7 +165,8 @@ pub enum Error {
     /// A Nexmon packet should have the magic bytes `NEXMON` at offset 6.
     #[error("not a Nexmon packet")]
     NotANexmonPacket,
-    /// A Nexmon packet should have the magic bytes `0x1111` at offset 42.
+    /// A Nexmon packet should have the magic bytes `0x1111` at the start of
+    /// the UDP payload (offset 42).
     #[error("missing magic bytes")]
     MissingMagicBytes,
     /// See [`UnknownChip`].
@@ -103,10 +177,49 @@ pub enum Error {
     InvalidChanSpec(#[from] crate::params::ParseChanSpecError),
 }
 
-impl Frame {
-    /// Parses a CSI frame from the given byte slice.
-    pub fn from_slice(b: &[u8]) -> Result<Self, Error> {
-        if b.len() < 60 {
+/// A borrowed, undecoded view of a CSI frame.
+///
+/// Unlike [`Frame`], parsing a `FrameRef` does not allocate. The CSI values
+/// are only decoded on request, into a buffer provided by the caller, which
+/// makes it possible to reuse the same buffer for every packet.
+///
+/// ```
+/// # use csi::frame::{Frame, FrameRef};
+/// # use num_complex::Complex;
+/// # fn packets() -> impl Iterator<Item = Vec<u8>> { std::iter::empty() }
+/// let mut buf = vec![Complex::<f32>::default(); 512];
+///
+/// for packet in packets() {
+///     let frame = FrameRef::from_slice(&packet).unwrap();
+///     let csi = &mut buf[..frame.len()];
+///     frame.decode_into(csi);
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameRef<'a> {
+    /// Received signal strength indicator (dBi).
+    pub rssi: i8,
+    /// Transmitter MAC address.
+    pub source_mac: MacAddr6,
+    /// The two byte sequence number of the Wi-Fi frame that triggered
+    /// the collection of the CSI contained in this packet.
+    pub seq_cnt: u16,
+    /// Core number.
+    pub core: u8,
+    /// Spatial stream number.
+    pub spatial: u8,
+    /// See the documentation for [`ChanSpec`].
+    pub chan_spec: ChanSpec,
+    /// Chip that generated the CSI frame.
+    pub chip: Chip,
+    csi: &'a [u8],
+}
+
+impl<'a> FrameRef<'a> {
+    /// Parses the header of a CSI frame from the given byte slice, which
+    /// contains the whole Ethernet frame (as captured by tcpdump).
+    pub fn from_slice(b: &'a [u8]) -> Result<Self, Error> {
+        if b.len() < UDP_PAYLOAD_OFFSET {
             return Err(Error::NotEnoughBytes);
         }
 
@@ -114,15 +227,25 @@ impl Frame {
             return Err(Error::NotANexmonPacket);
         }
 
-        if b[42..44] != [0x11, 0x11] {
+        Self::from_udp_payload(&b[UDP_PAYLOAD_OFFSET..])
+    }
+
+    /// Parses the header of a CSI frame from the payload of the UDP packet
+    /// sent by the firmware, as received on a socket bound to [`UDP_PORT`].
+    pub fn from_udp_payload(b: &'a [u8]) -> Result<Self, Error> {
+        if b.len() < 18 {
+            return Err(Error::NotEnoughBytes);
+        }
+
+        if b[..2] != [0x11, 0x11] {
             return Err(Error::MissingMagicBytes);
         }
 
         // BCM4366c0 always sets the frame control to 0, so it's not very
         // useful.
-        // let frame_control = b[45];
+        // let frame_control = b[3];
 
-        let config_bytes = [b[54], b[55]];
+        let config_bytes = [b[12], b[13]];
         let mut config = u16::from_le_bytes(config_bytes);
         // Some versions of nexutil seem to encode the config in big endian.
         // If the config is larger than the maximum possible value, assume it's
@@ -133,35 +256,187 @@ impl Frame {
         let core = (config & 0b111) as u8;
         let spatial = ((config >> 3) & 0b111) as u8;
 
-        let chan_spec: ChanSpec = u16::from_le_bytes([b[56], b[57]]).try_into()?;
-        let chip = u16::from_le_bytes([b[58], b[59]]).try_into()?;
+        let chan_spec: ChanSpec = u16::from_le_bytes([b[14], b[15]]).try_into()?;
+        let chip = u16::from_le_bytes([b[16], b[17]]).try_into()?;
 
-        let csi = &b[60..];
+        let csi = &b[18..];
 
         if csi.len() < chan_spec.bandwidth().nsub_pow2() * 4 {
             // not enough bytes
             return Err(Error::NotEnoughBytes);
         }
 
-        let mut csi = unpack_csi(csi).collect::<Vec<_>>();
-        let n = csi.len() / 2;
-        csi.rotate_right(n);
-
         Ok(Self {
-            rssi: b[44] as i8,
-            source_mac: MacAddr6::new(b[46], b[47], b[48], b[49], b[50], b[51]),
-            seq_cnt: u16::from_le_bytes([b[52], b[53]]),
+            rssi: b[2] as i8,
+            source_mac: MacAddr6::new(b[4], b[5], b[6], b[7], b[8], b[9]),
+            seq_cnt: u16::from_le_bytes([b[10], b[11]]),
             core,
             spatial,
             chan_spec,
             chip,
-            csi: csi.into(),
+            csi: &csi[..csi.len() / 4 * 4],
         })
     }
+
+    /// Number of CSI values.
+    pub fn len(&self) -> usize {
+        self.csi.len() / 4
+    }
+
+    /// Returns `true` if there are no CSI values.
+    pub fn is_empty(&self) -> bool {
+        self.csi.is_empty()
+    }
+
+    /// The packed CSI values, as sent by the firmware.
+    pub fn raw_csi(&self) -> &'a [u8] {
+        self.csi
+    }
+
+    /// Decodes the CSI values into `out`, in the same (FFT-shifted) order
+    /// as [`Frame::csi`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `out.len()` is not equal to [`FrameRef::len`].
+    pub fn decode_into<T: Scalar>(&self, out: &mut [Complex<T>]) {
+        assert_eq!(out.len(), self.len(), "output buffer has the wrong size");
+
+        // equivalent to unpacking and then rotating right by n / 2
+        let n = self.len();
+        let (lo, hi) = self.csi.split_at((n - n / 2) * 4);
+        let (out_lo, out_hi) = out.split_at_mut(n / 2);
+
+        unpack_csi_into(lo, out_hi);
+        unpack_csi_into(hi, out_lo);
+    }
+
+    /// Decodes the frame into an owned [`Frame`].
+    ///
+    /// ```
+    /// # use csi::frame::{Frame, FrameRef};
+    /// # fn f(packet: &[u8]) -> Result<(), csi::frame::Error> {
+    /// let frame: Frame<f32> = FrameRef::from_slice(packet)?.to_frame();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn to_frame<T: Scalar>(&self) -> Frame<T> {
+        let mut csi = Array1::from_elem(self.len(), Complex::new(T::zero(), T::zero()));
+        self.decode_into(csi.as_slice_mut().unwrap());
+
+        Frame {
+            rssi: self.rssi,
+            source_mac: self.source_mac,
+            seq_cnt: self.seq_cnt,
+            core: self.core,
+            spatial: self.spatial,
+            chan_spec: self.chan_spec,
+            chip: self.chip,
+            csi,
+        }
+    }
+}
+
+impl<T: Scalar> From<FrameRef<'_>> for Frame<T> {
+    fn from(value: FrameRef<'_>) -> Self {
+        value.to_frame()
+    }
+}
+
+impl Frame {
+    /// Parses a CSI frame from the given byte slice.
+    ///
+    /// See [`FrameRef`] for a non-allocating alternative, and for decoding
+    /// into other [`Scalar`] types.
+    pub fn from_slice(b: &[u8]) -> Result<Self, Error> {
+        FrameRef::from_slice(b).map(Into::into)
+    }
+
+    /// Parses a CSI frame from the payload of a UDP packet. See
+    /// [`FrameRef::from_udp_payload`].
+    pub fn from_udp_payload(b: &[u8]) -> Result<Self, Error> {
+        FrameRef::from_udp_payload(b).map(Into::into)
+    }
+}
+
+impl<T: Scalar> Frame<T> {
+    /// Encodes the frame as an Ethernet frame, the way the firmware would
+    /// have sent it. This is the inverse of [`Frame::from_slice`].
+    ///
+    /// Returns `None` if the core or spatial stream number does not fit in
+    /// three bits, or if any of the CSI values cannot be packed (see
+    /// [`pack_complex`]).
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        if self.core > 0b111 || self.spatial > 0b111 {
+            return None;
+        }
+
+        let mut words = self
+            .csi
+            .iter()
+            .map(|z| pack_complex(Complex::new(z.re.to_f64(), z.im.to_f64())))
+            .collect::<Option<Vec<_>>>()?;
+        let n = words.len() / 2;
+        words.rotate_left(n);
+
+        let udp_len = 8 + 18 + 4 * words.len();
+        let ip_len = 20 + udp_len;
+        let mut b = Vec::with_capacity(14 + ip_len);
+
+        // Ethernet: broadcast from "NEXMON", IPv4
+        b.extend_from_slice(&[0xff; 6]);
+        b.extend_from_slice(b"NEXMON");
+        b.extend_from_slice(&[0x08, 0x00]);
+
+        // IPv4: 10.10.10.10 -> 255.255.255.255, TTL 1, UDP
+        let mut ip = [
+            0x45, 0x00, 0, 0, 0x00, 0x01, 0x00, 0x00, 0x01, 0x11, 0, 0, 10, 10, 10, 10, 0xff, 0xff,
+            0xff, 0xff,
+        ];
+        ip[2..4].copy_from_slice(&(ip_len as u16).to_be_bytes());
+        let sum = ip
+            .chunks_exact(2)
+            .map(|w| u32::from(u16::from_be_bytes([w[0], w[1]])))
+            .sum::<u32>();
+        let checksum = !((sum & 0xffff) + (sum >> 16)) as u16;
+        ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+        b.extend_from_slice(&ip);
+
+        // UDP: port 5500 -> 5500, no checksum
+        b.extend_from_slice(&UDP_PORT.to_be_bytes());
+        b.extend_from_slice(&UDP_PORT.to_be_bytes());
+        b.extend_from_slice(&(udp_len as u16).to_be_bytes());
+        b.extend_from_slice(&[0, 0]);
+
+        b.extend_from_slice(&[0x11, 0x11]);
+        b.push(self.rssi as u8);
+        b.push(0); // frame control
+        b.extend_from_slice(self.source_mac.as_bytes());
+        b.extend_from_slice(&self.seq_cnt.to_le_bytes());
+        b.extend_from_slice(&(u16::from(self.core) | u16::from(self.spatial) << 3).to_le_bytes());
+        b.extend_from_slice(&self.chan_spec.as_u16().to_le_bytes());
+        b.extend_from_slice(&u16::from(self.chip).to_le_bytes());
+
+        for word in words {
+            b.extend_from_slice(&word.to_le_bytes());
+        }
+
+        Some(b)
+    }
 }
 
 /// Unpacks a complex value from the given 32-bit integer.
 pub fn unpack_complex(i: u32) -> Complex<f64> {
+    let z = unpack_complex_i32(i);
+    Complex::new(z.re as f64, z.im as f64)
+}
+
+/// Unpacks a complex value from the given 32-bit integer, without
+/// converting it to floating point. See [`unpack_complex`].
+///
+/// This decodes [`FloatFormat::ACPHY`]; see [`FloatFormat`] for other
+/// packings.
+pub fn unpack_complex_i32(i: u32) -> Complex<i32> {
     // unpack_float_acphy(
     //   nbits: 10,
     //   autoscale: 0,
@@ -214,16 +489,323 @@ pub fn unpack_complex(i: u32) -> Complex<f64> {
         im = im.overflowing_shl(exp as _).0;
     }
 
-    Complex::new(re as f64, im as f64)
+    Complex::new(re, im)
+}
+
+/// Packs a complex value into a 32-bit integer. This is the inverse of
+/// [`unpack_complex`].
+///
+/// Returns `None` if the value cannot be represented, i.e. if the real and
+/// imaginary parts are not integers that can be written as an 11-bit
+/// mantissa shifted by a common exponent.
+///
+/// ```
+/// # use csi::frame::{pack_complex, unpack_complex};
+/// # use num_complex::Complex;
+/// let z = Complex::new(-1536., 4096.);
+/// assert_eq!(unpack_complex(pack_complex(z).unwrap()), z);
+///
+/// assert_eq!(pack_complex(Complex::new(0.5, 0.)), None);
+/// ```
+pub fn pack_complex(z: Complex<f64>) -> Option<u32> {
+    const MAN_MAX: u32 = 0b11111111111; // 11 bits
+
+    let part = |x: f64| {
+        // `as` saturates, so this also rejects out-of-range values and NaN
+        let i = x as i32;
+        if i as f64 != x {
+            return None;
+        }
+        Some((i < 0, i.unsigned_abs()))
+    };
+
+    let (re_neg, re) = part(z.re)?;
+    let (im_neg, im) = part(z.im)?;
+
+    // smallest shift that makes both mantissas fit
+    let shift = (0..32u32).find(|&s| re >> s <= MAN_MAX && im >> s <= MAN_MAX)?;
+    if (re | im) & ((1 << shift) - 1) != 0 {
+        // low bits would be lost
+        return None;
+    }
+
+    // shifts wrap around at 32, so `exp + 42 == shift + 32` is always
+    // reachable with a 6-bit exponent
+    let exp = (shift as i32 - 10) as u32 & 0b111111;
+
+    let mut i = exp;
+    i |= (re >> shift) << 18;
+    i |= (im >> shift) << 6;
+    if re_neg {
+        i |= 1 << 29;
+    }
+    if im_neg {
+        i |= 1 << 17;
+    }
+
+    Some(i)
+}
+
+/// Branchless equivalent of [`unpack_complex_i32`].
+#[inline(always)]
+fn unpack_complex_branchless(i: u32) -> Complex<i32> {
+    const MAN_MASK: u32 = 0b11111111111; // 11 bits
+
+    // The sign-extended exponent plus 42 lies in 10..=73, so it is never
+    // negative (and never below -12), and since the shift wraps around at
+    // 32 only `(exp + 42) % 32` matters. The sign extension itself adds a
+    // multiple of 64, which vanishes modulo 32.
+    let shift = (i + 42) & 0b11111;
+
+    // two's complement negation without branches: (x ^ -1) + 1 == -x
+    let re_sign = (i >> 29 & 1).wrapping_neg();
+    let im_sign = (i >> 17 & 1).wrapping_neg();
+    let re = ((i >> 18 & MAN_MASK) ^ re_sign).wrapping_sub(re_sign);
+    let im = ((i >> 6 & MAN_MASK) ^ im_sign).wrapping_sub(im_sign);
+
+    Complex::new((re << shift) as i32, (im << shift) as i32)
+}
+
+/// Unpacks the CSI values from `b` into `out`.
+///
+/// This produces the same values as [`unpack_csi`], but decodes whole
+/// slices without branching on the sign or exponent of each value, which
+/// lets the compiler vectorise the loop.
+///
+/// ```
+/// # use csi::frame::{unpack_csi, unpack_csi_into};
+/// # use num_complex::Complex;
+/// let b = [0x21, 0x0c, 0x0f, 0x0f, 0x00, 0xb6, 0xb6, 0xbc];
+/// let mut out = [Complex::<f32>::default(); 2];
+/// unpack_csi_into(&b, &mut out);
+///
+/// assert!(out.iter().zip(unpack_csi(&b)).all(|(a, b)| a.re as f64 == b.re));
+/// ```
+///
+/// # Panics
+///
+/// Panics if `out.len()` is not equal to `b.len() / 4`.
+pub fn unpack_csi_into<T: Scalar>(b: &[u8], out: &mut [Complex<T>]) {
+    assert_eq!(out.len(), b.len() / 4, "output buffer has the wrong size");
+
+    // fixed-size blocks give the compiler a known trip count to vectorise
+    const LANES: usize = 8;
+
+    let mut src = b.chunks_exact(4 * LANES);
+    let mut dst = out.chunks_exact_mut(LANES);
+    for (src, dst) in (&mut src).zip(&mut dst) {
+        let mut words = [0; LANES];
+        for (w, b) in words.iter_mut().zip(src.chunks_exact(4)) {
+            *w = u32::from_le_bytes(b.try_into().unwrap());
+        }
+        for (dst, w) in dst.iter_mut().zip(words) {
+            let z = unpack_complex_branchless(w);
+            *dst = Complex::new(T::from_i32(z.re), T::from_i32(z.im));
+        }
+    }
+
+    for (dst, src) in dst
+        .into_remainder()
+        .iter_mut()
+        .zip(src.remainder().chunks_exact(4))
+    {
+        let z = unpack_complex_branchless(u32::from_le_bytes(src.try_into().unwrap()));
+        *dst = Complex::new(T::from_i32(z.re), T::from_i32(z.im));
+    }
+}
+
+/// Packed floating-point format of the CSI values.
+///
+/// The fields mirror the arguments of `unpack_float_acphy` in the
+/// [Nexmon CSI utilities](https://github.com/seemoo-lab/nexmon_csi/blob/fdb25ef0e4e1402e968bb644d4914ad1a3d0a84d/utils/matlab/unpack_float.c),
+/// where each value is a sign bit and an `nman - 1` bit mantissa for the
+/// real and imaginary parts, followed by a shared `nexp` bit exponent.
+///
+/// ```
+/// # use csi::frame::{unpack_csi, FloatFormat};
+/// # use num_complex::Complex;
+/// let b = [0x21, 0x0c, 0x0f, 0x0f, 0x00, 0xb6, 0xb6, 0xbc];
+/// let mut out = [Complex::<f64>::default(); 2];
+/// FloatFormat::ACPHY.unpack_into(&b, &mut out);
+///
+/// assert!(out.iter().copied().eq(unpack_csi(&b)));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FloatFormat {
+    /// Number of bits the largest exponent is scaled to (`nbits`).
+    pub nbits: i32,
+    /// Whether to take the magnitude of the mantissas into account when
+    /// finding the largest exponent (`autoscale`).
+    pub autoscale: bool,
+    /// Exponent shift applied to every value (`shft`).
+    ///
+    /// `unpack_float_acphy` overwrites its `shft` argument with
+    /// `nbits - maxbit`, which is what `None` does.
+    pub shift: Option<i32>,
+    /// Width of each mantissa, including its sign bit (`nman`).
+    pub nman: u32,
+    /// Width of the exponent (`nexp`).
+    pub nexp: u32,
+}
+
+impl FloatFormat {
+    /// The format used by the BCM4366c0 (`fmt = 1`), as decoded by
+    /// [`unpack_complex`].
+    pub const ACPHY: Self = Self {
+        nbits: 10,
+        autoscale: false,
+        shift: None,
+        nman: 12,
+        nexp: 6,
+    };
+
+    /// The older format selected by `fmt = 0`.
+    pub const ACPHY_FMT0: Self = Self {
+        nman: 9,
+        nexp: 5,
+        ..Self::ACPHY
+    };
+
+    /// Returns `true` if the mantissas and exponent fit in 32 bits, and
+    /// the exponent fits in an `i8` like in `unpack_float_acphy`.
+    pub const fn is_valid(&self) -> bool {
+        self.nman >= 2 && self.nexp >= 1 && self.nexp <= 8 && self.nexp + 2 * self.nman <= 32
+    }
+
+    /// Unpacks the CSI values from `b` into `out`.
+    ///
+    /// Unlike [`unpack_csi_into`], the values depend on each other when
+    /// [`FloatFormat::autoscale`] is set, so the whole frame should be
+    /// passed at once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the format is not [valid](FloatFormat::is_valid), or if
+    /// `out.len()` is not equal to `b.len() / 4`.
+    pub fn unpack_into<T: Scalar>(&self, b: &[u8], out: &mut [Complex<T>]) {
+        assert!(self.is_valid(), "unsupported float format");
+        assert_eq!(out.len(), b.len() / 4, "output buffer has the wrong size");
+
+        let Self { nman, nexp, .. } = *self;
+        let iq_mask = (1 << (nman - 1)) - 1;
+        let e_mask = (1 << nexp) - 1;
+        let e_p = 1 << (nexp - 1);
+        let sgnr_mask = 1 << (nexp + 2 * nman - 1);
+        let sgni_mask = sgnr_mask >> nman;
+        let e_zero = -(nman as i32);
+
+        let words = || {
+            b.chunks_exact(4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        };
+        let exp = |w: u32| {
+            let e = (w & e_mask) as i32;
+            if e >= e_p {
+                e - (e_p << 1)
+            } else {
+                e
+            }
+        };
+        let mantissas = |w: u32| ((w >> (nexp + nman)) & iq_mask, (w >> nexp) & iq_mask);
+
+        let shift = self.shift.unwrap_or_else(|| {
+            let mut maxbit = -e_p;
+            if self.autoscale {
+                for w in words() {
+                    let (vi, vq) = mantissas(w);
+                    if let Some(bits) = (vi | vq).checked_ilog2() {
+                        maxbit = maxbit.max(exp(w) + bits as i32);
+                    }
+                }
+            }
+            self.nbits - maxbit
+        });
+
+        let scale = |v: u32, neg: bool, e: i32| {
+            let v = if e < e_zero {
+                0
+            } else if e < 0 {
+                v >> -e
+            } else {
+                // like the C code on most platforms, shifts wrap around at 32
+                v.wrapping_shl(e as u32)
+            };
+            let v = v as i32;
+            T::from_i32(if neg { v.wrapping_neg() } else { v })
+        };
+
+        for (dst, w) in out.iter_mut().zip(words()) {
+            let (vi, vq) = mantissas(w);
+            let e = exp(w).wrapping_add(shift);
+            *dst = Complex::new(
+                scale(vi, w & sgnr_mask != 0, e),
+                scale(vq, w & sgni_mask != 0, e),
+            );
+        }
+    }
+}
+
+impl Default for FloatFormat {
+    fn default() -> Self {
+        Self::ACPHY
+    }
 }
 
 /// Unpacks the CSI values from the given buffer.
+///
+/// See [`unpack_csi_into`] for a faster alternative.
 pub fn unpack_csi(b: &[u8]) -> impl Iterator<Item = Complex<f64>> + '_ {
     b.chunks_exact(4)
         .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
         .map(unpack_complex)
 }
 
+/// Unpacks a complex value like the
+/// [WiROS](https://github.com/ucsdwcsng/wiros_csi_node) CSI parser.
+///
+/// WiROS builds an IEEE 754 double directly from the packed word: the
+/// 6-bit exponent is read as unsigned with a bias of 31, and each 11-bit
+/// mantissa is normalised to an implicit leading one. Compared to
+/// [`unpack_complex`], this gives `mantissa * 2^(exp - 41)` without any
+/// wrap-around. Mantissas of 0 and 1 are decoded as ±1.
+///
+/// ```
+/// # use csi::frame::unpack_complex_wiros;
+/// # use num_complex::Complex;
+/// // real: +3, imaginary: -2, exponent: 41
+/// let i = 3 << 18 | 1 << 17 | 2 << 6 | 41;
+/// assert_eq!(unpack_complex_wiros(i), Complex::new(3., -2.));
+/// ```
+pub fn unpack_complex_wiros(i: u32) -> Complex<f64> {
+    const MAN_MASK: u32 = 0b11111111111; // 11 bits
+    const FRAC_MASK: u64 = 0b1111111111; // 10 bits
+
+    let exp = (i & 0b111111) as u64 + 1023 - 31;
+
+    let part = |mant: u32, sign: u32| {
+        let (mant, exp) = match mant.checked_ilog2() {
+            // WiROS gives up after 10 shifts, which also discards a mantissa of 1
+            Some(bits) if bits > 0 => ((mant as u64) << (10 - bits), exp - 10 + bits as u64),
+            _ => (0, 1023),
+        };
+        f64::from_bits((sign as u64) << 63 | exp << 52 | (mant & FRAC_MASK) << 42)
+    };
+
+    Complex::new(
+        part(i >> 18 & MAN_MASK, i >> 29 & 1),
+        part(i >> 6 & MAN_MASK, i >> 17 & 1),
+    )
+}
+
+/// Unpacks the CSI values from the given buffer like WiROS. See
+/// [`unpack_complex_wiros`].
+pub fn unpack_csi_wiros(b: &[u8]) -> impl Iterator<Item = Complex<f64>> + '_ {
+    b.chunks_exact(4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .map(unpack_complex_wiros)
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -232,5 +814,115 @@ mod tests {
         let frame = super::Frame::from_slice(bytes).unwrap();
 
         assert_eq!(frame.rssi, -51);
+
+        let frame_ref = super::FrameRef::from_slice(bytes).unwrap();
+        let mut csi = vec![num_complex::Complex::<f32>::default(); frame_ref.len()];
+        frame_ref.decode_into(&mut csi);
+        assert!(csi
+            .iter()
+            .zip(&frame.csi)
+            .all(|(a, b)| a.re as f64 == b.re && a.im as f64 == b.im));
+
+        let frame_i32: super::Frame<i32> = frame_ref.to_frame();
+        assert!(frame_i32
+            .csi
+            .iter()
+            .zip(&frame.csi)
+            .all(|(a, b)| a.re as f64 == b.re && a.im as f64 == b.im));
+        assert_eq!(frame_i32.to_bytes(), frame.to_bytes());
+
+        let bytes = frame.to_bytes().unwrap();
+        assert_eq!(super::Frame::from_slice(&bytes).unwrap(), frame);
+        assert_eq!(
+            super::Frame::from_udp_payload(&bytes[super::UDP_PAYLOAD_OFFSET..]).unwrap(),
+            frame
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn frame_serde() {
+        use crate::{
+            ieee80211::{Band, Bandwidth},
+            params::ChanSpec,
+            proc::WifiCsi,
+        };
+        use num_complex::Complex;
+
+        let frame = super::Frame::<i32> {
+            rssi: -51,
+            source_mac: macaddr::MacAddr6::new(0x00, 0x11, 0x22, 0x33, 0x44, 0x55),
+            seq_cnt: 42,
+            core: 1,
+            spatial: 2,
+            chan_spec: ChanSpec::new(36, Band::Band5G, Bandwidth::Bw20).unwrap(),
+            chip: super::Chip::Bcm4366c0,
+            csi: ndarray::arr1(&[Complex::new(1, -2), Complex::new(-3, 4)]),
+        };
+        let json = serde_json::to_string(&frame).unwrap();
+        assert_eq!(
+            serde_json::from_str::<super::Frame<i32>>(&json).unwrap(),
+            frame
+        );
+
+        let mut csi = WifiCsi::<f32>::new(frame.chan_spec, frame.rssi);
+        csi.insert(
+            1,
+            2,
+            frame.csi.mapv(|z| Complex::new(z.re as f32, z.im as f32)),
+        );
+        let json = serde_json::to_string(&csi).unwrap();
+        let csi2: WifiCsi<f32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(csi2.get(1, 2), csi.get(1, 2));
+        assert_eq!(csi2.get(0, 0), None);
+        assert_eq!((csi2.chan_spec, csi2.rssi), (csi.chan_spec, csi.rssi));
+    }
+
+    #[test]
+    fn unpack_branchless() {
+        use super::{unpack_complex_branchless, unpack_complex_i32};
+
+        for i in (0..=u32::MAX).step_by(997) {
+            assert_eq!(
+                unpack_complex_branchless(i),
+                unpack_complex_i32(i),
+                "{i:#x}"
+            );
+        }
+    }
+
+    #[test]
+    fn float_format() {
+        use super::{unpack_csi_into, FloatFormat};
+        use num_complex::Complex;
+
+        let b = (0..=u32::MAX)
+            .step_by(9973)
+            .flat_map(u32::to_le_bytes)
+            .collect::<Vec<_>>();
+        let mut expected = vec![Complex::<i32>::default(); b.len() / 4];
+        let mut actual = expected.clone();
+
+        unpack_csi_into(&b, &mut expected);
+        FloatFormat::ACPHY.unpack_into(&b, &mut actual);
+        assert_eq!(actual, expected);
+
+        // the default shift is nbits - maxbit
+        FloatFormat {
+            shift: Some(42),
+            ..FloatFormat::ACPHY
+        }
+        .unpack_into(&b, &mut actual);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn pack_unpack() {
+        use super::{pack_complex, unpack_complex};
+
+        for i in (0..=u32::MAX).step_by(9973) {
+            let z = unpack_complex(i);
+            assert_eq!(unpack_complex(pack_complex(z).unwrap()), z, "{i:#x}");
+        }
     }
 }
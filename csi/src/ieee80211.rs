@@ -4,17 +4,22 @@
 //! - [802.11ac: A Survival Guide](https://www.oreilly.com/library/view/80211ac-a-survival/9781449357702/ch02.html)
 //! - [List of WLAN channels (Wikipedia)](https://en.wikipedia.org/wiki/List_of_WLAN_channels#5_GHz_(802.11a/h/n/ac/ax))
 
+#[cfg(feature = "std")]
 use std::marker::PhantomData;
 
+#[cfg(feature = "std")]
 use ndarray::Array1;
+#[cfg(feature = "std")]
 use uom::si::{f64::Frequency, frequency::hertz};
 
 /// Speed of light in meters per second.
+#[cfg(feature = "std")]
 const C: f64 = 299_792_458.;
 
 /// Band.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Band {
     /// 2.4 GHz.
     Band2G,
@@ -25,6 +30,7 @@ pub enum Band {
 /// Bandwidth.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Bandwidth {
     /// 20 MHz.
     Bw20,
@@ -67,6 +73,7 @@ impl Bandwidth {
     }
 
     /// The frequency in Hz.
+    #[cfg(feature = "std")]
     pub const fn freq(&self) -> Frequency {
         Frequency {
             dimension: PhantomData,
@@ -157,6 +164,7 @@ pub const fn subcarrier_type_160mhz(i: i16) -> SubcarrierType {
     }
 }
 
+#[cfg(feature = "std")]
 fn channel_mhz(channel: u8) -> u32 {
     5000 + 5 * channel as u32
 }
@@ -172,6 +180,7 @@ fn channel_mhz(channel: u8) -> u32 {
 /// assert_eq!(freqs[0], 5.250e9);
 /// assert_eq!(freqs[255], 5.330e9);
 /// ```
+#[cfg(feature = "std")]
 pub fn subcarrier_freqs(center: u8, bandwidth: Bandwidth) -> Array1<f64> {
     let center = channel_mhz(center) as f64 * 1e6;
     let half_bw = bandwidth.freq().get::<hertz>() / 2.;
@@ -180,13 +189,14 @@ pub fn subcarrier_freqs(center: u8, bandwidth: Bandwidth) -> Array1<f64> {
 }
 
 /// Returns the subcarrier wavelengths for a given center frequency and bandwidth.
+#[cfg(feature = "std")]
 pub fn subcarrier_lambda(center: u8, bandwidth: Bandwidth) -> Array1<f64> {
     let mut v = subcarrier_freqs(center, bandwidth);
     v.mapv_inplace(|f| C / f);
     v
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use crate::ieee80211::channel_mhz;
 
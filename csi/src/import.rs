@@ -0,0 +1,15 @@
+//! Importers for CSI captured with other tools.
+//!
+//! Each importer parses the tool's native records and converts them to
+//! [`WifiCsi`](crate::proc::WifiCsi), placing the reported subcarriers on
+//! the same FFT-shifted grid as Nexmon CSI so that the [`proc`](crate::proc)
+//! algorithms can be applied unchanged.
+//!
+//! Nexmon CSI itself is captured as UDP packets; [`pcapng`] reads the
+//! captures that Wireshark and tcpdump save them in.
+
+pub mod atheros;
+pub mod esp32;
+pub mod intel;
+pub mod pcapng;
+pub mod picoscenes;
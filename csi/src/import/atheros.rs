@@ -0,0 +1,434 @@
+//! Reader for the [Atheros CSI Tool](https://wands.sg/research/wifi/AtherosCSI/)
+//! (ath9k).
+//!
+//! The log written by `recv_csi` is a sequence of records, each prefixed
+//! by a `u16` length, parsed like
+//! [`read_log_file.m`](https://github.com/xieyaxiongfly/Atheros-CSI-Tool-UserSpace-APP/blob/master/matlab/read_log_file.m).
+//! The CSI itself is a little-endian bit stream of 10-bit two's complement
+//! values, imaginary part first.
+
+use std::io::{self, Read};
+
+use ndarray::{s, Array1, Array3};
+use num_complex::Complex;
+
+use crate::{
+    ieee80211::{Band, Bandwidth},
+    params::ChanSpec,
+    proc::WifiCsi,
+};
+
+/// Length of the record header, excluding the length prefix.
+const HEADER_LEN: usize = 25;
+
+/// Byte order of the header fields, which depends on the host that
+/// recorded the log (e.g. big-endian on MIPS routers).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// Little-endian.
+    #[default]
+    Little,
+    /// Big-endian.
+    Big,
+}
+
+impl ByteOrder {
+    fn u16(self, b: &[u8]) -> u16 {
+        let b = b[..2].try_into().unwrap();
+        match self {
+            Self::Little => u16::from_le_bytes(b),
+            Self::Big => u16::from_be_bytes(b),
+        }
+    }
+
+    fn u64(self, b: &[u8]) -> u64 {
+        let b = b[..8].try_into().unwrap();
+        match self {
+            Self::Little => u64::from_le_bytes(b),
+            Self::Big => u64::from_be_bytes(b),
+        }
+    }
+}
+
+/// Error returned when reading a record.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Reading from the underlying reader failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The record is shorter than its header says.
+    #[error("truncated record")]
+    Truncated,
+    /// The number of tones is neither 56 nor 114, or does not match the
+    /// bandwidth.
+    #[error("invalid number of tones ({0})")]
+    InvalidTones(u8),
+    /// The number of antennas is not between 1 and 3.
+    #[error("invalid antenna count (nr = {nr}, nc = {nc})")]
+    InvalidAntennas {
+        /// Number of receive antennas.
+        nr: u8,
+        /// Number of transmit antennas.
+        nc: u8,
+    },
+}
+
+/// A CSI record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// Timestamp of the NIC (µs).
+    pub timestamp: u64,
+    /// Channel frequency (MHz).
+    pub channel: u16,
+    /// PHY error code, or 0.
+    pub err_info: u8,
+    /// Noise floor.
+    pub noise_floor: u8,
+    /// Rate code.
+    pub rate: u8,
+    /// Bandwidth of the frame.
+    pub bandwidth: Bandwidth,
+    /// Number of receive antennas.
+    pub nr: u8,
+    /// Number of transmit antennas (spatial streams).
+    pub nc: u8,
+    /// Combined RSSI, relative to the noise floor (dB).
+    pub rssi: u8,
+    /// RSSI of each receive antenna, relative to the noise floor (dB).
+    pub rssi_chains: [u8; 3],
+    /// CSI, indexed by receive antenna, transmit antenna and subcarrier.
+    /// Empty if the record has no CSI.
+    pub csi: Array3<Complex<f64>>,
+    /// The received frame.
+    pub payload: Vec<u8>,
+}
+
+/// Returns the subcarrier indices of the tones reported at the given
+/// bandwidth: 56 tones at 20 MHz and 114 tones at 40 MHz.
+pub fn subcarriers(bandwidth: Bandwidth) -> impl Iterator<Item = i8> {
+    let (lo, hi) = match bandwidth {
+        Bandwidth::Bw20 => (1, 28),
+        _ => (2, 58),
+    };
+    (-hi..=-lo).chain(lo..=hi)
+}
+
+/// Sign-extends a 10-bit value.
+fn sign_extend(x: u32) -> i32 {
+    ((x << 22) as i32) >> 22
+}
+
+/// Unpacks the bit stream of `nr * nc * num_tones` complex values into an
+/// array indexed by receive antenna, transmit antenna and tone, or returns
+/// `None` if `b` is too short.
+pub(crate) fn unpack_csi(b: &[u8], nr: u8, nc: u8, num_tones: u8) -> Option<Array3<Complex<f64>>> {
+    let (nr, nc, num_tones) = (usize::from(nr), usize::from(nc), usize::from(num_tones));
+    let values = 2 * num_tones * nr * nc;
+    if b.len() * 8 < values * 10 {
+        return None;
+    }
+
+    // the i-th 10-bit value of the little-endian bit stream, which
+    // starts at an even bit offset and therefore spans at most two bytes
+    let value = |i: usize| {
+        let bit = i * 10;
+        let lo = u32::from(b[bit / 8]);
+        let hi = u32::from(b.get(bit / 8 + 1).copied().unwrap_or(0));
+        sign_extend((lo | hi << 8) >> (bit % 8) & 0x3ff)
+    };
+    let mut values = (0..values).map(value);
+
+    let mut csi = Array3::zeros((nr, nc, num_tones));
+    for tone in 0..num_tones {
+        for c in 0..nc {
+            for r in 0..nr {
+                let im = values.next().unwrap();
+                let re = values.next().unwrap();
+                csi[[r, c, tone]] = Complex::new(re.into(), im.into());
+            }
+        }
+    }
+    Some(csi)
+}
+
+impl Record {
+    /// Parses a record, excluding its length prefix.
+    pub fn from_slice(b: &[u8], order: ByteOrder) -> Result<Self, Error> {
+        let header = b.get(..HEADER_LEN).ok_or(Error::Truncated)?;
+        let csi_len = order.u16(&header[8..]) as usize;
+        let payload_len = order.u16(&header[23..]) as usize;
+        let body = &b[HEADER_LEN..];
+        if body.len() < csi_len + payload_len {
+            return Err(Error::Truncated);
+        }
+
+        let bandwidth = if header[15] == 0 {
+            Bandwidth::Bw20
+        } else {
+            Bandwidth::Bw40
+        };
+        let num_tones = header[16];
+        let (nr, nc) = (header[17], header[18]);
+
+        let csi = if csi_len == 0 {
+            Array3::zeros((0, 0, 0))
+        } else {
+            if usize::from(num_tones) != subcarriers(bandwidth).count() {
+                return Err(Error::InvalidTones(num_tones));
+            }
+            if !(1..=3).contains(&nr) || !(1..=3).contains(&nc) {
+                return Err(Error::InvalidAntennas { nr, nc });
+            }
+            unpack_csi(&body[..csi_len], nr, nc, num_tones).ok_or(Error::Truncated)?
+        };
+
+        Ok(Self {
+            timestamp: order.u64(header),
+            channel: order.u16(&header[10..]),
+            err_info: header[12],
+            noise_floor: header[13],
+            rate: header[14],
+            bandwidth,
+            nr,
+            nc,
+            rssi: header[19],
+            rssi_chains: [header[20], header[21], header[22]],
+            csi,
+            payload: body[csi_len..csi_len + payload_len].to_vec(),
+        })
+    }
+
+    /// Returns the chanspec corresponding to [`Record::channel`] and
+    /// [`Record::bandwidth`], if it is a valid one.
+    ///
+    /// The log does not say on which side of the control channel a 40 MHz
+    /// channel lies, so this relies on the 5 GHz channel plan.
+    pub fn chan_spec(&self) -> Option<ChanSpec> {
+        let (band, channel) = match self.channel {
+            2412..=2472 => (Band::Band2G, (self.channel - 2407) / 5),
+            // channel 14 is 12 MHz above channel 13, off the 5 MHz grid
+            2484 => (Band::Band2G, 14),
+            5000..=5900 => (Band::Band5G, (self.channel - 5000) / 5),
+            _ => return None,
+        };
+        ChanSpec::new(u8::try_from(channel).ok()?, band, self.bandwidth)
+    }
+
+    /// Converts the record to a [`WifiCsi`], with receive antennas as cores
+    /// and transmit antennas as spatial streams.
+    ///
+    /// The tones are placed on a grid of [`Bandwidth::nsub_pow2`]
+    /// subcarriers in the same order as
+    /// [`Frame::csi`](crate::frame::Frame::csi), with zeros in between.
+    /// Returns `None` if the record has no CSI, or if the bandwidth of
+    /// `chan_spec` does not match [`Record::bandwidth`].
+    pub fn to_wifi_csi(&self, chan_spec: ChanSpec) -> Option<WifiCsi> {
+        if self.csi.is_empty() || chan_spec.bandwidth() != self.bandwidth {
+            return None;
+        }
+
+        let n = self.bandwidth.nsub_pow2();
+        let mut wifi_csi = WifiCsi::new(chan_spec, self.rssi.min(i8::MAX as u8) as i8);
+
+        for r in 0..self.nr.into() {
+            for c in 0..self.nc.into() {
+                let mut grid = Array1::zeros(n);
+                for (k, &z) in subcarriers(self.bandwidth).zip(self.csi.slice(s![r, c, ..])) {
+                    grid[(k as isize + n as isize / 2) as usize] = z;
+                }
+                wifi_csi.insert(r, c, grid);
+            }
+        }
+
+        Some(wifi_csi)
+    }
+}
+
+/// Reads records from a `recv_csi` log.
+///
+/// Like `read_log_file.m`, a truncated record at the end of the log (e.g.
+/// from interrupting `recv_csi`) is ignored.
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use csi::import::atheros::{ByteOrder, LogReader};
+///
+/// let file = std::fs::File::open("csi.log")?;
+/// for record in LogReader::new(std::io::BufReader::new(file), ByteOrder::Little) {
+///     let record = record?;
+///     if let Some(csi) = record.chan_spec().and_then(|cs| record.to_wifi_csi(cs)) {
+///         println!("{:?}", csi::proc::tof(&csi));
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct LogReader<R> {
+    reader: R,
+    order: ByteOrder,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> LogReader<R> {
+    /// Creates a new reader.
+    pub fn new(reader: R, order: ByteOrder) -> Self {
+        Self {
+            reader,
+            order,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Reads the next record, or `None` at the end of the log.
+    pub fn read_record(&mut self) -> Result<Option<Record>, Error> {
+        let mut len = [0; 2];
+        if let Err(e) = self.reader.read_exact(&mut len) {
+            return match e.kind() {
+                io::ErrorKind::UnexpectedEof => Ok(None),
+                _ => Err(e.into()),
+            };
+        }
+
+        self.buf.resize(self.order.u16(&len).into(), 0);
+        match self.reader.read_exact(&mut self.buf) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            r => r?,
+        }
+
+        Record::from_slice(&self.buf, self.order).map(Some)
+    }
+}
+
+impl<R: Read> Iterator for LogReader<R> {
+    type Item = Result<Record, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packs a record like the ath9k driver does.
+    fn record(order: ByteOrder, bandwidth: Bandwidth, nr: u8, nc: u8) -> Vec<u8> {
+        let tones = subcarriers(bandwidth).count();
+        let values = (0..tones * nr as usize * nc as usize)
+            .flat_map(|i| {
+                let i = i as i32;
+                [-(i % 512), i % 511] // imaginary, real
+            })
+            .collect::<Vec<_>>();
+
+        let mut csi = vec![0u8; (values.len() * 10).div_ceil(8)];
+        for (i, v) in values.iter().enumerate() {
+            for bit in 0..10 {
+                if (*v as u32) >> bit & 1 != 0 {
+                    let n = i * 10 + bit;
+                    csi[n / 8] |= 1 << (n % 8);
+                }
+            }
+        }
+
+        let u16 = |x: u16| match order {
+            ByteOrder::Little => x.to_le_bytes(),
+            ByteOrder::Big => x.to_be_bytes(),
+        };
+        let mut b = match order {
+            ByteOrder::Little => 1234u64.to_le_bytes(),
+            ByteOrder::Big => 1234u64.to_be_bytes(),
+        }
+        .to_vec();
+        b.extend(u16(csi.len() as u16));
+        b.extend(u16(5180));
+        b.extend([
+            0,
+            0,
+            0x8c,
+            (bandwidth == Bandwidth::Bw40).into(),
+            tones as u8,
+        ]);
+        b.extend([nr, nc, 40, 38, 39, 0]);
+        b.extend(u16(3));
+        b.extend(csi);
+        b.extend([1, 2, 3]);
+
+        let mut rec = u16(b.len() as u16).to_vec();
+        rec.extend(b);
+        rec
+    }
+
+    #[test]
+    fn read_log() {
+        for (order, bandwidth) in [
+            (ByteOrder::Little, Bandwidth::Bw20),
+            (ByteOrder::Big, Bandwidth::Bw40),
+        ] {
+            let mut log = record(order, bandwidth, 3, 2);
+            log.extend(record(order, bandwidth, 1, 1));
+            log.extend(&record(order, bandwidth, 1, 1)[..30]);
+
+            let records = LogReader::new(&log[..], order)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(records.len(), 2);
+
+            let record = &records[0];
+            let tones = subcarriers(bandwidth).count();
+            assert_eq!(record.timestamp, 1234);
+            assert_eq!(record.channel, 5180);
+            assert_eq!(record.bandwidth, bandwidth);
+            assert_eq!(record.csi.dim(), (3, 2, tones));
+            assert_eq!(record.payload, [1, 2, 3]);
+            for tone in 0..tones {
+                for c in 0..2 {
+                    for r in 0..3 {
+                        let i = ((tone * 2 + c) * 3 + r) as f64;
+                        let z = Complex::new(i % 511., -(i % 512.));
+                        assert_eq!(record.csi[[r, c, tone]], z);
+                    }
+                }
+            }
+
+            let chan_spec = record.chan_spec().unwrap();
+            assert_eq!(chan_spec.control_channel(), 36);
+            let wifi_csi = record.to_wifi_csi(chan_spec).unwrap();
+            let n = bandwidth.nsub_pow2();
+            let grid = wifi_csi.get(2, 1).unwrap();
+            assert_eq!(grid.len(), n);
+            assert_eq!(grid[n / 2], Complex::new(0., 0.));
+            let lo = subcarriers(bandwidth).nth(tones / 2).unwrap() as usize;
+            assert_eq!(grid[n / 2 + lo], record.csi[[2, 1, tones / 2]]);
+            assert_eq!(grid[n / 2 - lo], record.csi[[2, 1, tones / 2 - 1]]);
+            assert_eq!(crate::proc::tof(&wifi_csi).len(), 3);
+        }
+    }
+
+    #[test]
+    fn chan_spec() {
+        let log = record(ByteOrder::Little, Bandwidth::Bw20, 1, 1);
+        let mut record = LogReader::new(&log[..], ByteOrder::Little)
+            .next()
+            .unwrap()
+            .unwrap();
+
+        for (freq, channel) in [
+            (2412, Some(1)),
+            (2472, Some(13)),
+            (2477, None),
+            (2484, Some(14)),
+            (5180, Some(36)),
+            (5825, Some(165)),
+            (6000, None),
+        ] {
+            record.channel = freq;
+            assert_eq!(
+                record.chan_spec().map(|cs| cs.control_channel()),
+                channel,
+                "{freq} MHz"
+            );
+        }
+    }
+}
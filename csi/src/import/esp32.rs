@@ -0,0 +1,513 @@
+//! Parser for the CSV lines printed by Espressif's
+//! [esp-csi](https://github.com/espressif/esp-csi) examples.
+//!
+//! Each line looks like
+//!
+//! ```text
+//! CSI_DATA,<id>,<mac>,<rssi>,<rate>,<sig_mode>,<mcs>,<bandwidth>,<smoothing>,<not_sounding>,<aggregation>,<stbc>,<fec_coding>,<sgi>,<noise_floor>,<ampdu_cnt>,<channel>,<secondary_channel>,<local_timestamp>,<ant>,<sig_len>,<rx_state>,<len>,<first_word>,"[<i8>,...]"
+//! ```
+//!
+//! where the data is a sequence of (imaginary, real) pairs. Which
+//! subcarriers are reported, and in which order, depends on the secondary
+//! channel and the kind of frame, as described in the
+//! [ESP-IDF documentation](https://docs.espressif.com/projects/esp-idf/en/stable/esp32/api-guides/wifi.html#wi-fi-channel-state-information).
+
+use std::{
+    io::{self, BufRead},
+    ops::RangeInclusive,
+    str::FromStr,
+    time::Duration,
+};
+
+use macaddr::MacAddr6;
+use ndarray::Array1;
+use num_complex::Complex;
+
+use crate::{ieee80211::Bandwidth, params::ChanSpec, proc::WifiCsi};
+
+/// Prefix of CSI lines.
+const PREFIX: &str = "CSI_DATA,";
+
+/// Number of comma-separated fields before the data.
+const NFIELDS: usize = 24;
+
+/// Error returned when parsing a CSI line.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Reading from the underlying reader failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The line does not start with `CSI_DATA`.
+    #[error("not a CSI_DATA line")]
+    NotCsi,
+    /// A field is missing or could not be parsed.
+    #[error("invalid {0} field")]
+    InvalidField(&'static str),
+    /// The data length does not match the `len` field.
+    #[error("expected {expected} values, got {actual}")]
+    LengthMismatch {
+        /// The `len` field.
+        expected: usize,
+        /// The number of values.
+        actual: usize,
+    },
+    /// The data length does not match any known subcarrier layout.
+    #[error("unknown subcarrier layout for {0} values")]
+    UnknownLayout(usize),
+}
+
+/// Position of the secondary channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecondaryChannel {
+    /// 20 MHz channel.
+    None,
+    /// The secondary channel is above the primary channel.
+    Above,
+    /// The secondary channel is below the primary channel.
+    Below,
+}
+
+/// A CSI line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// Sequence number assigned by the example.
+    pub id: u32,
+    /// Source MAC address.
+    pub mac: MacAddr6,
+    /// RSSI (dBm).
+    pub rssi: i8,
+    /// PHY rate (non-HT frames).
+    pub rate: u8,
+    /// 0 for non-HT (11b/g), 1 for HT (11n) and 3 for VHT frames.
+    pub sig_mode: u8,
+    /// Modulation coding scheme (HT frames).
+    pub mcs: u8,
+    /// Bandwidth of the frame.
+    pub bandwidth: Bandwidth,
+    /// Whether the frame was sent with space-time block coding.
+    pub stbc: bool,
+    /// Noise floor (dBm).
+    pub noise_floor: i8,
+    /// Primary channel.
+    pub channel: u8,
+    /// Secondary channel.
+    pub secondary_channel: SecondaryChannel,
+    /// Local timestamp (µs).
+    pub local_timestamp: u32,
+    /// Whether the first four bytes of [`Record::data`] are invalid, which
+    /// is a hardware limitation of the ESP32.
+    pub first_word_invalid: bool,
+    /// Raw data, as (imaginary, real) pairs.
+    pub data: Vec<i8>,
+}
+
+/// A long training field, as (subcarrier, value) pairs.
+pub type Field = Vec<(i8, Complex<f64>)>;
+
+/// Subcarrier indices of a long training field.
+type Segment = &'static [RangeInclusive<i8>];
+
+const FULL: Segment = &[0..=31, -32..=-1];
+const LOWER: Segment = &[0..=63];
+const UPPER: Segment = &[-64..=-1];
+const HT40: Segment = &[0..=63, -64..=-1];
+const HT40_STBC: Segment = &[0..=60, -60..=-1];
+
+impl Record {
+    /// Returns the subcarrier indices of the LLTF, HT-LTF and STBC-HT-LTF,
+    /// in the order they appear in [`Record::data`].
+    fn layout(&self) -> Option<[Segment; 3]> {
+        use SecondaryChannel::*;
+
+        let lltf = match self.secondary_channel {
+            None => FULL,
+            Below => LOWER,
+            Above => UPPER,
+        };
+        if self.sig_mode == 0 {
+            return Some([lltf, &[], &[]]);
+        }
+
+        let (ht_ltf, stbc): (Segment, Segment) =
+            match (self.secondary_channel, self.bandwidth, self.stbc) {
+                (None, Bandwidth::Bw20, _) => (FULL, FULL),
+                (Below, Bandwidth::Bw20, false) => (LOWER, &[]),
+                (Below, Bandwidth::Bw20, true) => (&[0..=62], &[0..=62]),
+                (Above, Bandwidth::Bw20, false) => (UPPER, &[]),
+                (Above, Bandwidth::Bw20, true) => (&[-62..=-1], &[-62..=-1]),
+                (Below | Above, Bandwidth::Bw40, false) => (HT40, &[]),
+                (Below | Above, Bandwidth::Bw40, true) => (HT40_STBC, HT40_STBC),
+                _ => return Option::None,
+            };
+
+        Some([lltf, ht_ltf, if self.stbc { stbc } else { &[] }])
+    }
+
+    /// Returns the reported long training fields (LLTF, HT-LTF and
+    /// STBC-HT-LTF).
+    ///
+    /// The data may end after any of the fields, depending on which ones
+    /// are enabled in `wifi_csi_config_t`. Subcarriers in the first word
+    /// are left out if [`Record::first_word_invalid`] is set.
+    pub fn fields(&self) -> Result<[Field; 3], Error> {
+        let layout = self.layout().ok_or(Error::UnknownLayout(self.data.len()))?;
+
+        let mut values = self
+            .data
+            .chunks_exact(2)
+            .map(|iq| Complex::new(iq[1].into(), iq[0].into()))
+            .enumerate()
+            .map(|(i, z)| (self.first_word_invalid && i < 2, z));
+
+        let mut fields: [Field; 3] = Default::default();
+        let mut len = 0;
+        for (field, segment) in fields.iter_mut().zip(layout) {
+            if len == self.data.len() {
+                break;
+            }
+            for k in segment.iter().flat_map(Clone::clone) {
+                let (invalid, z) = values.next().ok_or(Error::UnknownLayout(self.data.len()))?;
+                if !invalid {
+                    field.push((k, z));
+                }
+                len += 2;
+            }
+        }
+
+        if len != self.data.len() {
+            return Err(Error::UnknownLayout(self.data.len()));
+        }
+
+        Ok(fields)
+    }
+
+    /// Returns the chanspec of the channel the ESP32 was listening on.
+    pub fn chan_spec(&self) -> Option<ChanSpec> {
+        // #define WL_CHANSPEC_BAND_2G 0x0000, WL_CHANSPEC_BAND_5G 0xc000
+        let band = if self.channel <= 14 { 0x0000 } else { 0xc000 };
+        let (bw, center, sideband) = match self.secondary_channel {
+            SecondaryChannel::None => (0x1000, self.channel, 0),
+            SecondaryChannel::Above => (0x1800, self.channel.checked_add(2)?, 0),
+            SecondaryChannel::Below => (0x1800, self.channel.checked_sub(2)?, 1),
+        };
+        ChanSpec::try_from(band | bw | sideband << 8 | center as u16).ok()
+    }
+
+    /// Converts the record to a [`WifiCsi`] with a single core and spatial
+    /// stream.
+    ///
+    /// The HT-LTF is used if present, otherwise the LLTF. Subcarriers are
+    /// placed on a grid of [`Bandwidth::nsub_pow2`] subcarriers (according
+    /// to `chan_spec`) in the same order as
+    /// [`Frame::csi`](crate::frame::Frame::csi), with zeros in between.
+    pub fn to_wifi_csi(&self, chan_spec: ChanSpec) -> Result<WifiCsi, Error> {
+        let [lltf, ht_ltf, _] = self.fields()?;
+        let field = if ht_ltf.is_empty() { lltf } else { ht_ltf };
+
+        let n = chan_spec.bandwidth().nsub_pow2() as isize;
+        let mut grid = Array1::zeros(n as usize);
+        for (k, z) in field {
+            if let Some(v) = grid.get_mut((k as isize + n / 2) as usize) {
+                *v = z;
+            }
+        }
+
+        let mut wifi_csi = WifiCsi::new(chan_spec, self.rssi);
+        wifi_csi.insert(0, 0, grid);
+        Ok(wifi_csi)
+    }
+}
+
+impl FromStr for Record {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // serial logs may have other output before the prefix
+        let s = &s[s.find(PREFIX).ok_or(Error::NotCsi)? + PREFIX.len()..];
+        let (header, data) = s.split_once('[').ok_or(Error::InvalidField("data"))?;
+        let data = data
+            .trim_end()
+            .trim_end_matches('"')
+            .strip_suffix(']')
+            .ok_or(Error::InvalidField("data"))?;
+
+        let fields = header
+            .trim_end()
+            .trim_end_matches('"')
+            .split(',')
+            .map(str::trim)
+            .collect::<Vec<_>>();
+        // trailing comma before the data
+        let fields = match fields.split_last() {
+            Some((&"", rest)) => rest,
+            _ => &fields[..],
+        };
+        if fields.len() != NFIELDS - 1 {
+            return Err(Error::InvalidField("header"));
+        }
+
+        let mut fields = fields.iter();
+        let mut next = |name| fields.next().copied().ok_or(Error::InvalidField(name));
+        fn parse<T: FromStr>(s: &str, name: &'static str) -> Result<T, Error> {
+            s.parse().map_err(|_| Error::InvalidField(name))
+        }
+        macro_rules! field {
+            ($name:ident) => {
+                parse(next(stringify!($name))?, stringify!($name))?
+            };
+            ($name:ident: $t:ty) => {
+                parse::<$t>(next(stringify!($name))?, stringify!($name))?
+            };
+        }
+
+        let id = field!(id);
+        let mac = field!(mac);
+        let rssi = field!(rssi);
+        let rate = field!(rate);
+        let sig_mode = field!(sig_mode);
+        let mcs = field!(mcs);
+        let bandwidth = match field!(bandwidth: u8) {
+            0 => Bandwidth::Bw20,
+            1 => Bandwidth::Bw40,
+            _ => return Err(Error::InvalidField("bandwidth")),
+        };
+        let _smoothing = next("smoothing")?;
+        let _not_sounding = next("not_sounding")?;
+        let _aggregation = next("aggregation")?;
+        let stbc = field!(stbc: u8) != 0;
+        let _fec_coding = next("fec_coding")?;
+        let _sgi = next("sgi")?;
+        let noise_floor = field!(noise_floor);
+        let _ampdu_cnt = next("ampdu_cnt")?;
+        let channel = field!(channel);
+        let secondary_channel = match field!(secondary_channel: u8) {
+            0 => SecondaryChannel::None,
+            1 => SecondaryChannel::Above,
+            2 => SecondaryChannel::Below,
+            _ => return Err(Error::InvalidField("secondary_channel")),
+        };
+        let local_timestamp = field!(local_timestamp);
+        let _ant = next("ant")?;
+        let _sig_len = next("sig_len")?;
+        let _rx_state = next("rx_state")?;
+        let len = field!(len: usize);
+        let first_word_invalid = field!(first_word: u8) != 0;
+
+        let data = data
+            .split([',', ' '])
+            .filter(|s| !s.is_empty())
+            .map(|s| parse(s, "data"))
+            .collect::<Result<Vec<i8>, _>>()?;
+        if data.len() != len {
+            return Err(Error::LengthMismatch {
+                expected: len,
+                actual: data.len(),
+            });
+        }
+
+        Ok(Self {
+            id,
+            mac,
+            rssi,
+            rate,
+            sig_mode,
+            mcs,
+            bandwidth,
+            stbc,
+            noise_floor,
+            channel,
+            secondary_channel,
+            local_timestamp,
+            first_word_invalid,
+            data,
+        })
+    }
+}
+
+/// Extends [`Record::local_timestamp`] past its wrap-around.
+///
+/// The timestamp is a 32-bit microsecond counter, so it wraps around about
+/// every 71.6 minutes. Consecutive timestamps are assumed to be less than
+/// half of that apart, which also lets slightly out-of-order records step
+/// back in time instead of being taken for a wrap-around.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// let mut clock = csi::import::esp32::LocalClock::default();
+/// assert_eq!(clock.time(u32::MAX), Duration::from_micros(u32::MAX.into()));
+/// assert_eq!(clock.time(9), Duration::from_micros(u32::MAX as u64 + 10));
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalClock {
+    /// The last timestamp and its extended value.
+    last: Option<(u32, u64)>,
+}
+
+impl LocalClock {
+    /// Returns the extended value of the next timestamp, in microseconds
+    /// since the counter was last zero before the first timestamp.
+    pub fn time(&mut self, local_timestamp: u32) -> Duration {
+        let time = match self.last {
+            Some((last, time)) => {
+                time.saturating_add_signed(local_timestamp.wrapping_sub(last) as i32 as i64)
+            }
+            None => local_timestamp.into(),
+        };
+        self.last = Some((local_timestamp, time));
+        Duration::from_micros(time)
+    }
+}
+
+/// Reads CSI lines from a log, skipping all other lines.
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use csi::import::esp32::CsvReader;
+///
+/// let file = std::fs::File::open("esp32.csv")?;
+/// for record in CsvReader::new(std::io::BufReader::new(file)) {
+///     let record = record?;
+///     if let Some(chan_spec) = record.chan_spec() {
+///         let csi = record.to_wifi_csi(chan_spec)?;
+///         println!("{:?}", csi.get(0, 0));
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct CsvReader<R> {
+    reader: R,
+    line: String,
+}
+
+impl<R: BufRead> CsvReader<R> {
+    /// Creates a new reader.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::new(),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for CsvReader<R> {
+    type Item = Result<Record, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => match self.line.parse() {
+                    Err(Error::NotCsi) => continue,
+                    r => return Some(r),
+                },
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(secondary: u8, sig_mode: u8, bandwidth: u8, first_word: u8, len: usize) -> String {
+        let data = (0..len)
+            .map(|i| {
+                let v = (i / 2 % 100) as i8;
+                if i % 2 == 0 { -v } else { v }.to_string()
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            "I (1234) csi: CSI_DATA,7,aa:bb:cc:dd:ee:ff,-42,11,{sig_mode},7,{bandwidth},1,1,0,0,0,0,-95,0,6,{secondary},123456,0,76,0,{len},{first_word},\"[{data}]\"\n"
+        )
+    }
+
+    #[test]
+    fn read_csv() {
+        let log = [
+            "type,id,mac,rssi,rate,sig_mode,mcs,bandwidth,smoothing,not_sounding,aggregation,stbc,fec_coding,sgi,noise_floor,ampdu_cnt,channel,secondary_channel,local_timestamp,ant,sig_len,rx_state,len,first_word,data\n".to_owned(),
+            line(0, 0, 0, 0, 128),
+            "I (1235) wifi: some other log line\n".to_owned(),
+            line(1, 1, 1, 1, 384),
+        ]
+        .concat();
+
+        let records = CsvReader::new(log.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 2);
+
+        let record = &records[0];
+        assert_eq!(record.id, 7);
+        assert_eq!(
+            record.mac,
+            MacAddr6::new(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff)
+        );
+        assert_eq!(record.rssi, -42);
+        assert_eq!(record.noise_floor, -95);
+        assert_eq!(record.local_timestamp, 123456);
+        let [lltf, ht_ltf, stbc] = record.fields().unwrap();
+        assert_eq!(lltf.len(), 64);
+        assert!(ht_ltf.is_empty() && stbc.is_empty());
+        assert_eq!(lltf[1], (1, Complex::new(1., -1.)));
+        assert_eq!(lltf[32], (-32, Complex::new(32., -32.)));
+
+        let chan_spec = record.chan_spec().unwrap();
+        assert_eq!(chan_spec.control_channel(), 6);
+        let csi = record.to_wifi_csi(chan_spec).unwrap();
+        let grid = csi.get(0, 0).unwrap();
+        assert_eq!(grid.len(), 64);
+        assert_eq!(grid[32 + 1], Complex::new(1., -1.));
+        assert_eq!(grid[0], Complex::new(32., -32.));
+
+        let record = &records[1];
+        assert_eq!(record.secondary_channel, SecondaryChannel::Above);
+        let [lltf, ht_ltf, _] = record.fields().unwrap();
+        assert_eq!(lltf.len(), 62);
+        assert_eq!(lltf[0], (-62, Complex::new(2., -2.)));
+        assert_eq!(ht_ltf.len(), 128);
+        assert_eq!(ht_ltf[0], (0, Complex::new(64., -64.)));
+        assert_eq!(ht_ltf[64], (-64, Complex::new(28., -28.)));
+
+        let chan_spec = record.chan_spec().unwrap();
+        assert_eq!(chan_spec.control_channel(), 6);
+        assert_eq!(chan_spec.bandwidth(), Bandwidth::Bw40);
+        let csi = record.to_wifi_csi(chan_spec).unwrap();
+        let grid = csi.get(0, 0).unwrap();
+        assert_eq!(grid.len(), 128);
+        assert_eq!(grid[0], Complex::new(28., -28.));
+        assert_eq!(grid[64 + 5], Complex::new(69., -69.));
+    }
+
+    #[test]
+    fn local_clock() {
+        let mut clock = LocalClock::default();
+        let start = u32::MAX - 1_000_000;
+        let times: Vec<_> = [start, u32::MAX, 500_000, 400_000, 2_000_000]
+            .into_iter()
+            .map(|ts| clock.time(ts).as_micros() as u64 - start as u64)
+            .collect();
+        assert_eq!(times, [0, 1_000_000, 1_500_001, 1_400_001, 3_000_001]);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(matches!("hello".parse::<Record>(), Err(Error::NotCsi)));
+        assert!(matches!(
+            line(0, 0, 0, 0, 128)
+                .replace(",128,", ",130,")
+                .parse::<Record>(),
+            Err(Error::LengthMismatch {
+                expected: 130,
+                actual: 128
+            })
+        ));
+        let record = line(0, 0, 0, 0, 100).parse::<Record>().unwrap();
+        assert!(matches!(record.fields(), Err(Error::UnknownLayout(100))));
+    }
+}
@@ -0,0 +1,391 @@
+//! Reader for the [Linux 802.11n CSI Tool](https://dhalperi.github.io/linux-80211n-csitool/)
+//! (Intel 5300).
+//!
+//! `log_to_file` writes a sequence of records, each prefixed by a
+//! big-endian `u16` length and a one-byte code. Records with code `0xbb`
+//! contain beamforming feedback (bfee) and are parsed like
+//! [`read_bfee.c`](https://github.com/dhalperi/linux-80211n-csitool-supplementary/blob/master/matlab/read_bfee.c);
+//! all other records are skipped.
+
+use std::io::{self, Read};
+
+use ndarray::{s, Array1, Array3};
+use num_complex::Complex;
+
+use crate::{ieee80211::Bandwidth, params::ChanSpec, proc::WifiCsi};
+
+/// Code of beamforming feedback records.
+const BFEE_CODE: u8 = 0xbb;
+
+/// Number of (grouped) subcarriers in a bfee record.
+pub const NSUB: usize = 30;
+
+/// Subcarrier indices of the grouped subcarriers at 20 MHz.
+pub const SUBCARRIERS_20MHZ: [i8; NSUB] = [
+    -28, -26, -24, -22, -20, -18, -16, -14, -12, -10, -8, -6, -4, -2, -1, 1, 3, 5, 7, 9, 11, 13,
+    15, 17, 19, 21, 23, 25, 27, 28,
+];
+
+/// Subcarrier indices of the grouped subcarriers at 40 MHz.
+pub const SUBCARRIERS_40MHZ: [i8; NSUB] = [
+    -58, -54, -50, -46, -42, -38, -34, -30, -26, -22, -18, -14, -10, -6, -2, 2, 6, 10, 14, 18, 22,
+    26, 30, 34, 38, 42, 46, 50, 54, 58,
+];
+
+/// Error returned when reading a bfee record.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Reading from the underlying reader failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The record ends before its header does.
+    #[error("truncated bfee record")]
+    Truncated,
+    /// The CSI length does not match `Nrx` and `Ntx`.
+    #[error("wrong CSI length (expected {expected}, got {actual})")]
+    InvalidLength {
+        /// Length given by `Nrx` and `Ntx`.
+        expected: usize,
+        /// Length in the record.
+        actual: usize,
+    },
+    /// `Nrx` or `Ntx` is not between 1 and 3.
+    #[error("invalid antenna count (Nrx = {nrx}, Ntx = {ntx})")]
+    InvalidAntennas {
+        /// Number of receive antennas.
+        nrx: u8,
+        /// Number of transmit antennas.
+        ntx: u8,
+    },
+}
+
+/// A beamforming feedback record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bfee {
+    /// Low 32 bits of the NIC's 1 MHz clock.
+    pub timestamp_low: u32,
+    /// Number of bfee records the driver has reported.
+    pub bfee_count: u16,
+    /// Number of receive antennas.
+    pub nrx: u8,
+    /// Number of transmit antennas (spatial streams).
+    pub ntx: u8,
+    /// RSSI of receive chains A, B and C (dB relative to the AGC).
+    pub rssi: [u8; 3],
+    /// Noise floor (dBm), or -127 if unknown.
+    pub noise: i8,
+    /// Automatic gain control setting (dB).
+    pub agc: u8,
+    /// Receive chain of each receive antenna.
+    pub perm: [u8; 3],
+    /// Rate and flags of the frame.
+    pub rate: u16,
+    /// Unscaled CSI, indexed by transmit antenna, receive chain (already
+    /// permuted) and subcarrier.
+    pub csi: Array3<Complex<f64>>,
+}
+
+impl Bfee {
+    /// Parses the payload of a bfee record, i.e. everything after the code.
+    pub fn from_slice(b: &[u8]) -> Result<Self, Error> {
+        if b.len() < 20 {
+            return Err(Error::Truncated);
+        }
+
+        let nrx = b[8];
+        let ntx = b[9];
+        if !(1..=3).contains(&nrx) || !(1..=3).contains(&ntx) {
+            return Err(Error::InvalidAntennas { nrx, ntx });
+        }
+
+        let len = u16::from_le_bytes([b[16], b[17]]) as usize;
+        let expected = (NSUB * (nrx as usize * ntx as usize * 8 * 2 + 3)).div_ceil(8);
+        let payload = &b[20..];
+        if len != expected || payload.len() < len {
+            return Err(Error::InvalidLength {
+                expected,
+                actual: len.min(payload.len()),
+            });
+        }
+
+        let antenna_sel = b[15];
+        let perm = [antenna_sel & 3, antenna_sel >> 2 & 3, antenna_sel >> 4 & 3];
+        // only apply the permutation if it is one
+        let permute = {
+            let mut seen = [false; 3];
+            perm[..nrx as usize]
+                .iter()
+                .all(|&p| p < nrx && !std::mem::replace(&mut seen[p as usize], true))
+        };
+
+        // values are 8-bit and packed at arbitrary bit offsets
+        let byte_at = |bit: usize| {
+            let lo = payload.get(bit / 8).copied().unwrap_or(0) as u16;
+            let hi = payload.get(bit / 8 + 1).copied().unwrap_or(0) as u16;
+            ((lo | hi << 8) >> (bit % 8)) as u8 as i8
+        };
+
+        let mut csi = Array3::zeros((ntx as usize, nrx as usize, NSUB));
+        let mut index = 0;
+        for sub in 0..NSUB {
+            index += 3;
+            for j in 0..nrx as usize * ntx as usize {
+                let (rx, tx) = (j / ntx as usize, j % ntx as usize);
+                let rx = if permute { perm[rx] as usize } else { rx };
+                csi[[tx, rx, sub]] = Complex::new(byte_at(index) as f64, byte_at(index + 8) as f64);
+                index += 16;
+            }
+        }
+
+        Ok(Self {
+            timestamp_low: u32::from_le_bytes(b[0..4].try_into().unwrap()),
+            bfee_count: u16::from_le_bytes([b[4], b[5]]),
+            nrx,
+            ntx,
+            rssi: [b[10], b[11], b[12]],
+            noise: b[13] as i8,
+            agc: b[14],
+            perm,
+            rate: u16::from_le_bytes([b[18], b[19]]),
+            csi,
+        })
+    }
+
+    /// Returns the bandwidth of the frame, according to [`Bfee::rate`].
+    pub fn bandwidth(&self) -> Bandwidth {
+        if self.rate & 0x800 != 0 {
+            Bandwidth::Bw40
+        } else {
+            Bandwidth::Bw20
+        }
+    }
+
+    /// Returns the subcarrier indices of the CSI.
+    pub fn subcarriers(&self) -> &'static [i8; NSUB] {
+        match self.bandwidth() {
+            Bandwidth::Bw40 => &SUBCARRIERS_40MHZ,
+            _ => &SUBCARRIERS_20MHZ,
+        }
+    }
+
+    /// Returns the total received signal strength (dBm), like
+    /// `get_total_rss.m`.
+    pub fn total_rss(&self) -> f64 {
+        let mag = self
+            .rssi
+            .iter()
+            .filter(|&&rssi| rssi != 0)
+            .map(|&rssi| 10f64.powf(rssi as f64 / 10.))
+            .sum::<f64>();
+        10. * mag.log10() - 44. - self.agc as f64
+    }
+
+    /// Converts the record to a [`WifiCsi`], with receive antennas as cores
+    /// and transmit antennas as spatial streams.
+    ///
+    /// The 30 subcarriers are placed on a grid of
+    /// [`Bandwidth::nsub_pow2`] subcarriers in the same order as
+    /// [`Frame::csi`](crate::frame::Frame::csi), with zeros in between.
+    /// Returns `None` if the bandwidth of `chan_spec` does not match
+    /// [`Bfee::bandwidth`].
+    pub fn to_wifi_csi(&self, chan_spec: ChanSpec) -> Option<WifiCsi> {
+        if chan_spec.bandwidth() != self.bandwidth() {
+            return None;
+        }
+
+        let n = chan_spec.bandwidth().nsub_pow2();
+        let rssi = self
+            .total_rss()
+            .round()
+            .clamp(i8::MIN.into(), i8::MAX.into()) as i8;
+        let mut wifi_csi = WifiCsi::new(chan_spec, rssi);
+
+        for tx in 0..self.ntx as usize {
+            for rx in 0..self.nrx as usize {
+                let mut grid = Array1::zeros(n);
+                for (&k, &z) in self
+                    .subcarriers()
+                    .iter()
+                    .zip(self.csi.slice(s![tx, rx, ..]))
+                {
+                    grid[(k as isize + n as isize / 2) as usize] = z;
+                }
+                wifi_csi.insert(rx, tx, grid);
+            }
+        }
+
+        Some(wifi_csi)
+    }
+}
+
+/// Reads bfee records from a `log_to_file` stream.
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use csi::import::intel::DatReader;
+///
+/// let file = std::fs::File::open("csi.dat")?;
+/// for bfee in DatReader::new(std::io::BufReader::new(file)) {
+///     let bfee = bfee?;
+///     println!("{} {:?}", bfee.timestamp_low, bfee.csi.dim());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct DatReader<R> {
+    reader: R,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> DatReader<R> {
+    /// Creates a new reader.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Reads the next bfee record, or `None` at the end of the stream.
+    pub fn read_bfee(&mut self) -> Result<Option<Bfee>, Error> {
+        loop {
+            let mut header = [0; 3];
+            match self.reader.read_exact(&mut header[..1]) {
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                r => r?,
+            }
+            self.reader.read_exact(&mut header[1..])?;
+
+            let field_len = u16::from_be_bytes([header[0], header[1]]) as usize;
+            self.buf.resize(field_len.saturating_sub(1), 0);
+            self.reader.read_exact(&mut self.buf)?;
+
+            if header[2] == BFEE_CODE {
+                return Bfee::from_slice(&self.buf).map(Some);
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for DatReader<R> {
+    type Item = Result<Bfee, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_bfee().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ieee80211::Band;
+
+    use super::*;
+
+    /// Packs a bfee record like the driver does.
+    fn record(
+        nrx: u8,
+        ntx: u8,
+        antenna_sel: u8,
+        rate: u16,
+        csi: impl Fn(usize, usize) -> (i8, i8),
+    ) -> Vec<u8> {
+        let len = (NSUB * (nrx as usize * ntx as usize * 16 + 3)).div_ceil(8);
+        let mut b = vec![0; 20 + len];
+        b[0..4].copy_from_slice(&123456u32.to_le_bytes());
+        b[4..6].copy_from_slice(&7u16.to_le_bytes());
+        b[8] = nrx;
+        b[9] = ntx;
+        b[10..13].copy_from_slice(&[40, 38, 0]);
+        b[13] = -92i8 as u8;
+        b[14] = 30;
+        b[15] = antenna_sel;
+        b[16..18].copy_from_slice(&(len as u16).to_le_bytes());
+        b[18..20].copy_from_slice(&rate.to_le_bytes());
+
+        let payload = &mut b[20..];
+        let mut put = |bit: usize, v: i8| {
+            for i in 0..8 {
+                if (v as u8) >> i & 1 != 0 {
+                    payload[(bit + i) / 8] |= 1 << ((bit + i) % 8);
+                }
+            }
+        };
+        let mut index = 0;
+        for sub in 0..NSUB {
+            index += 3;
+            for j in 0..(nrx * ntx) as usize {
+                let (re, im) = csi(sub, j);
+                put(index, re);
+                put(index + 8, im);
+                index += 16;
+            }
+        }
+
+        let mut rec = ((b.len() + 1) as u16).to_be_bytes().to_vec();
+        rec.push(BFEE_CODE);
+        rec.extend(b);
+        rec
+    }
+
+    #[test]
+    fn read_dat() {
+        let value = |sub: usize, j: usize| (sub as i8 - 15, -(j as i8) * 10);
+
+        // a non-bfee record, then antennas C, A, B
+        let mut dat = vec![0, 3, 0xc1, 1, 2];
+        dat.extend(record(3, 2, 0b01_00_10, 0x4101, value));
+
+        let bfees = DatReader::new(&dat[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(bfees.len(), 1);
+        let bfee = &bfees[0];
+
+        assert_eq!(bfee.timestamp_low, 123456);
+        assert_eq!(bfee.bfee_count, 7);
+        assert_eq!((bfee.nrx, bfee.ntx), (3, 2));
+        assert_eq!(bfee.noise, -92);
+        assert_eq!(bfee.perm, [2, 0, 1]);
+        assert_eq!(bfee.bandwidth(), Bandwidth::Bw20);
+        assert_eq!(bfee.csi.dim(), (2, 3, NSUB));
+        for sub in 0..NSUB {
+            for j in 0..6 {
+                let (re, im) = value(sub, j);
+                let (rx, tx) = (j / 2, j % 2);
+                assert_eq!(
+                    bfee.csi[[tx, bfee.perm[rx] as usize, sub]],
+                    Complex::new(re.into(), im.into())
+                );
+            }
+        }
+        assert!((bfee.total_rss() - (10. * (1e4 + 10f64.powf(3.8)).log10() - 74.)).abs() < 1e-9);
+
+        let chan_spec = ChanSpec::new(6, Band::Band2G, Bandwidth::Bw20).unwrap();
+        let wifi_csi = bfee.to_wifi_csi(chan_spec).unwrap();
+        let grid = wifi_csi.get(2, 1).unwrap();
+        assert_eq!(grid.len(), 64);
+        assert_eq!(grid[32 - 28], bfee.csi[[1, 2, 0]]);
+        assert_eq!(grid[32 + 28], bfee.csi[[1, 2, NSUB - 1]]);
+        assert_eq!(grid[32], Complex::new(0., 0.));
+        assert!(wifi_csi.get(3, 0).is_none());
+
+        let chan_spec = ChanSpec::new(36, Band::Band5G, Bandwidth::Bw40).unwrap();
+        assert!(bfee.to_wifi_csi(chan_spec).is_none());
+    }
+
+    #[test]
+    fn invalid() {
+        let mut rec = record(1, 1, 0, 0, |_, _| (0, 0));
+        rec[3 + 16] += 1;
+        assert!(matches!(
+            DatReader::new(&rec[..]).next(),
+            Some(Err(Error::InvalidLength { .. }))
+        ));
+
+        assert!(matches!(
+            DatReader::new(&rec[..10]).next(),
+            Some(Err(Error::Io(_)))
+        ));
+    }
+}
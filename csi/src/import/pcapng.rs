@@ -0,0 +1,659 @@
+//! Reader for [pcapng](https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-02.html)
+//! captures, the default format of Wireshark and recent versions of
+//! tcpdump.
+//!
+//! A file is a sequence of blocks, each starting with a `u32` block type and
+//! a `u32` total length, which is repeated at the end of the block. The byte
+//! order is that of the Section Header Block that starts each section. The
+//! Section Header, Interface Description and Enhanced Packet Blocks are
+//! decoded, including their comments; all other blocks are skipped.
+//!
+//! Packet timestamps are converted using the interface's `if_tsresol` and
+//! `if_tsoffset` options, so nanosecond captures keep their resolution.
+//! Use [`Frame::from_slice`](crate::frame::Frame::from_slice) on the packet
+//! data of Nexmon captures, after checking that the packet's interface has
+//! the [`LINKTYPE_ETHERNET`] link type.
+
+use std::{
+    io::{self, Read},
+    time::Duration,
+};
+
+/// Type of the Section Header Block, which is the same in either byte order.
+///
+/// Files start with it, so it doubles as the magic number of pcapng.
+pub const SECTION_HEADER: u32 = 0x0a0d0d0a;
+const INTERFACE_DESCRIPTION: u32 = 1;
+const ENHANCED_PACKET: u32 = 6;
+
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+
+// options
+const OPT_END: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const IF_NAME: u16 = 2;
+const IF_DESCRIPTION: u16 = 3;
+const IF_TSRESOL: u16 = 9;
+const IF_TSOFFSET: u16 = 14;
+
+/// Length of the start of a block needed by [`Parser::block_len`].
+pub const BLOCK_HEAD_LEN: usize = 12;
+
+/// Packet blocks written by libpcap hold at most 262144 bytes of packet data
+/// (its `MAXIMUM_SNAPLEN`). Other blocks, such as name resolution blocks,
+/// have no such limit, so only blocks longer than 16 MiB are assumed to be a
+/// corrupt file.
+const MAX_BLOCK_LEN: u32 = 1 << 24;
+
+/// [`Interface::link_type`] of Ethernet, the link type of Nexmon captures.
+pub const LINKTYPE_ETHERNET: u16 = 1;
+
+/// Error returned when reading a pcapng file.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Reading from the underlying reader failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// A block is truncated or its lengths are inconsistent.
+    #[error("truncated block")]
+    Truncated,
+    /// The length of a block is too large for a valid block.
+    #[error("block of {0} bytes is too long")]
+    TooLong(u32),
+    /// The file does not start with a Section Header Block.
+    #[error("missing section header block")]
+    NoSection,
+    /// A Section Header Block has an invalid byte-order magic.
+    #[error("invalid byte-order magic {0:#010x}")]
+    InvalidByteOrder(u32),
+    /// A section has an unsupported major version.
+    #[error("unsupported pcapng version {0}")]
+    UnsupportedVersion(u16),
+    /// A packet refers to an interface that has not been described.
+    #[error("unknown interface {0}")]
+    UnknownInterface(u32),
+    /// An interface has a timestamp resolution finer than supported.
+    #[error("invalid timestamp resolution {0:#04x}")]
+    InvalidResolution(u8),
+}
+
+/// An interface that packets were captured on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interface {
+    /// Link-layer header type, such as 1 for Ethernet.
+    pub link_type: u16,
+    /// Maximum number of captured bytes per packet, or 0 if unlimited.
+    pub snap_len: u32,
+    /// Name of the interface, such as `eth6`.
+    pub name: Option<String>,
+    /// Description of the interface.
+    pub description: Option<String>,
+    /// Timestamp resolution (`if_tsresol`): if the most significant bit is
+    /// clear, a tick is 10<sup>-n</sup> s, otherwise 2<sup>-n</sup> s
+    /// where `n` is the remaining bits.
+    pub ts_resolution: u8,
+    /// Offset (s) added to every timestamp (`if_tsoffset`).
+    pub ts_offset: i64,
+    /// Comments.
+    pub comments: Vec<String>,
+}
+
+impl Interface {
+    /// Converts a timestamp in ticks to the time since the Unix epoch.
+    pub fn timestamp(&self, ticks: u64) -> Result<Duration, Error> {
+        let n = u32::from(self.ts_resolution & 0x7f);
+        let per_sec = if self.ts_resolution & 0x80 == 0 {
+            10u128.checked_pow(n)
+        } else {
+            1u128.checked_shl(n)
+        }
+        .ok_or(Error::InvalidResolution(self.ts_resolution))?;
+
+        let ticks = u128::from(ticks);
+        let t = Duration::new(
+            (ticks / per_sec) as u64,
+            (ticks % per_sec * 1_000_000_000 / per_sec) as u32,
+        );
+        let offset = Duration::from_secs(self.ts_offset.unsigned_abs());
+        Ok(if self.ts_offset < 0 {
+            t.saturating_sub(offset)
+        } else {
+            t.saturating_add(offset)
+        })
+    }
+}
+
+/// A captured packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    /// Index of the interface in the current section.
+    pub interface: u32,
+    /// Capture time, relative to the Unix epoch.
+    pub timestamp: Duration,
+    /// Length of the packet on the wire, which may be larger than
+    /// `data.len()`.
+    pub orig_len: u32,
+    /// Captured bytes.
+    pub data: Vec<u8>,
+    /// Comments.
+    pub comments: Vec<String>,
+}
+
+/// A decoded block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Block {
+    /// Start of a new section, which resets the interfaces.
+    SectionHeader {
+        /// Comments, such as a description of the experiment.
+        comments: Vec<String>,
+    },
+    /// Interface Description Block.
+    Interface(Interface),
+    /// Enhanced Packet Block.
+    Packet(Packet),
+    /// A block of another type.
+    Other(u32),
+}
+
+/// Reads values in the byte order of the current section.
+struct Reader<'a> {
+    b: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if n > self.b.len() {
+            return Err(Error::Truncated);
+        }
+        let (b, rest) = self.b.split_at(n);
+        self.b = rest;
+        Ok(b)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut b: [u8; N] = self.take(N)?.try_into().unwrap();
+        if self.big_endian {
+            b.reverse();
+        }
+        Ok(b)
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn i64(&mut self) -> Result<i64, Error> {
+        self.array().map(i64::from_le_bytes)
+    }
+
+    /// Reads `n` bytes followed by padding to a multiple of 4.
+    fn padded(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        let b = self.take(n)?;
+        self.take(n.next_multiple_of(4) - n)?;
+        Ok(b)
+    }
+
+    /// Reads options until the end of the block, calling `f` with the code
+    /// and value of each option other than comments, which are returned.
+    fn options(&mut self, mut f: impl FnMut(u16, &[u8], bool)) -> Result<Vec<String>, Error> {
+        let mut comments = vec![];
+        while !self.b.is_empty() {
+            let code = self.u16()?;
+            let len = self.u16()?;
+            let value = self.padded(len.into())?;
+            match code {
+                OPT_END => break,
+                OPT_COMMENT => comments.push(String::from_utf8_lossy(value).into_owned()),
+                _ => f(code, value, self.big_endian),
+            }
+        }
+        Ok(comments)
+    }
+}
+
+/// Decodes blocks, keeping track of the byte order and interfaces of the
+/// current section.
+#[derive(Debug, Clone, Default)]
+pub struct Parser {
+    /// Byte order of the current section, or `None` before the first one.
+    big_endian: Option<bool>,
+    interfaces: Vec<Interface>,
+}
+
+impl Parser {
+    /// Creates a parser.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the interfaces of the current section.
+    pub fn interfaces(&self) -> &[Interface] {
+        &self.interfaces
+    }
+
+    /// Returns the total length of a block given its first
+    /// [`BLOCK_HEAD_LEN`] bytes.
+    pub fn block_len(&self, head: &[u8; BLOCK_HEAD_LEN]) -> Result<usize, Error> {
+        // the type of a section header block reads the same in either order
+        let big_endian = if head[..4] == SECTION_HEADER.to_le_bytes() {
+            Self::byte_order(head)?
+        } else {
+            self.big_endian.ok_or(Error::NoSection)?
+        };
+        let len = Reader {
+            b: &head[4..8],
+            big_endian,
+        }
+        .u32()?;
+
+        if len > MAX_BLOCK_LEN {
+            return Err(Error::TooLong(len));
+        }
+        let len = len as usize;
+        if len < BLOCK_HEAD_LEN || !len.is_multiple_of(4) {
+            return Err(Error::Truncated);
+        }
+        Ok(len)
+    }
+
+    /// Prepares `buf` for a block given its first [`BLOCK_HEAD_LEN`] bytes,
+    /// returning the part of `buf` to read the rest of the block into. The
+    /// block can then be passed to [`Parser::parse`].
+    pub fn start_block<'a>(
+        &self,
+        head: &[u8; BLOCK_HEAD_LEN],
+        buf: &'a mut Vec<u8>,
+    ) -> Result<&'a mut [u8], Error> {
+        let len = self.block_len(head)?;
+        buf.clear();
+        buf.extend(head);
+        buf.resize(len, 0);
+        Ok(&mut buf[BLOCK_HEAD_LEN..])
+    }
+
+    fn byte_order(head: &[u8]) -> Result<bool, Error> {
+        let magic = u32::from_le_bytes(head[8..12].try_into().unwrap());
+        match magic {
+            BYTE_ORDER_MAGIC => Ok(false),
+            _ if magic.swap_bytes() == BYTE_ORDER_MAGIC => Ok(true),
+            _ => Err(Error::InvalidByteOrder(magic)),
+        }
+    }
+
+    /// Decodes a complete block.
+    pub fn parse(&mut self, b: &[u8]) -> Result<Block, Error> {
+        let head: &[u8; BLOCK_HEAD_LEN] = b
+            .get(..BLOCK_HEAD_LEN)
+            .ok_or(Error::Truncated)?
+            .try_into()
+            .unwrap();
+        let len = self.block_len(head)?;
+        if b.len() != len {
+            return Err(Error::Truncated);
+        }
+
+        if b[..4] == SECTION_HEADER.to_le_bytes() {
+            self.big_endian = Some(Self::byte_order(head)?);
+            self.interfaces.clear();
+        }
+
+        let big_endian = self.big_endian.unwrap();
+        let mut trailer = Reader {
+            b: &b[len - 4..],
+            big_endian,
+        };
+        if trailer.u32()? as usize != len {
+            return Err(Error::Truncated);
+        }
+
+        let mut r = Reader {
+            b: &b[..len - 4],
+            big_endian,
+        };
+        let block_type = r.u32()?;
+        let _len = r.u32()?;
+
+        match block_type {
+            SECTION_HEADER => {
+                let _magic = r.u32()?;
+                let major = r.u16()?;
+                let _minor = r.u16()?;
+                let _section_len = r.take(8)?;
+                if major != 1 {
+                    return Err(Error::UnsupportedVersion(major));
+                }
+
+                let comments = r.options(|_, _, _| {})?;
+                Ok(Block::SectionHeader { comments })
+            }
+            INTERFACE_DESCRIPTION => {
+                let link_type = r.u16()?;
+                let _reserved = r.u16()?;
+                let snap_len = r.u32()?;
+
+                let mut interface = Interface {
+                    link_type,
+                    snap_len,
+                    name: None,
+                    description: None,
+                    ts_resolution: 6,
+                    ts_offset: 0,
+                    comments: vec![],
+                };
+                interface.comments = r.options(|code, value, big_endian| match code {
+                    IF_NAME => interface.name = Some(String::from_utf8_lossy(value).into_owned()),
+                    IF_DESCRIPTION => {
+                        interface.description = Some(String::from_utf8_lossy(value).into_owned())
+                    }
+                    IF_TSRESOL if value.len() == 1 => interface.ts_resolution = value[0],
+                    IF_TSOFFSET if value.len() == 8 => {
+                        interface.ts_offset = Reader {
+                            b: value,
+                            big_endian,
+                        }
+                        .i64()
+                        .unwrap()
+                    }
+                    _ => {}
+                })?;
+
+                self.interfaces.push(interface.clone());
+                Ok(Block::Interface(interface))
+            }
+            ENHANCED_PACKET => {
+                let id = r.u32()?;
+                let ticks = u64::from(r.u32()?) << 32 | u64::from(r.u32()?);
+                let cap_len = r.u32()?;
+                let orig_len = r.u32()?;
+                let data = r.padded(cap_len as usize)?.to_vec();
+                let comments = r.options(|_, _, _| {})?;
+
+                let interface = self
+                    .interfaces
+                    .get(id as usize)
+                    .ok_or(Error::UnknownInterface(id))?;
+
+                Ok(Block::Packet(Packet {
+                    interface: id,
+                    timestamp: interface.timestamp(ticks)?,
+                    orig_len,
+                    data,
+                    comments,
+                }))
+            }
+            _ => Ok(Block::Other(block_type)),
+        }
+    }
+}
+
+/// Reads the blocks of a pcapng file.
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use csi::{
+///     frame::Frame,
+///     import::pcapng::{Block, BlockReader, LINKTYPE_ETHERNET},
+/// };
+///
+/// let file = std::io::BufReader::new(std::fs::File::open("capture.pcapng")?);
+/// let mut reader = BlockReader::new(file);
+/// while let Some(block) = reader.read_block()? {
+///     if let Block::Packet(packet) = block {
+///         let interface = &reader.parser().interfaces()[packet.interface as usize];
+///         if interface.link_type != LINKTYPE_ETHERNET {
+///             continue;
+///         }
+///         let frame: Frame = Frame::from_slice(&packet.data)?;
+///         println!("{:?}: {}", packet.timestamp, frame.seq_cnt);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct BlockReader<R> {
+    reader: R,
+    parser: Parser,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> BlockReader<R> {
+    /// Creates a new reader.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            parser: Parser::new(),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Returns the parser, which holds the interfaces of the current section.
+    pub fn parser(&self) -> &Parser {
+        &self.parser
+    }
+
+    /// Reads the next block, or `None` at the end of the file.
+    pub fn read_block(&mut self) -> Result<Option<Block>, Error> {
+        let mut head = [0; BLOCK_HEAD_LEN];
+        if let Err(e) = self.reader.read_exact(&mut head) {
+            return match e.kind() {
+                io::ErrorKind::UnexpectedEof => Ok(None),
+                _ => Err(e.into()),
+            };
+        }
+
+        let rest = self.parser.start_block(&head, &mut self.buf)?;
+        self.reader.read_exact(rest)?;
+
+        self.parser.parse(&self.buf).map(Some)
+    }
+}
+
+impl<R: Read> Iterator for BlockReader<R> {
+    type Item = Result<Block, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_block().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a block in the given byte order.
+    fn block(big_endian: bool, block_type: u32, body: &[u8], options: &[(u16, &[u8])]) -> Vec<u8> {
+        let u16 = |x: u16| {
+            if big_endian {
+                x.to_be_bytes()
+            } else {
+                x.to_le_bytes()
+            }
+        };
+        let u32 = |x: u32| {
+            if big_endian {
+                x.to_be_bytes()
+            } else {
+                x.to_le_bytes()
+            }
+        };
+
+        let mut b = body.to_vec();
+        for (code, value) in options {
+            b.extend(u16(*code));
+            b.extend(u16(value.len() as u16));
+            b.extend(*value);
+            b.resize(b.len().next_multiple_of(4), 0);
+        }
+        if !options.is_empty() {
+            b.extend([0; 4]);
+        }
+
+        let len = b.len() as u32 + 12;
+        [&u32(block_type)[..], &u32(len), &b, &u32(len)].concat()
+    }
+
+    fn capture(big_endian: bool) -> Vec<u8> {
+        let u32 = |x: u32| {
+            if big_endian {
+                x.to_be_bytes()
+            } else {
+                x.to_le_bytes()
+            }
+        };
+        let u16 = |x: u16| {
+            if big_endian {
+                x.to_be_bytes()
+            } else {
+                x.to_le_bytes()
+            }
+        };
+
+        let shb = [&u32(BYTE_ORDER_MAGIC)[..], &u16(1), &u16(0), &[0xff; 8]].concat();
+        let idb = [&u16(1)[..], &u16(0), &u32(0)].concat();
+        let ts_offset = if big_endian {
+            10i64.to_be_bytes()
+        } else {
+            10i64.to_le_bytes()
+        };
+        // 1.5 s + 1 ns
+        let ticks = 1_500_000_001u64;
+        let epb = |data: &[u8]| {
+            [
+                &u32(0)[..],
+                &u32((ticks >> 32) as u32),
+                &u32(ticks as u32),
+                &u32(data.len() as u32),
+                &u32(data.len() as u32),
+                data,
+                &vec![0; data.len().next_multiple_of(4) - data.len()],
+            ]
+            .concat()
+        };
+
+        [
+            block(
+                big_endian,
+                SECTION_HEADER,
+                &shb,
+                &[(OPT_COMMENT, b"walking")],
+            ),
+            block(
+                big_endian,
+                INTERFACE_DESCRIPTION,
+                &idb,
+                &[
+                    (IF_NAME, b"eth6"),
+                    (IF_TSRESOL, &[9]),
+                    (IF_TSOFFSET, &ts_offset),
+                ],
+            ),
+            block(big_endian, 5, &[0; 8], &[]),
+            block(
+                big_endian,
+                ENHANCED_PACKET,
+                &epb(b"hello"),
+                &[(OPT_COMMENT, b"door opened")],
+            ),
+            block(big_endian, ENHANCED_PACKET, &epb(b"world!!"), &[]),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn read_blocks() {
+        for big_endian in [false, true] {
+            let blocks = BlockReader::new(&capture(big_endian)[..])
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(blocks.len(), 5);
+            assert_eq!(
+                blocks[0],
+                Block::SectionHeader {
+                    comments: vec!["walking".into()]
+                }
+            );
+            let Block::Interface(interface) = &blocks[1] else {
+                panic!("expected interface");
+            };
+            assert_eq!(interface.name.as_deref(), Some("eth6"));
+            assert_eq!(interface.ts_offset, 10);
+            assert_eq!(blocks[2], Block::Other(5));
+
+            let Block::Packet(packet) = &blocks[3] else {
+                panic!("expected packet");
+            };
+            assert_eq!(packet.data, b"hello");
+            assert_eq!(packet.timestamp, Duration::new(11, 500_000_001));
+            assert_eq!(packet.comments, ["door opened"]);
+            let Block::Packet(packet) = &blocks[4] else {
+                panic!("expected packet");
+            };
+            assert_eq!(packet.data, b"world!!");
+            assert!(packet.comments.is_empty());
+        }
+    }
+
+    #[test]
+    fn timestamps() {
+        let mut interface = Interface {
+            link_type: 1,
+            snap_len: 0,
+            name: None,
+            description: None,
+            ts_resolution: 6,
+            ts_offset: 0,
+            comments: vec![],
+        };
+        assert_eq!(
+            interface.timestamp(1_700_000_000_123_456).unwrap(),
+            Duration::new(1_700_000_000, 123_456_000)
+        );
+
+        // 2^-10 s
+        interface.ts_resolution = 0x8a;
+        assert_eq!(
+            interface.timestamp(3 * 1024 + 512).unwrap(),
+            Duration::from_millis(3500)
+        );
+
+        interface.ts_resolution = 0x7f;
+        assert!(matches!(
+            interface.timestamp(0),
+            Err(Error::InvalidResolution(0x7f))
+        ));
+    }
+
+    #[test]
+    fn invalid() {
+        let b = capture(false);
+        let mut reader = BlockReader::new(&b[..b.len() - 2]);
+        assert_eq!(reader.by_ref().take(4).count(), 4);
+        assert!(matches!(reader.next(), Some(Err(Error::Io(_)))));
+
+        // a packet before its interface
+        let mut parser = Parser::new();
+        let shb_len = parser.block_len(b[..12].try_into().unwrap()).unwrap();
+        parser.parse(&b[..shb_len]).unwrap();
+        let epb_start = b.len() - block(false, ENHANCED_PACKET, &[0; 28], &[]).len();
+        assert!(matches!(
+            parser.parse(&b[epb_start..]),
+            Err(Error::UnknownInterface(0))
+        ));
+
+        assert!(matches!(
+            Parser::new().block_len(&[1, 0, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0]),
+            Err(Error::NoSection)
+        ));
+        let mut huge = b[..12].to_vec();
+        huge[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            Parser::new().block_len(huge[..].try_into().unwrap()),
+            Err(Error::TooLong(u32::MAX))
+        ));
+    }
+}
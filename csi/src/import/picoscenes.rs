@@ -0,0 +1,844 @@
+//! Reader for [PicoScenes](https://ps.zpj.io/) `.csi` files.
+//!
+//! A file is a sequence of frames, each prefixed by a little-endian `u32`
+//! length. A frame starts with a header (magic word `0x20150315`, a `u16`
+//! version and the number of segments) followed by that many segments and
+//! finally the MPDU. Each segment is prefixed by its `u32` length, its
+//! null-terminated name (itself prefixed by a `u8` length) and a `u16`
+//! version.
+//!
+//! The `RxSBasic`, `CSI` and `LegacyCSI` segments are decoded; all others
+//! are kept as raw [`Segment`]s. PicoScenes stores the CSI in the NIC's
+//! native format, which is unpacked for the QCA9300, the Intel 5300 and the
+//! Intel AX200/AX210 (iwlmvm). The subcarrier indices are not stored but
+//! follow from the packet format and bandwidth, see [`subcarriers`].
+
+use std::io::{self, Read};
+
+use ndarray::{s, Array1, Array3};
+use num_complex::Complex;
+
+use crate::{
+    ieee80211::Bandwidth,
+    import::{atheros, intel},
+    params::ChanSpec,
+    proc::WifiCsi,
+};
+
+/// Magic word of a frame header.
+const MAGIC: u32 = 0x20150315;
+
+/// Length of the prefix giving the length of each frame in a `.csi` file.
+pub const FRAME_HEAD_LEN: usize = 4;
+
+/// A frame holds a single received PPDU. Even an 802.11ax A-MPDU of at
+/// most 6,500,631 bytes together with its CSI fits in 16 MiB, so longer
+/// frames are assumed to be a corrupt file.
+const MAX_FRAME_LEN: u32 = 1 << 24;
+
+/// Supported versions of the `RxSBasic` segment.
+const RX_S_BASIC_VERSIONS: std::ops::RangeInclusive<u16> = 1..=4;
+
+/// Supported version of the `CSI` and `LegacyCSI` segments.
+const CSI_VERSION: u16 = 6;
+
+/// Error returned when reading a frame.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Reading from the underlying reader failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The frame or one of its segments is truncated.
+    #[error("truncated frame")]
+    Truncated,
+    /// The length prefix of a frame is too large for a valid frame.
+    #[error("frame of {0} bytes is too long")]
+    TooLong(u32),
+    /// The frame header does not start with the magic word.
+    #[error("invalid magic word {0:#010x}")]
+    InvalidMagic(u32),
+    /// A segment name is not valid UTF-8.
+    #[error("invalid segment name")]
+    InvalidName,
+    /// A decoded segment has an unsupported version.
+    #[error("unsupported {segment} segment version {version}")]
+    UnsupportedVersion {
+        /// Name of the segment.
+        segment: &'static str,
+        /// Version of the segment.
+        version: u16,
+    },
+    /// The CSI was recorded with a device whose format is unknown.
+    #[error("unsupported device {0:?}")]
+    UnsupportedDevice(DeviceType),
+    /// The number of tones does not match the packet format and bandwidth.
+    #[error("invalid number of tones: {0}")]
+    InvalidTones(u16),
+    /// The Intel 5300 CSI could not be parsed.
+    #[error(transparent)]
+    Intel(#[from] intel::Error),
+}
+
+/// Little-endian cursor over a byte slice.
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < n {
+            return Err(Error::Truncated);
+        }
+        let (b, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(b)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn i8(&mut self) -> Result<i8, Error> {
+        Ok(self.u8()? as i8)
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn i16(&mut self) -> Result<i16, Error> {
+        self.array().map(i16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        self.array().map(u64::from_le_bytes)
+    }
+}
+
+/// Device that recorded a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    /// Qualcomm Atheros QCA9300.
+    Qca9300,
+    /// Intel Wi-Fi Link 5300.
+    Iwl5300,
+    /// Intel Wi-Fi 6 AX200.
+    Ax200,
+    /// Intel Wi-Fi 6E AX210.
+    Ax210,
+    /// USRP software-defined radio.
+    Usrp,
+    /// Any other device.
+    Other(u16),
+}
+
+impl From<u16> for DeviceType {
+    fn from(value: u16) -> Self {
+        match value {
+            0x9300 => Self::Qca9300,
+            0x5300 => Self::Iwl5300,
+            0x2000 => Self::Ax200,
+            0x2100 => Self::Ax210,
+            0x1234 => Self::Usrp,
+            _ => Self::Other(value),
+        }
+    }
+}
+
+/// PHY format of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketFormat {
+    /// Legacy 802.11a/g.
+    NonHt,
+    /// 802.11n.
+    Ht,
+    /// 802.11ac.
+    Vht,
+    /// 802.11ax single-user.
+    HeSu,
+    /// 802.11ax multi-user.
+    HeMu,
+    /// Any other format.
+    Other(i8),
+}
+
+impl PacketFormat {
+    /// Whether this is an 802.11ax format, whose subcarriers are spaced a
+    /// quarter as far apart as those of earlier formats.
+    pub fn is_he(self) -> bool {
+        matches!(self, Self::HeSu | Self::HeMu)
+    }
+}
+
+impl From<i8> for PacketFormat {
+    fn from(value: i8) -> Self {
+        match value {
+            0 => Self::NonHt,
+            1 => Self::Ht,
+            2 => Self::Vht,
+            3 => Self::HeSu,
+            4 => Self::HeMu,
+            _ => Self::Other(value),
+        }
+    }
+}
+
+/// Converts a bandwidth in MHz.
+fn bandwidth(mhz: u16) -> Option<Bandwidth> {
+    Some(match mhz {
+        20 => Bandwidth::Bw20,
+        40 => Bandwidth::Bw40,
+        80 => Bandwidth::Bw80,
+        160 => Bandwidth::Bw160,
+        _ => return None,
+    })
+}
+
+/// Returns the indices of the subcarriers reported for a packet format and
+/// bandwidth, in ascending order, or `None` for unknown combinations.
+///
+/// HE indices are in units of the 78.125 kHz HE subcarrier spacing, the
+/// others in units of 312.5 kHz.
+///
+/// ```
+/// use csi::{ieee80211::Bandwidth, import::picoscenes::{subcarriers, PacketFormat}};
+///
+/// let he20 = subcarriers(PacketFormat::HeSu, Bandwidth::Bw20).unwrap();
+/// assert_eq!(he20.len(), 242);
+/// assert_eq!(he20[..2], [-122, -121]);
+/// ```
+pub fn subcarriers(format: PacketFormat, bandwidth: Bandwidth) -> Option<Vec<i16>> {
+    use Bandwidth::*;
+    use PacketFormat::*;
+
+    // tones of the positive half, mirrored below
+    let half: &[(i16, i16)] = match (format, bandwidth) {
+        (NonHt, Bw20) => &[(1, 26)],
+        (Ht | Vht, Bw20) => &[(1, 28)],
+        (Ht | Vht, Bw40) => &[(2, 58)],
+        (Vht, Bw80) => &[(2, 122)],
+        (Vht, Bw160) => &[(6, 126), (130, 250)],
+        (HeSu | HeMu, Bw20) => &[(2, 122)],
+        (HeSu | HeMu, Bw40) => &[(3, 244)],
+        (HeSu | HeMu, Bw80) => &[(3, 500)],
+        (HeSu | HeMu, Bw160) => &[(12, 509), (515, 1012)],
+        _ => return None,
+    };
+
+    let mut tones = half
+        .iter()
+        .rev()
+        .flat_map(|&(lo, hi)| (lo..=hi).rev().map(|k| -k))
+        .collect::<Vec<_>>();
+    tones.extend(half.iter().flat_map(|&(lo, hi)| lo..=hi));
+    Some(tones)
+}
+
+/// Basic receive information (the `RxSBasic` segment).
+#[derive(Debug, Clone, PartialEq)]
+pub struct RxSBasic {
+    /// Device that received the frame.
+    pub device_type: DeviceType,
+    /// Hardware timestamp (µs).
+    pub timestamp: u64,
+    /// System time (ns since the Unix epoch), from version 2.
+    pub system_time: Option<u64>,
+    /// Center frequency of the channel (MHz).
+    pub center_freq: i16,
+    /// Center frequency of the second segment of an 80+80 MHz channel
+    /// (MHz), from version 3.
+    pub center_freq2: Option<i16>,
+    /// Frequency of the control channel (MHz).
+    pub control_freq: i16,
+    /// Bandwidth of the channel (MHz).
+    pub cbw: u16,
+    /// PHY format of the frame.
+    pub packet_format: PacketFormat,
+    /// Bandwidth of the frame (MHz).
+    pub pkt_cbw: u16,
+    /// Guard interval (ns).
+    pub guard_interval: u16,
+    /// Modulation coding scheme.
+    pub mcs: u8,
+    /// Number of space-time streams.
+    pub num_sts: u8,
+    /// Number of extension spatial streams.
+    pub num_ess: u8,
+    /// Number of receive antennas.
+    pub num_rx: u8,
+    /// Number of users of an MU frame, from version 4.
+    pub num_user: Option<u8>,
+    /// Index of the receiving user of an MU frame, from version 4.
+    pub user_index: Option<u8>,
+    /// Noise floor (dBm).
+    pub noise_floor: i8,
+    /// RSSI (dBm).
+    pub rssi: i8,
+    /// RSSI of each receive chain (dBm); 3 chains before version 3, 8
+    /// after.
+    pub rssi_chains: Vec<i8>,
+}
+
+impl RxSBasic {
+    fn parse(version: u16, b: &[u8]) -> Result<Self, Error> {
+        if !RX_S_BASIC_VERSIONS.contains(&version) {
+            return Err(Error::UnsupportedVersion {
+                segment: "RxSBasic",
+                version,
+            });
+        }
+
+        let mut c = Cursor(b);
+        let device_type = c.u16()?.into();
+        let timestamp = c.u64()?;
+        let system_time = if version >= 2 { Some(c.u64()?) } else { None };
+        let center_freq = c.i16()?;
+        let center_freq2 = if version >= 3 { Some(c.i16()?) } else { None };
+        let control_freq = c.i16()?;
+        let cbw = c.u16()?;
+        let packet_format = c.i8()?.into();
+        let pkt_cbw = c.u16()?;
+        let guard_interval = c.u16()?;
+        let mcs = c.u8()?;
+        let num_sts = c.u8()?;
+        let num_ess = c.u8()?;
+        let num_rx = c.u8()?;
+        let (num_user, user_index) = if version >= 4 {
+            (Some(c.u8()?), Some(c.u8()?))
+        } else {
+            (None, None)
+        };
+        let noise_floor = c.i8()?;
+        let rssi = c.i8()?;
+        let chains = if version >= 3 { 8 } else { 3 };
+        let rssi_chains = c.bytes(chains)?.iter().map(|&x| x as i8).collect();
+
+        Ok(Self {
+            device_type,
+            timestamp,
+            system_time,
+            center_freq,
+            center_freq2,
+            control_freq,
+            cbw,
+            packet_format,
+            pkt_cbw,
+            guard_interval,
+            mcs,
+            num_sts,
+            num_ess,
+            num_rx,
+            num_user,
+            user_index,
+            noise_floor,
+            rssi,
+            rssi_chains,
+        })
+    }
+
+    /// Returns the chanspec of the channel, if it is a valid 2.4 or 5 GHz
+    /// one.
+    pub fn chan_spec(&self) -> Option<ChanSpec> {
+        let channel = |freq: i16| {
+            let (band, base) = match freq {
+                2412..=2472 => (0x0000, 2407),
+                5000..=5900 => (0xc000, 5000),
+                _ => return None,
+            };
+            Some((band, u16::try_from((freq - base) / 5).ok()?))
+        };
+
+        let (band, center) = channel(self.center_freq)?;
+        let (_, control) = channel(self.control_freq)?;
+        let bw = match bandwidth(self.cbw)? {
+            Bandwidth::Bw20 => 0x1000,
+            Bandwidth::Bw40 => 0x1800,
+            Bandwidth::Bw80 => 0x2000,
+            Bandwidth::Bw160 => 0x2800,
+        };
+        // index of the 20 MHz control channel within the channel, counting
+        // from below (4 channel numbers per 20 MHz)
+        let sideband = (control + self.cbw / 10 - 2).checked_sub(center)? / 4;
+
+        ChanSpec::try_from(band | bw | sideband << 8 | center).ok()
+    }
+}
+
+/// Channel state information (the `CSI` and `LegacyCSI` segments).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Csi {
+    /// Device that measured the CSI.
+    pub device_type: DeviceType,
+    /// Firmware version of the device.
+    pub firmware_version: u8,
+    /// PHY format of the frame.
+    pub packet_format: PacketFormat,
+    /// Bandwidth (MHz).
+    pub cbw: u16,
+    /// Carrier frequency (Hz).
+    pub carrier_freq: u64,
+    /// Carrier frequency of the second segment of an 80+80 MHz channel
+    /// (Hz).
+    pub carrier_freq2: u64,
+    /// Sampling rate (Hz).
+    pub sampling_rate: u64,
+    /// Subcarrier spacing (Hz).
+    pub subcarrier_bandwidth: u32,
+    /// Number of transmit streams (space-time and extension streams).
+    pub num_tx: u8,
+    /// Number of receive antennas.
+    pub num_rx: u8,
+    /// Number of extension spatial streams.
+    pub num_ess: u8,
+    /// Antenna selection bitmap.
+    pub ant_sel: u8,
+    /// Offset added to the subcarrier indices.
+    pub subcarrier_offset: i16,
+    /// Subcarrier index of each tone, see [`subcarriers`].
+    pub subcarriers: Vec<i16>,
+    /// Unscaled CSI, indexed by receive antenna, transmit stream and tone.
+    pub csi: Array3<Complex<f64>>,
+}
+
+impl Csi {
+    fn parse(segment: &'static str, version: u16, b: &[u8]) -> Result<Self, Error> {
+        if version != CSI_VERSION {
+            return Err(Error::UnsupportedVersion { segment, version });
+        }
+
+        let mut c = Cursor(b);
+        let device_type = c.u16()?.into();
+        let firmware_version = c.u8()?;
+        let packet_format = c.i8()?.into();
+        let cbw = c.u16()?;
+        let carrier_freq = c.u64()?;
+        let carrier_freq2 = c.u64()?;
+        let _is_merged = c.u8()?;
+        let sampling_rate = c.u64()?;
+        let subcarrier_bandwidth = c.u32()?;
+        let num_tones = c.u16()?;
+        let num_tx = c.u8()?;
+        let num_rx = c.u8()?;
+        let num_ess = c.u8()?;
+        let _num_csi = c.u16()?;
+        let ant_sel = c.u8()?;
+        let subcarrier_offset = c.i16()?;
+        let len = c.u32()? as usize;
+        let buf = c.bytes(len)?;
+
+        let (subcarriers, csi) = match device_type {
+            DeviceType::Qca9300 => {
+                let bw = bandwidth(cbw).ok_or(Error::InvalidTones(num_tones))?;
+                let subcarriers = atheros::subcarriers(bw).map(i16::from).collect::<Vec<_>>();
+                if usize::from(num_tones) != subcarriers.len() {
+                    return Err(Error::InvalidTones(num_tones));
+                }
+                let csi = atheros::unpack_csi(buf, num_rx, num_tx, num_tones as u8)
+                    .ok_or(Error::Truncated)?;
+                (subcarriers, csi)
+            }
+            DeviceType::Iwl5300 => {
+                let bfee = intel::Bfee::from_slice(buf)?;
+                let subcarriers = bfee.subcarriers().iter().map(|&k| k.into()).collect();
+                (subcarriers, bfee.csi.permuted_axes([1, 0, 2]))
+            }
+            DeviceType::Ax200 | DeviceType::Ax210 => {
+                let subcarriers = bandwidth(cbw)
+                    .and_then(|bw| subcarriers(packet_format, bw))
+                    .filter(|s| s.len() == usize::from(num_tones))
+                    .ok_or(Error::InvalidTones(num_tones))?;
+                let shape = (num_rx.into(), num_tx.into(), num_tones.into());
+                let values = shape.0 * shape.1 * shape.2;
+                let mut values = Cursor(buf.get(..values * 4).ok_or(Error::Truncated)?);
+                // (real, imaginary) i16 pairs, one receive antenna and stream
+                // at a time
+                let csi = Array3::from_shape_simple_fn(shape, || {
+                    let re = values.i16().unwrap();
+                    let im = values.i16().unwrap();
+                    Complex::new(re.into(), im.into())
+                });
+                (subcarriers, csi)
+            }
+            device => return Err(Error::UnsupportedDevice(device)),
+        };
+
+        Ok(Self {
+            device_type,
+            firmware_version,
+            packet_format,
+            cbw,
+            carrier_freq,
+            carrier_freq2,
+            sampling_rate,
+            subcarrier_bandwidth,
+            num_tx: csi.dim().1 as u8,
+            num_rx: csi.dim().0 as u8,
+            num_ess,
+            ant_sel,
+            subcarrier_offset,
+            subcarriers: subcarriers
+                .into_iter()
+                .map(|k| k + subcarrier_offset)
+                .collect(),
+            csi,
+        })
+    }
+
+    /// Converts the CSI to a [`WifiCsi`], with receive antennas as cores
+    /// and transmit streams as spatial streams (at most four of each).
+    ///
+    /// The tones are placed on a grid of [`Bandwidth::nsub_pow2`]
+    /// subcarriers in the same order as
+    /// [`Frame::csi`](crate::frame::Frame::csi), with zeros in between.
+    /// Since HE subcarriers are four times as dense, only every fourth HE
+    /// tone is kept. Returns `None` if the bandwidth of `chan_spec` does
+    /// not match [`Csi::cbw`].
+    pub fn to_wifi_csi(&self, chan_spec: ChanSpec, rssi: i8) -> Option<WifiCsi> {
+        if chan_spec.bandwidth().mhz() as u16 != self.cbw {
+            return None;
+        }
+
+        let n = chan_spec.bandwidth().nsub_pow2() as isize;
+        let mut wifi_csi = WifiCsi::new(chan_spec, rssi);
+        let (nrx, ntx, _) = self.csi.dim();
+
+        for rx in 0..nrx.min(4) {
+            for tx in 0..ntx.min(4) {
+                let mut grid = Array1::zeros(n as usize);
+                for (&k, &z) in self.subcarriers.iter().zip(self.csi.slice(s![rx, tx, ..])) {
+                    let k = if !self.packet_format.is_he() {
+                        k
+                    } else if k % 4 == 0 {
+                        k / 4
+                    } else {
+                        continue;
+                    };
+                    if let Some(v) = grid.get_mut((k as isize + n / 2) as usize) {
+                        *v = z;
+                    }
+                }
+                wifi_csi.insert(rx, tx, grid);
+            }
+        }
+
+        Some(wifi_csi)
+    }
+}
+
+/// A segment that is not decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    /// Name of the segment, e.g. `ExtraInfo`.
+    pub name: String,
+    /// Version of the segment.
+    pub version: u16,
+    /// Payload of the segment.
+    pub data: Vec<u8>,
+}
+
+/// A received frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RxFrame {
+    /// Version of the frame header.
+    pub version: u16,
+    /// The `RxSBasic` segment.
+    pub rx_s_basic: Option<RxSBasic>,
+    /// The `CSI` segment.
+    pub csi: Option<Csi>,
+    /// The `LegacyCSI` segment, i.e. CSI of the legacy preamble.
+    pub legacy_csi: Option<Csi>,
+    /// All other segments.
+    pub segments: Vec<Segment>,
+    /// The MAC protocol data unit.
+    pub mpdu: Vec<u8>,
+}
+
+impl RxFrame {
+    /// Parses a frame, excluding its length prefix.
+    pub fn from_slice(b: &[u8]) -> Result<Self, Error> {
+        let mut c = Cursor(b);
+        let magic = c.u32()?;
+        if magic != MAGIC {
+            return Err(Error::InvalidMagic(magic));
+        }
+        let version = c.u16()?;
+        let num_segments = c.u8()?;
+
+        let mut frame = Self {
+            version,
+            rx_s_basic: None,
+            csi: None,
+            legacy_csi: None,
+            segments: Vec::new(),
+            mpdu: Vec::new(),
+        };
+
+        for _ in 0..num_segments {
+            let len = c.u32()? as usize;
+            let mut segment = Cursor(c.bytes(len)?);
+            let name_len = segment.u8()?.into();
+            let name = segment.bytes(name_len)?;
+            let name = std::str::from_utf8(name.strip_suffix(&[0]).unwrap_or(name))
+                .map_err(|_| Error::InvalidName)?;
+            let version = segment.u16()?;
+            let data = segment.0;
+
+            match name {
+                "RxSBasic" => frame.rx_s_basic = Some(RxSBasic::parse(version, data)?),
+                "CSI" => frame.csi = Some(Csi::parse("CSI", version, data)?),
+                "LegacyCSI" => frame.legacy_csi = Some(Csi::parse("LegacyCSI", version, data)?),
+                _ => frame.segments.push(Segment {
+                    name: name.to_owned(),
+                    version,
+                    data: data.to_vec(),
+                }),
+            }
+        }
+
+        frame.mpdu = c.0.to_vec();
+        Ok(frame)
+    }
+
+    /// Converts the `CSI` segment to a [`WifiCsi`] (see
+    /// [`Csi::to_wifi_csi`]), using the channel and RSSI of the `RxSBasic`
+    /// segment.
+    pub fn to_wifi_csi(&self) -> Option<WifiCsi> {
+        let rx_s_basic = self.rx_s_basic.as_ref()?;
+        self.csi
+            .as_ref()?
+            .to_wifi_csi(rx_s_basic.chan_spec()?, rx_s_basic.rssi)
+    }
+}
+
+/// Returns the length of a frame in a `.csi` file given its
+/// [`FRAME_HEAD_LEN`] bytes long prefix, which is not part of the frame.
+pub fn frame_len(head: &[u8; FRAME_HEAD_LEN]) -> Result<usize, Error> {
+    match u32::from_le_bytes(*head) {
+        len if len <= MAX_FRAME_LEN => Ok(len as usize),
+        len => Err(Error::TooLong(len)),
+    }
+}
+
+/// Reads frames from a `.csi` file.
+///
+/// A truncated frame at the end of the file (e.g. from interrupting
+/// PicoScenes) is ignored.
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use csi::import::picoscenes::FrameReader;
+///
+/// let file = std::fs::File::open("rx.csi")?;
+/// for frame in FrameReader::new(std::io::BufReader::new(file)) {
+///     if let Some(csi) = frame?.to_wifi_csi() {
+///         println!("{:?}", csi.get(0, 0));
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct FrameReader<R> {
+    reader: R,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> FrameReader<R> {
+    /// Creates a new reader.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Reads the next frame, or `None` at the end of the file.
+    pub fn read_frame(&mut self) -> Result<Option<RxFrame>, Error> {
+        let mut head = [0; FRAME_HEAD_LEN];
+        if let Err(e) = self.reader.read_exact(&mut head) {
+            return match e.kind() {
+                io::ErrorKind::UnexpectedEof => Ok(None),
+                _ => Err(e.into()),
+            };
+        }
+
+        self.buf.resize(frame_len(&head)?, 0);
+        match self.reader.read_exact(&mut self.buf) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            r => r?,
+        }
+
+        RxFrame::from_slice(&self.buf).map(Some)
+    }
+}
+
+impl<R: Read> Iterator for FrameReader<R> {
+    type Item = Result<RxFrame, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(name: &str, version: u16, data: &[u8]) -> Vec<u8> {
+        let mut b = vec![name.len() as u8 + 1];
+        b.extend(name.as_bytes());
+        b.push(0);
+        b.extend(version.to_le_bytes());
+        b.extend(data);
+
+        let mut seg = (b.len() as u32).to_le_bytes().to_vec();
+        seg.extend(b);
+        seg
+    }
+
+    /// An AX200 frame on channel 36 with HE-SU CSI of 2 receive antennas.
+    fn frame() -> Vec<u8> {
+        let mut rx_s_basic = vec![];
+        rx_s_basic.extend(0x2000u16.to_le_bytes());
+        rx_s_basic.extend(1234u64.to_le_bytes());
+        rx_s_basic.extend(5678u64.to_le_bytes());
+        rx_s_basic.extend(5190i16.to_le_bytes());
+        rx_s_basic.extend(0i16.to_le_bytes());
+        rx_s_basic.extend(5180i16.to_le_bytes());
+        rx_s_basic.extend(40u16.to_le_bytes());
+        rx_s_basic.push(3);
+        rx_s_basic.extend(40u16.to_le_bytes());
+        rx_s_basic.extend(800u16.to_le_bytes());
+        rx_s_basic.extend([7, 1, 0, 2, 1, 0, -92i8 as u8, -40i8 as u8]);
+        rx_s_basic.extend([-41i8 as u8, -42i8 as u8, 0, 0, 0, 0, 0, 0]);
+
+        let tones = subcarriers(PacketFormat::HeSu, Bandwidth::Bw40).unwrap();
+        let mut csi = vec![];
+        csi.extend(0x2000u16.to_le_bytes());
+        csi.push(1);
+        csi.push(3);
+        csi.extend(40u16.to_le_bytes());
+        csi.extend(5_190_000_000u64.to_le_bytes());
+        csi.extend(0u64.to_le_bytes());
+        csi.push(0);
+        csi.extend(40_000_000u64.to_le_bytes());
+        csi.extend(78_125u32.to_le_bytes());
+        csi.extend((tones.len() as u16).to_le_bytes());
+        csi.extend([1, 2, 0]);
+        csi.extend(1u16.to_le_bytes());
+        csi.push(3);
+        csi.extend(0i16.to_le_bytes());
+        csi.extend((tones.len() as u32 * 2 * 4).to_le_bytes());
+        for rx in 0..2i16 {
+            for &k in &tones {
+                csi.extend(k.to_le_bytes());
+                csi.extend(rx.to_le_bytes());
+            }
+        }
+
+        let mut b = vec![];
+        b.extend(MAGIC.to_le_bytes());
+        b.extend(1u16.to_le_bytes());
+        b.push(3);
+        b.extend(segment("RxSBasic", 4, &rx_s_basic));
+        b.extend(segment("ExtraInfo", 1, &[1, 2, 3]));
+        b.extend(segment("CSI", CSI_VERSION, &csi));
+        b.extend([0x88, 0x01]);
+
+        let mut frame = (b.len() as u32).to_le_bytes().to_vec();
+        frame.extend(b);
+        frame
+    }
+
+    #[test]
+    fn tone_plans() {
+        use Bandwidth::*;
+        use PacketFormat::*;
+
+        for (format, bw, n) in [
+            (NonHt, Bw20, 52),
+            (Ht, Bw40, 114),
+            (Vht, Bw80, 242),
+            (Vht, Bw160, 484),
+            (HeSu, Bw20, 242),
+            (HeMu, Bw40, 484),
+            (HeSu, Bw80, 996),
+            (HeSu, Bw160, 1992),
+        ] {
+            let tones = subcarriers(format, bw).unwrap();
+            assert_eq!(tones.len(), n, "{format:?} {bw:?}");
+            assert!(tones.windows(2).all(|w| w[0] < w[1]));
+            assert!(tones.iter().zip(tones.iter().rev()).all(|(a, b)| *a == -b));
+        }
+        assert_eq!(subcarriers(NonHt, Bw80), None);
+    }
+
+    #[test]
+    fn read_frames() {
+        let mut file = frame();
+        file.extend(frame());
+        file.extend(&frame()[..40]);
+
+        let frames = FrameReader::new(&file[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(frames.len(), 2);
+
+        let frame = &frames[0];
+        assert_eq!(frame.mpdu, [0x88, 0x01]);
+        assert_eq!(frame.segments.len(), 1);
+        assert_eq!(frame.segments[0].name, "ExtraInfo");
+
+        let rx_s_basic = frame.rx_s_basic.as_ref().unwrap();
+        assert_eq!(rx_s_basic.device_type, DeviceType::Ax200);
+        assert_eq!(rx_s_basic.system_time, Some(5678));
+        assert_eq!(rx_s_basic.packet_format, PacketFormat::HeSu);
+        assert_eq!(rx_s_basic.num_user, Some(1));
+        assert_eq!(rx_s_basic.rssi, -40);
+        assert_eq!(rx_s_basic.rssi_chains[..2], [-41, -42]);
+
+        let chan_spec = rx_s_basic.chan_spec().unwrap();
+        assert_eq!(chan_spec.control_channel(), 36);
+        assert_eq!(chan_spec.center(), 38);
+        assert_eq!(chan_spec.bandwidth(), Bandwidth::Bw40);
+
+        let csi = frame.csi.as_ref().unwrap();
+        assert_eq!(csi.csi.dim(), (2, 1, 484));
+        assert_eq!(csi.subcarriers[0], -244);
+        assert_eq!(csi.csi[[1, 0, 0]], Complex::new(-244., 1.));
+
+        let wifi_csi = frame.to_wifi_csi().unwrap();
+        assert_eq!(wifi_csi.rssi, -40);
+        let grid = wifi_csi.get(1, 0).unwrap();
+        assert_eq!(grid.len(), 128);
+        // HE tone 240 is legacy subcarrier 60
+        assert_eq!(grid[64 + 60], Complex::new(240., 1.));
+        assert_eq!(grid[64 - 1], Complex::new(-4., 1.));
+        assert_eq!(grid[64], Complex::new(0., 0.));
+        assert!(wifi_csi.get(0, 1).is_none());
+    }
+
+    #[test]
+    fn invalid() {
+        let mut b = frame();
+        b[4] ^= 1;
+        assert!(matches!(
+            RxFrame::from_slice(&b[4..]),
+            Err(Error::InvalidMagic(0x20150314))
+        ));
+        assert!(matches!(
+            RxFrame::from_slice(&frame()[4..60]),
+            Err(Error::Truncated)
+        ));
+        assert!(matches!(
+            FrameReader::new(&[0xff; 8][..]).next(),
+            Some(Err(Error::TooLong(u32::MAX)))
+        ));
+    }
+}
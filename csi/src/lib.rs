@@ -1,10 +1,31 @@
 //! Nexmon firmware patching framework utilities.
 //!
 //! As of now, only the BCM4436c0 chip is supported.
+//!
+//! # Features
+//!
+//! - `std` (default): enables the [`proc`], [`import`], [`export`] and
+//!   [`archive`] modules and the unit-aware helpers in [`ieee80211`]. Without
+//!   it, the crate is `no_std` (but requires `alloc`), so [`frame`] and
+//!   [`params`] can be used on embedded collectors.
+//! - `serde`: `Serialize` and `Deserialize` implementations.
+//! - `arbitrary`: `Arbitrary` implementations for fuzzing (requires `std`).
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![warn(missing_docs)]
 
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod archive;
+#[cfg(feature = "std")]
+pub mod export;
 pub mod frame;
 pub mod ieee80211;
+#[cfg(feature = "std")]
+pub mod import;
 pub mod params;
+#[cfg(feature = "std")]
 pub mod proc;
+#[cfg(feature = "serde")]
+mod serde_impls;
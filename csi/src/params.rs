@@ -1,14 +1,19 @@
 //! CSI collection parameters passed to the firmware.
 
-use std::fmt::Display;
+use alloc::{vec, vec::Vec};
+use core::{
+    fmt::{self, Display},
+    str::FromStr,
+};
 
-use base64::{display::Base64Display, engine::general_purpose::STANDARD};
+use base64::{display::Base64Display, engine::general_purpose::STANDARD, Engine};
 use macaddr::MacAddr6;
 
 use crate::ieee80211::{Band, Bandwidth};
 
 fn bands(ctl_ch: u8, bw: Bandwidth) -> Option<(u8, u8)> {
     let channels: &[u8] = match bw {
+        Bandwidth::Bw20 if ctl_ch == 0 => return None,
         Bandwidth::Bw20 => return Some((ctl_ch, 0)), // trivial case
         Bandwidth::Bw40 => &[38, 46, 54, 62, 102, 110, 118, 126, 134, 142, 151, 159],
         Bandwidth::Bw80 => &[42, 58, 106, 122, 138, 155],
@@ -63,6 +68,16 @@ impl ChanSpec {
         self.sideband
     }
 
+    /// Returns the 20 MHz control channel.
+    pub const fn control_channel(&self) -> u8 {
+        self.channel_lo_20mhz() + 4 * self.sideband
+    }
+
+    /// Returns the band.
+    pub const fn band(&self) -> Band {
+        self.band
+    }
+
     /// Returns the bandwidth.
     pub const fn bandwidth(&self) -> Bandwidth {
         self.bandwidth
@@ -96,10 +111,52 @@ impl ChanSpec {
             bandwidth,
         })
     }
+
+    /// Construct a 40 MHz chanspec with the control channel in the lower or
+    /// upper half. Unlike at 5 GHz, 40 MHz channels at 2.4 GHz overlap, so
+    /// the sideband is needed to pick the center channel.
+    fn new_40mhz(channel: u8, band: Band, upper: bool) -> Option<Self> {
+        let cs = match band {
+            Band::Band2G => Self {
+                center: if upper {
+                    channel.checked_sub(2)?
+                } else {
+                    channel.checked_add(2)?
+                },
+                sideband: upper.into(),
+                band,
+                bandwidth: Bandwidth::Bw40,
+            },
+            Band::Band5G => Self::new(channel, band, Bandwidth::Bw40)?,
+        };
+
+        (cs.is_valid() && cs.sideband == u8::from(upper)).then_some(cs)
+    }
+
+    /// Whether the center channel and sideband describe a valid channel.
+    fn is_valid(&self) -> bool {
+        if self.sideband >= self.bandwidth.mhz() / 20 {
+            return false;
+        }
+
+        if self.band == Band::Band2G && self.bandwidth == Bandwidth::Bw40 {
+            // both halves must be channels 1 to 13
+            return (3..=11).contains(&self.center);
+        }
+
+        // the center channel must be the one `ChanSpec::new` picks for the
+        // control channel, or `control_channel` may overflow
+        let control = self
+            .center
+            .checked_sub((self.bandwidth.mhz() - 20) / 10)
+            .and_then(|lo| lo.checked_add(4 * self.sideband));
+        control.and_then(|control| bands(control, self.bandwidth))
+            == Some((self.center, self.sideband))
+    }
 }
 
 /// Error returned when parsing a [`ChanSpec`].
-#[derive(Debug, Clone, Copy, thiserror::Error, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
 pub enum ParseChanSpecError {
     /// Bandwidth is not one of the supported values (none of the
     /// [`Bandwidth`] variants) or when the bandwidth is unsupported
@@ -110,6 +167,117 @@ pub enum ParseChanSpecError {
     /// variants).
     #[error("invalid band")]
     InvalidBand,
+    /// The control sideband lies outside the channel's bandwidth.
+    #[error("invalid sideband")]
+    InvalidSideband,
+    /// The control channel is not valid for the given bandwidth.
+    #[error("invalid channel")]
+    InvalidChannel,
+    /// The string is not of the form
+    /// `[<band>g]<channel>[/<bandwidth>|l|u]`.
+    #[error("malformed chanspec")]
+    Malformed,
+}
+
+impl Display for ChanSpec {
+    /// Formats the chanspec like `wf_chspec_ntoa`, i.e. in a format
+    /// accepted by [`ChanSpec::from_str`]. 40 MHz channels at 2.4 GHz are
+    /// written with an `l` or `u` suffix for the lower or upper sideband.
+    ///
+    /// ```
+    /// # use csi::params::ChanSpec;
+    /// # use csi::ieee80211::{Band, Bandwidth};
+    /// let cs = ChanSpec::new(120, Band::Band5G, Bandwidth::Bw160).unwrap();
+    /// assert_eq!(cs.to_string(), "120/160");
+    /// assert_eq!(ChanSpec::try_from(0x1903).unwrap().to_string(), "5u");
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let channel = self.control_channel();
+
+        // band prefix is only needed when it differs from the default
+        match self.band {
+            Band::Band2G if channel > 14 => write!(f, "2g")?,
+            Band::Band5G if channel <= 14 => write!(f, "5g")?,
+            _ => (),
+        }
+
+        match self.bandwidth {
+            Bandwidth::Bw20 => write!(f, "{channel}"),
+            Bandwidth::Bw40 if self.band == Band::Band2G => {
+                let sb = if self.sideband == 0 { 'l' } else { 'u' };
+                write!(f, "{channel}{sb}")
+            }
+            bw => write!(f, "{channel}/{}", bw.mhz()),
+        }
+    }
+}
+
+impl FromStr for ChanSpec {
+    type Err = ParseChanSpecError;
+
+    /// Parse a chanspec of the form `[<band>g]<channel>[/<bandwidth>|l|u]`,
+    /// as accepted by `wl` and `makecsiparams`. Channels up to 14 default
+    /// to the 2.4 GHz band. An `l` or `u` suffix selects 40 MHz with the
+    /// control channel in the lower or upper half.
+    ///
+    /// ```
+    /// # use csi::params::ChanSpec;
+    /// # use csi::ieee80211::{Band, Bandwidth};
+    /// assert_eq!(
+    ///     "36/40".parse(),
+    ///     Ok(ChanSpec::new(36, Band::Band5G, Bandwidth::Bw40).unwrap())
+    /// );
+    /// assert_eq!(
+    ///     "2g6".parse(),
+    ///     Ok(ChanSpec::new(6, Band::Band2G, Bandwidth::Bw20).unwrap())
+    /// );
+    /// assert_eq!("40u".parse::<ChanSpec>().map(|cs| cs.center()), Ok(38));
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim_end_matches(' ');
+        let (channel, bandwidth, upper) = match s.split_once('/') {
+            Some((channel, bandwidth)) => (channel, bandwidth, None),
+            None => match s.strip_suffix(['l', 'u']) {
+                Some(channel) => (channel, "40", Some(s.ends_with('u'))),
+                None => (s, "20", None),
+            },
+        };
+
+        let (band, channel) = match channel.split_once(['g', 'G']) {
+            Some(("2", channel)) => (Some(Band::Band2G), channel),
+            Some(("5", channel)) => (Some(Band::Band5G), channel),
+            Some(_) => return Err(ParseChanSpecError::InvalidBand),
+            None => (None, channel),
+        };
+
+        let parse = |s: &str| {
+            if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(ParseChanSpecError::Malformed);
+            }
+            s.parse::<u16>().map_err(|_| ParseChanSpecError::Malformed)
+        };
+
+        let channel =
+            u8::try_from(parse(channel)?).map_err(|_| ParseChanSpecError::InvalidChannel)?;
+        let bandwidth = match parse(bandwidth)? {
+            20 => Bandwidth::Bw20,
+            40 => Bandwidth::Bw40,
+            80 => Bandwidth::Bw80,
+            160 => Bandwidth::Bw160,
+            _ => return Err(ParseChanSpecError::InvalidBandwidth),
+        };
+        let band = band.unwrap_or(if channel <= 14 {
+            Band::Band2G
+        } else {
+            Band::Band5G
+        });
+
+        match upper {
+            Some(upper) => Self::new_40mhz(channel, band, upper),
+            None => Self::new(channel, band, bandwidth),
+        }
+        .ok_or(ParseChanSpecError::InvalidChannel)
+    }
 }
 
 impl TryFrom<u16> for ChanSpec {
@@ -146,14 +314,36 @@ impl TryFrom<u16> for ChanSpec {
         };
 
         let center = ((value >> Self::CENTER_SHIFT) & 0xff) as u8;
-        let sideband = ((value >> Self::SIDEBAND_SHIFT) & 0x3) as u8;
+        // #define WL_CHANSPEC_CTL_SB_MASK         0x0700
+        let sideband = ((value >> Self::SIDEBAND_SHIFT) & 0x7) as u8;
 
-        Ok(Self {
+        if sideband >= bandwidth.mhz() / 20 {
+            return Err(ParseChanSpecError::InvalidSideband);
+        }
+
+        let cs = Self {
             center,
             sideband,
             band,
             bandwidth,
-        })
+        };
+
+        if !cs.is_valid() {
+            return Err(ParseChanSpecError::InvalidChannel);
+        }
+
+        Ok(cs)
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for ChanSpec {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Self::try_from(u16::arbitrary(u)?).map_err(|_| arbitrary::Error::IncorrectFormat)
+    }
+
+    fn size_hint(depth: usize) -> (usize, Option<usize>) {
+        u16::size_hint(depth)
     }
 }
 
@@ -180,7 +370,8 @@ impl From<ChanSpec> for u16 {
 
 bitflags::bitflags! {
     /// Core filter.
-    #[derive(Debug, Clone, Copy)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct Cores: u8 {
         /// Enable core 0.
         const CORE0 = 0b0001;
@@ -193,7 +384,8 @@ bitflags::bitflags! {
     }
 
     /// Spatial stream filter.
-    #[derive(Debug, Clone, Copy)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct SpatialStreams: u8 {
         /// Enable spatial stream 0.
         const S0 = 0b0001;
@@ -206,7 +398,8 @@ bitflags::bitflags! {
     }
 }
 
-/// Default delay for the given cores and spatial streams.
+/// Default delay for the given cores and spatial streams. This is also the
+/// minimum delay accepted by [`ParamsBuilder`].
 /// See [`Params::delay_us`].
 pub const fn default_delay_us(cores: Cores, spatial_streams: SpatialStreams) -> u16 {
     // int csi_to_capture = countbit (nssmask) * countbit (coremask);
@@ -228,6 +421,10 @@ pub const fn default_delay_us(cores: Cores, spatial_streams: SpatialStreams) ->
 /// CSI collection parameters used by
 /// [nexutil](https://github.com/seemoo-lab/nexmon/blob/ae8addba003ceb68a4217c014242d5c747eeaf36/utilities/nexutil/README.md).
 ///
+/// Prefer [`Params::builder`], which validates the parameters. If the struct
+/// is constructed directly, call [`Params::validate`] before passing it to
+/// the firmware.
+///
 /// ```
 /// # use csi::params::{Cores, SpatialStreams, Params, ChanSpec};
 /// # use csi::ieee80211::{Bandwidth, Band};
@@ -247,6 +444,9 @@ pub const fn default_delay_us(cores: Cores, spatial_streams: SpatialStreams) ->
 ///     "JtgBdQAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=="
 /// );
 /// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "crate::serde_impls::RawParams"))]
 pub struct Params {
     /// Channel specification. See [`ChanSpec`].
     pub chan_spec: ChanSpec,
@@ -259,6 +459,7 @@ pub struct Params {
     /// First packet byte to filter on.
     pub first_pkt_byte: Option<u8>,
     /// Source MAC addresses to filter on. Maximum length is 4.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_impls::mac_addrs"))]
     pub mac_addrs: Vec<MacAddr6>,
     /// Delay in microseconds after each CSI operation
     /// (really needed for 3x4, 4x3 and 4x4 configurations).
@@ -266,6 +467,55 @@ pub struct Params {
 }
 
 impl Params {
+    /// Start building parameters for the given chanspec.
+    ///
+    /// ```
+    /// # use csi::params::{Cores, SpatialStreams, Params, ChanSpec};
+    /// # use csi::ieee80211::{Bandwidth, Band};
+    /// let params = Params::builder(ChanSpec::new(36, Band::Band5G, Bandwidth::Bw40).unwrap())
+    ///     .cores(Cores::CORE0 | Cores::CORE2)
+    ///     .spatial_streams(SpatialStreams::S0 | SpatialStreams::S1 | SpatialStreams::S2)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(
+    ///     params.to_string(),
+    ///     "JtgBdQAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=="
+    /// );
+    /// ```
+    pub fn builder(chan_spec: ChanSpec) -> ParamsBuilder {
+        ParamsBuilder::new(chan_spec)
+    }
+
+    /// Check that the parameters are valid.
+    pub fn validate(&self) -> Result<(), ParamsError> {
+        if self.mac_addrs.len() > 4 {
+            return Err(ParamsError::TooManyMacAddrs(self.mac_addrs.len()));
+        }
+
+        if !self.csi_collect {
+            return Ok(());
+        }
+
+        if self.cores.is_empty() {
+            return Err(ParamsError::EmptyCoreMask);
+        }
+
+        if self.spatial_streams.is_empty() {
+            return Err(ParamsError::EmptySpatialStreamMask);
+        }
+
+        let min = default_delay_us(self.cores, self.spatial_streams);
+        if self.delay_us < min {
+            return Err(ParamsError::DelayTooShort {
+                delay_us: self.delay_us,
+                min,
+            });
+        }
+
+        Ok(())
+    }
+
     /// Convert to a byte array that can be passed to nexutil. The format conforms to the
     /// following C struct (little endian):
     ///
@@ -294,7 +544,16 @@ impl Params {
     /// ```
     ///
     /// [GitHub source](https://github.com/seemoo-lab/nexmon_csi/blob/fdb25ef0e4e1402e968bb644d4914ad1a3d0a84d/utils/makecsiparams/makecsiparams.c#L44C8-L64)
-    pub fn to_bytes(&self) -> [u8; 34] {
+    ///
+    /// # Errors
+    ///
+    /// Fails if there are more than four MAC addresses, which do not fit.
+    /// Other invalid parameters are encoded as is. See [`Params::validate`].
+    pub fn to_bytes(&self) -> Result<[u8; 34], ParamsError> {
+        if self.mac_addrs.len() > 4 {
+            return Err(ParamsError::TooManyMacAddrs(self.mac_addrs.len()));
+        }
+
         let mut out = [0u8; 34];
 
         out[0..2].copy_from_slice(&self.chan_spec.as_u16().to_le_bytes());
@@ -317,21 +576,245 @@ impl Params {
 
         out[32..34].copy_from_slice(&self.delay_us.to_le_bytes());
 
-        out
+        Ok(out)
+    }
+
+    /// Parse the byte representation produced by [`Params::to_bytes`].
+    ///
+    /// ```
+    /// # use csi::params::{Cores, Params, SpatialStreams};
+    /// let bytes = [
+    ///     0x26, 0xd8, 0x01, 0x75, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ///     0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ///     0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    /// ];
+    /// let params = Params::from_bytes(&bytes).unwrap();
+    ///
+    /// assert_eq!(params.cores, Cores::CORE0 | Cores::CORE2);
+    /// assert_eq!(params.to_bytes(), Ok(bytes));
+    /// ```
+    pub fn from_bytes(b: &[u8]) -> Result<Self, ParseParamsError> {
+        let b: &[u8; 34] = b
+            .try_into()
+            .map_err(|_| ParseParamsError::InvalidLength(b.len()))?;
+
+        let chan_spec = ChanSpec::try_from(u16::from_le_bytes([b[0], b[1]]))?;
+
+        let csi_collect = match b[2] {
+            0 => false,
+            1 => true,
+            n => return Err(ParseParamsError::InvalidCsiCollect(n)),
+        };
+
+        let cores = Cores::from_bits_truncate(b[3] & 0x0f);
+        let spatial_streams = SpatialStreams::from_bits_truncate(b[3] >> 4);
+
+        // makecsiparams refuses to enable collection without both masks
+        if csi_collect && (cores.is_empty() || spatial_streams.is_empty()) {
+            return Err(ParseParamsError::EmptyMask);
+        }
+
+        let first_pkt_byte = match b[4] {
+            0 => None,
+            1 => Some(b[5]),
+            n => return Err(ParseParamsError::InvalidPacketFilter(n)),
+        };
+
+        let n_mac_addrs = u16::from_le_bytes([b[6], b[7]]);
+        if n_mac_addrs > 4 {
+            return Err(ParseParamsError::TooManyMacAddrs(n_mac_addrs));
+        }
+        let mac_addrs = b[8..32]
+            .chunks_exact(6)
+            .take(n_mac_addrs.into())
+            .map(|mac| MacAddr6::new(mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]))
+            .collect();
+
+        Ok(Self {
+            chan_spec,
+            csi_collect,
+            cores,
+            spatial_streams,
+            first_pkt_byte,
+            mac_addrs,
+            delay_us: u16::from_le_bytes([b[32], b[33]]),
+        })
+    }
+}
+
+/// Error returned when [`Params`] are invalid.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ParamsError {
+    /// More than four MAC addresses are specified.
+    #[error("too many MAC addresses ({0} > 4)")]
+    TooManyMacAddrs(usize),
+    /// No cores are enabled.
+    #[error("empty core mask")]
+    EmptyCoreMask,
+    /// No spatial streams are enabled.
+    #[error("empty spatial stream mask")]
+    EmptySpatialStreamMask,
+    /// The delay is shorter than [`default_delay_us`], which the firmware
+    /// needs when collecting 12 or more CSI frames per packet.
+    #[error("delay of {delay_us} µs is too short (minimum {min} µs)")]
+    DelayTooShort {
+        /// The requested delay.
+        delay_us: u16,
+        /// The minimum delay.
+        min: u16,
+    },
+}
+
+/// Builder for [`Params`]. See [`Params::builder`].
+#[derive(Debug, Clone)]
+pub struct ParamsBuilder {
+    params: Params,
+    delay_us: Option<u16>,
+}
+
+impl ParamsBuilder {
+    fn new(chan_spec: ChanSpec) -> Self {
+        Self {
+            params: Params {
+                chan_spec,
+                csi_collect: true,
+                cores: Cores::empty(),
+                spatial_streams: SpatialStreams::empty(),
+                first_pkt_byte: None,
+                mac_addrs: vec![],
+                delay_us: 0,
+            },
+            delay_us: None,
+        }
+    }
+
+    /// Whether to collect CSI. Defaults to `true`.
+    pub fn csi_collect(mut self, csi_collect: bool) -> Self {
+        self.params.csi_collect = csi_collect;
+        self
+    }
+
+    /// Cores to collect on.
+    pub fn cores(mut self, cores: Cores) -> Self {
+        self.params.cores = cores;
+        self
+    }
+
+    /// Spatial streams to collect.
+    pub fn spatial_streams(mut self, spatial_streams: SpatialStreams) -> Self {
+        self.params.spatial_streams = spatial_streams;
+        self
+    }
+
+    /// Only collect CSI from frames starting with this byte.
+    pub fn first_pkt_byte(mut self, first_pkt_byte: u8) -> Self {
+        self.params.first_pkt_byte = Some(first_pkt_byte);
+        self
+    }
+
+    /// Only collect CSI from this source MAC address. Can be called up to
+    /// four times.
+    pub fn mac_addr(mut self, mac_addr: MacAddr6) -> Self {
+        self.params.mac_addrs.push(mac_addr);
+        self
+    }
+
+    /// Delay in microseconds after each CSI operation. Defaults to
+    /// [`default_delay_us`].
+    pub fn delay_us(mut self, delay_us: u16) -> Self {
+        self.delay_us = Some(delay_us);
+        self
+    }
+
+    /// Validate and build the parameters.
+    pub fn build(self) -> Result<Params, ParamsError> {
+        let Self {
+            mut params,
+            delay_us,
+        } = self;
+
+        params.delay_us =
+            delay_us.unwrap_or_else(|| default_delay_us(params.cores, params.spatial_streams));
+        params.validate()?;
+
+        Ok(params)
+    }
+}
+
+/// Error returned when parsing [`Params`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ParseParamsError {
+    /// The input is not valid base64.
+    // `DecodeError` only implements `Error` with `base64/std`, so it is not
+    // the source.
+    #[error("{0}")]
+    Base64(base64::DecodeError),
+    /// The parameters must be exactly 34 bytes long.
+    #[error("expected 34 bytes, got {0}")]
+    InvalidLength(usize),
+    /// See [`ParseChanSpecError`].
+    #[error(transparent)]
+    InvalidChanSpec(#[from] ParseChanSpecError),
+    /// The CSI collection flag is neither 0 nor 1.
+    #[error("invalid csi_collect value {0}")]
+    InvalidCsiCollect(u8),
+    /// CSI collection is enabled, but the core or spatial stream
+    /// mask is empty.
+    #[error("empty core or spatial stream mask")]
+    EmptyMask,
+    /// The packet filter flag is neither 0 nor 1.
+    #[error("invalid use_pkt_filter value {0}")]
+    InvalidPacketFilter(u8),
+    /// More than four MAC addresses are specified.
+    #[error("too many MAC addresses ({0} > 4)")]
+    TooManyMacAddrs(u16),
+}
+
+impl From<base64::DecodeError> for ParseParamsError {
+    fn from(value: base64::DecodeError) -> Self {
+        Self::Base64(value)
     }
 }
 
 impl Display for Params {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        Base64Display::new(&self.to_bytes(), &STANDARD).fmt(f)
+    /// Formats the parameters as the base64 string passed to nexutil.
+    ///
+    /// Fails with [`fmt::Error`] if [`Params::to_bytes`] fails.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self.to_bytes().map_err(|_| fmt::Error)?;
+        Base64Display::new(&bytes, &STANDARD).fmt(f)
+    }
+}
+
+impl FromStr for Params {
+    type Err = ParseParamsError;
+
+    /// Parse the base64 string passed to nexutil.
+    ///
+    /// ```
+    /// # use csi::params::Params;
+    /// // makecsiparams -c 36/40 -C 0x5 -N 0x7
+    /// let params: Params = "JtgBdQAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA==".parse().unwrap();
+    ///
+    /// assert_eq!(params.chan_spec.center(), 38);
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_bytes(&STANDARD.decode(s.trim())?)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use alloc::string::String;
+    use core::fmt::Write;
+
+    use macaddr::MacAddr6;
+
     use crate::ieee80211::{Band, Bandwidth};
 
-    use super::ChanSpec;
+    use super::{
+        ChanSpec, Cores, Params, ParamsError, ParseChanSpecError, ParseParamsError, SpatialStreams,
+    };
 
     #[test]
     fn chanspec_channel_lo() {
@@ -347,4 +830,271 @@ mod tests {
         assert_eq!(cs.center(), 134);
         assert_eq!(cs.channel_lo_20mhz(), 132);
     }
+
+    #[test]
+    fn params_roundtrip() {
+        let params = Params {
+            chan_spec: ChanSpec::new(157, Band::Band5G, Bandwidth::Bw80).unwrap(),
+            csi_collect: true,
+            cores: Cores::all(),
+            spatial_streams: SpatialStreams::S0 | SpatialStreams::S3,
+            first_pkt_byte: Some(0x88),
+            mac_addrs: vec![
+                MacAddr6::new(0x50, 0xed, 0x3c, 0x2e, 0x04, 0x00),
+                MacAddr6::new(0x00, 0x11, 0x22, 0x33, 0x44, 0x55),
+            ],
+            delay_us: 50,
+        };
+
+        assert_eq!(
+            Params::from_bytes(&params.to_bytes().unwrap()),
+            Ok(params.clone())
+        );
+        assert_eq!(params.to_string().parse(), Ok(params.clone()));
+
+        let mut too_many = params;
+        too_many.mac_addrs = vec![MacAddr6::nil(); 5];
+        assert_eq!(too_many.to_bytes(), Err(ParamsError::TooManyMacAddrs(5)));
+        assert!(write!(String::new(), "{too_many}").is_err());
+    }
+
+    #[test]
+    fn params_invalid() {
+        let mut bytes = [0u8; 34];
+        bytes[0..2].copy_from_slice(&0xd826u16.to_le_bytes());
+        bytes[2] = 1;
+        bytes[3] = 0x75;
+
+        assert!(Params::from_bytes(&bytes).is_ok());
+        assert_eq!(
+            Params::from_bytes(&bytes[..33]),
+            Err(ParseParamsError::InvalidLength(33))
+        );
+
+        let mut b = bytes;
+        b[1] = 0x00;
+        assert!(matches!(
+            Params::from_bytes(&b),
+            Err(ParseParamsError::InvalidChanSpec(_))
+        ));
+
+        let mut b = bytes;
+        b[3] = 0x70;
+        assert_eq!(Params::from_bytes(&b), Err(ParseParamsError::EmptyMask));
+
+        let mut b = bytes;
+        b[6] = 5;
+        assert_eq!(
+            Params::from_bytes(&b),
+            Err(ParseParamsError::TooManyMacAddrs(5))
+        );
+
+        assert!(matches!(
+            "not base64!".parse::<Params>(),
+            Err(ParseParamsError::Base64(_))
+        ));
+    }
+
+    #[test]
+    fn params_builder() {
+        let builder = Params::builder(ChanSpec::new(36, Band::Band5G, Bandwidth::Bw80).unwrap())
+            .cores(Cores::all())
+            .spatial_streams(SpatialStreams::all());
+
+        let params = builder.clone().build().unwrap();
+        assert_eq!(params.delay_us, 50);
+        assert_eq!(
+            builder.clone().delay_us(10).build(),
+            Err(ParamsError::DelayTooShort {
+                delay_us: 10,
+                min: 50
+            })
+        );
+        assert_eq!(
+            builder
+                .clone()
+                .spatial_streams(SpatialStreams::empty())
+                .build(),
+            Err(ParamsError::EmptySpatialStreamMask)
+        );
+        assert_eq!(
+            builder.clone().cores(Cores::empty()).build(),
+            Err(ParamsError::EmptyCoreMask)
+        );
+
+        let mac = MacAddr6::nil();
+        assert_eq!(
+            builder
+                .mac_addr(mac)
+                .mac_addr(mac)
+                .mac_addr(mac)
+                .mac_addr(mac)
+                .mac_addr(mac)
+                .build(),
+            Err(ParamsError::TooManyMacAddrs(5))
+        );
+    }
+
+    #[test]
+    fn chanspec_sideband() {
+        // the eight sidebands of a 160 MHz channel need all three bits of
+        // WL_CHANSPEC_CTL_SB_MASK
+        let cs = ChanSpec::new(128, Band::Band5G, Bandwidth::Bw160).unwrap();
+        assert_eq!(cs.sideband(), 7);
+        assert_eq!(ChanSpec::try_from(u16::from(cs)), Ok(cs));
+
+        // sideband 4 of an 80 MHz channel and sideband 1 of a 20 MHz channel
+        assert_eq!(
+            ChanSpec::try_from(0xe42a),
+            Err(ParseChanSpecError::InvalidSideband)
+        );
+        assert_eq!(
+            ChanSpec::try_from(0xd124),
+            Err(ParseChanSpecError::InvalidSideband)
+        );
+        assert!(ChanSpec::try_from(0xe32a).is_ok());
+    }
+
+    #[test]
+    fn chanspec_invalid_center() {
+        // center 0 at 80 MHz and center 250 at 160 MHz, sideband 7, would
+        // overflow in `control_channel`
+        for value in [0xe000, 0xe800 | 0x700 | 250] {
+            assert_eq!(
+                ChanSpec::try_from(value),
+                Err(ParseChanSpecError::InvalidChannel)
+            );
+
+            let mut bytes = [0u8; 34];
+            bytes[0..2].copy_from_slice(&value.to_le_bytes());
+            assert_eq!(
+                Params::from_bytes(&bytes),
+                Err(ParseParamsError::InvalidChanSpec(
+                    ParseChanSpecError::InvalidChannel
+                ))
+            );
+        }
+
+        // 40 MHz centered on channel 40 is not a valid channel
+        assert_eq!(
+            ChanSpec::try_from(0xd828),
+            Err(ParseChanSpecError::InvalidChannel)
+        );
+        assert_eq!(
+            ChanSpec::try_from(0xd000),
+            Err(ParseChanSpecError::InvalidChannel)
+        );
+    }
+
+    #[test]
+    fn chanspec_exhaustive() {
+        for value in 0..=u16::MAX {
+            let Ok(cs) = ChanSpec::try_from(value) else {
+                continue;
+            };
+
+            assert_eq!(u16::from(cs), value);
+            if cs.band() == Band::Band5G || cs.bandwidth() == Bandwidth::Bw20 {
+                assert_eq!(
+                    ChanSpec::new(cs.control_channel(), cs.band(), cs.bandwidth()),
+                    Some(cs)
+                );
+            }
+            assert_eq!(cs.to_string().parse(), Ok(cs), "{value:#06x}");
+        }
+    }
+
+    #[test]
+    fn chanspec_display() {
+        for s in [
+            "36", "36/40", "48/80", "100/160", "120/160", "165", "6", "5g14", "6l", "6u",
+        ] {
+            assert_eq!(s.parse::<ChanSpec>().unwrap().to_string(), s);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn params_serde() {
+        let params = Params::builder(ChanSpec::new(44, Band::Band5G, Bandwidth::Bw80).unwrap())
+            .cores(Cores::CORE0 | Cores::CORE1)
+            .spatial_streams(SpatialStreams::S0)
+            .mac_addr(MacAddr6::new(0x50, 0xed, 0x3c, 0x2e, 0x04, 0x00))
+            .build()
+            .unwrap();
+
+        let json = serde_json::to_value(&params).unwrap();
+        assert_eq!(json["chan_spec"], "44/80");
+        assert_eq!(json["mac_addrs"][0], "50:ED:3C:2E:04:00");
+        assert_eq!(
+            serde_json::from_value::<Params>(json.clone()).unwrap(),
+            params
+        );
+
+        let mut invalid = json;
+        invalid["mac_addrs"] = serde_json::json!(vec!["00:00:00:00:00:00"; 5]);
+        assert!(serde_json::from_value::<Params>(invalid).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn chanspec_serde() {
+        for value in 0..=u16::MAX {
+            let Ok(cs) = ChanSpec::try_from(value) else {
+                continue;
+            };
+
+            let json = serde_json::to_string(&cs).unwrap();
+            assert_eq!(
+                serde_json::from_str::<ChanSpec>(&json).unwrap(),
+                cs,
+                "{json}"
+            );
+        }
+    }
+
+    #[test]
+    fn chanspec_from_str() {
+        assert_eq!(
+            "100/80".parse(),
+            Ok(ChanSpec::new(100, Band::Band5G, Bandwidth::Bw80).unwrap())
+        );
+        assert_eq!(
+            "5g157".parse(),
+            Ok(ChanSpec::new(157, Band::Band5G, Bandwidth::Bw20).unwrap())
+        );
+        assert_eq!(
+            "36/30".parse::<ChanSpec>(),
+            Err(ParseChanSpecError::InvalidBandwidth)
+        );
+        assert_eq!(
+            "3g36".parse::<ChanSpec>(),
+            Err(ParseChanSpecError::InvalidBand)
+        );
+        assert_eq!(
+            "38/80".parse::<ChanSpec>(),
+            Err(ParseChanSpecError::InvalidChannel)
+        );
+        assert_eq!(
+            "36q".parse::<ChanSpec>(),
+            Err(ParseChanSpecError::Malformed)
+        );
+        assert_eq!(
+            "36l".parse(),
+            Ok(ChanSpec::new(36, Band::Band5G, Bandwidth::Bw40).unwrap())
+        );
+        assert_eq!(
+            "36u".parse::<ChanSpec>(),
+            Err(ParseChanSpecError::InvalidChannel)
+        );
+        assert_eq!(
+            "1u".parse::<ChanSpec>(),
+            Err(ParseChanSpecError::InvalidChannel)
+        );
+        assert_eq!("13u".parse::<ChanSpec>().map(|cs| cs.center()), Ok(11));
+        assert_eq!(
+            "36l/40".parse::<ChanSpec>(),
+            Err(ParseChanSpecError::Malformed)
+        );
+    }
 }
@@ -7,7 +7,7 @@ use rustfft::Fft;
 use uom::si::f64::Time;
 
 use crate::{
-    frame::Frame,
+    frame::{Frame, Scalar},
     ieee80211::{subcarrier_lambda, Bandwidth},
     params::ChanSpec,
 };
@@ -17,18 +17,52 @@ use crate::{
 /// Each Wi-Fi frame generates multiple CSI frames, one for each
 /// spatial stream. This struct contains all CSI frames for a single
 /// Wi-Fi frame.
+///
+/// See [`Scalar`] for the supported value types.
 #[derive(Debug, Clone)]
-pub struct WifiCsi {
-    frames: [[Option<Array1<Complex<f64>>>; 4]; 4],
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T: serde::Serialize",
+        deserialize = "T: serde::Deserialize<'de> + num_traits::Num + Clone"
+    ))
+)]
+pub struct WifiCsi<T = f64> {
+    frames: [[Option<Array1<Complex<T>>>; 4]; 4],
     /// See the documentation for [`ChanSpec`].
     pub chan_spec: ChanSpec,
     /// Received signal strength indicator (dBi).
     pub rssi: i8,
 }
 
-impl WifiCsi {
+impl<T> WifiCsi<T> {
+    /// Creates a `WifiCsi` without any CSI frames.
+    pub fn new(chan_spec: ChanSpec, rssi: i8) -> Self {
+        Self {
+            frames: Default::default(),
+            chan_spec,
+            rssi,
+        }
+    }
+
+    /// Sets the CSI frame for a given core and spatial stream, returning
+    /// the previous one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `core` or `spatial` is not less than 4.
+    pub fn insert(
+        &mut self,
+        core: usize,
+        spatial: usize,
+        csi: Array1<Complex<T>>,
+    ) -> Option<Array1<Complex<T>>> {
+        self.frames[core][spatial].replace(csi)
+    }
+
     /// Returns the CSI frame for a given core and spatial stream.
-    pub fn get(&self, core: usize, spatial: usize) -> Option<&Array1<Complex<f64>>> {
+    pub fn get(&self, core: usize, spatial: usize) -> Option<&Array1<Complex<T>>> {
         self.frames[core][spatial].as_ref()
     }
 }
@@ -36,7 +70,7 @@ impl WifiCsi {
 /// Groups CSI frames by Wi-Fi frame.
 ///
 /// ```
-/// # let mut frames = std::iter::empty();
+/// # let mut frames = std::iter::empty::<csi::frame::Frame>();
 /// let mut groups = vec![];
 /// let mut grouper = csi::proc::FrameGrouper::new();
 ///
@@ -50,10 +84,16 @@ impl WifiCsi {
 ///     groups.push(group);
 /// }
 /// ```
-#[derive(Debug, Clone, Default)]
-pub struct FrameGrouper(Option<(WifiCsi, u16)>);
+#[derive(Debug, Clone)]
+pub struct FrameGrouper<T = f64>(Option<(WifiCsi<T>, u16)>);
 
-impl FrameGrouper {
+impl<T> Default for FrameGrouper<T> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
+impl<T> FrameGrouper<T> {
     /// Creates a new `FrameGrouper`.
     pub fn new() -> Self {
         Self::default()
@@ -66,31 +106,17 @@ impl FrameGrouper {
     /// Adds a CSI frame to the grouper.
     ///
     /// Returns `Some` if the grouper is full and should be yielded.
-    pub fn add(&mut self, frame: Frame) -> Option<WifiCsi> {
+    pub fn add(&mut self, frame: Frame<T>) -> Option<WifiCsi<T>> {
         let ret = if Some(frame.seq_cnt) != self.seq_cnt() {
             let group = self.take();
-            self.0 = Some((
-                WifiCsi {
-                    frames: [
-                        [None, None, None, None],
-                        [None, None, None, None],
-                        [None, None, None, None],
-                        [None, None, None, None],
-                    ],
-                    chan_spec: frame.chan_spec,
-                    rssi: frame.rssi,
-                },
-                frame.seq_cnt,
-            ));
+            self.0 = Some((WifiCsi::new(frame.chan_spec, frame.rssi), frame.seq_cnt));
             group
         } else {
             None
         };
 
         let (group, _) = self.0.as_mut().unwrap();
-        let core = frame.core as usize;
-        let spatial = frame.spatial as usize;
-        group.frames[core][spatial] = Some(frame.csi);
+        group.insert(frame.core.into(), frame.spatial.into(), frame.csi);
 
         ret
     }
@@ -99,7 +125,7 @@ impl FrameGrouper {
     ///
     /// To ensure that the last group is yielded, this method should be
     /// called after the stream of CSI frames has ended.
-    pub fn take(&mut self) -> Option<WifiCsi> {
+    pub fn take(&mut self) -> Option<WifiCsi<T>> {
         let (csi, _) = self.0.take()?;
         if csi.frames.iter().flatten().all(Option::is_none) {
             return None;
@@ -109,6 +135,10 @@ impl FrameGrouper {
     }
 }
 
+fn to_c64<T: Scalar>(z: Complex<T>) -> Complex<f64> {
+    Complex::new(z.re.to_f64(), z.im.to_f64())
+}
+
 fn phase_shift_to_angle(
     phase: &ArrayBase<impl Data<Elem = f64>, Dim<[usize; 1]>>,
     wavelength: &ArrayBase<impl Data<Elem = f64>, Dim<[usize; 1]>>,
@@ -124,15 +154,15 @@ fn phase_shift_to_angle(
 /// <img src="https://user-images.githubusercontent.com/57238941/115536641-50408100-a29a-11eb-9ee7-866e654e6969.png" width="200" />
 ///
 /// (0, 3, 1) from left to right.
-pub fn aoa(csi: &WifiCsi, d: f64) -> Option<[Array1<f64>; 2]> {
+pub fn aoa<T: Scalar>(csi: &WifiCsi<T>, d: f64) -> Option<[Array1<f64>; 2]> {
     // https://user-images.githubusercontent.com/57238941/115536641-50408100-a29a-11eb-9ee7-866e654e6969.png
     const LEFT_ANTENNA: usize = 0;
     const CENTER_ANTENNA: usize = 3;
     const RIGHT_ANTENNA: usize = 1;
 
-    let a0 = csi.get(RIGHT_ANTENNA, 0)?.map(|z| z.arg());
-    let a1 = csi.get(CENTER_ANTENNA, 0)?.map(|z| z.arg());
-    let a2 = csi.get(LEFT_ANTENNA, 0)?.map(|z| z.arg());
+    let a0 = csi.get(RIGHT_ANTENNA, 0)?.map(|&z| to_c64(z).arg());
+    let a1 = csi.get(CENTER_ANTENNA, 0)?.map(|&z| to_c64(z).arg());
+    let a2 = csi.get(LEFT_ANTENNA, 0)?.map(|&z| to_c64(z).arg());
 
     let wavelengths = subcarrier_lambda(csi.chan_spec.center(), csi.chan_spec.bandwidth());
 
@@ -156,12 +186,13 @@ fn tof_in_place(csi: &mut [Complex<f64>], bandwidth: Bandwidth) -> Time {
 }
 
 /// Calculate the time of flight (ToF) of a Wi-Fi frame.
-pub fn tof(csi: &WifiCsi) -> Vec<Time> {
+pub fn tof<T: Scalar>(csi: &WifiCsi<T>) -> Vec<Time> {
     let mut tofs = vec![];
 
     for core in 0..4 {
         if let Some(buf) = csi.get(core, 0) {
-            tofs.push(tof_in_place(&mut buf.to_vec(), csi.chan_spec.bandwidth()));
+            let mut buf = buf.iter().map(|&z| to_c64(z)).collect::<Vec<_>>();
+            tofs.push(tof_in_place(&mut buf, csi.chan_spec.bandwidth()));
         }
     }
 
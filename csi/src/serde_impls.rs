@@ -0,0 +1,126 @@
+//! Serde implementations for types that are more readable as strings.
+//!
+//! Human-readable formats (JSON, TOML, ...) get chanspecs like `"36/80"` and
+//! MAC addresses like `"50:ED:3C:2E:04:00"`, while binary formats get the
+//! compact wire representation.
+
+use alloc::{string::String, vec::Vec};
+use core::{fmt::Display, str::FromStr};
+
+use macaddr::MacAddr6;
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::params::{ChanSpec, Cores, Params, ParamsError, SpatialStreams};
+
+fn deserialize_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(D::Error::custom)
+}
+
+impl Serialize for ChanSpec {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            self.as_u16().serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ChanSpec {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserialize_str(deserializer)
+        } else {
+            u16::deserialize(deserializer)?
+                .try_into()
+                .map_err(D::Error::custom)
+        }
+    }
+}
+
+/// The fields of [`Params`], which are deserialized into this and then
+/// validated by [`Params::builder`].
+#[derive(Deserialize)]
+pub(crate) struct RawParams {
+    chan_spec: ChanSpec,
+    csi_collect: bool,
+    cores: Cores,
+    spatial_streams: SpatialStreams,
+    first_pkt_byte: Option<u8>,
+    #[serde(with = "mac_addrs")]
+    mac_addrs: Vec<MacAddr6>,
+    delay_us: u16,
+}
+
+impl TryFrom<RawParams> for Params {
+    type Error = ParamsError;
+
+    fn try_from(raw: RawParams) -> Result<Self, Self::Error> {
+        let mut builder = Params::builder(raw.chan_spec)
+            .csi_collect(raw.csi_collect)
+            .cores(raw.cores)
+            .spatial_streams(raw.spatial_streams)
+            .delay_us(raw.delay_us);
+
+        if let Some(first_pkt_byte) = raw.first_pkt_byte {
+            builder = builder.first_pkt_byte(first_pkt_byte);
+        }
+        for mac_addr in raw.mac_addrs {
+            builder = builder.mac_addr(mac_addr);
+        }
+
+        builder.build()
+    }
+}
+
+/// `#[serde(with = "mac_addr")]` for [`macaddr::MacAddr6`].
+pub mod mac_addr {
+    use macaddr::MacAddr6;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(mac: &MacAddr6, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(mac)
+        } else {
+            mac.into_array().serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<MacAddr6, D::Error> {
+        if deserializer.is_human_readable() {
+            super::deserialize_str(deserializer)
+        } else {
+            <[u8; 6]>::deserialize(deserializer).map(MacAddr6::from)
+        }
+    }
+}
+
+/// `#[serde(with = "mac_addrs")]` for `Vec<MacAddr6>`.
+pub mod mac_addrs {
+    use alloc::vec::Vec;
+
+    use macaddr::MacAddr6;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(transparent)]
+    struct Wrapper(#[serde(with = "super::mac_addr")] MacAddr6);
+
+    pub fn serialize<S: Serializer>(macs: &[MacAddr6], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(macs.iter().map(|&mac| Wrapper(mac)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<MacAddr6>, D::Error> {
+        let macs = Vec::<Wrapper>::deserialize(deserializer)?;
+        Ok(macs.into_iter().map(|Wrapper(mac)| mac).collect())
+    }
+}
@@ -0,0 +1,240 @@
+//! Pure-Rust drop-in replacement for Nexmon's `makecsiparams`.
+
+use std::io::Write;
+
+use anyhow::{bail, Context};
+use base64::{display::Base64Display, engine::general_purpose::STANDARD};
+use clap::Parser;
+use csi::ieee80211::{Band, Bandwidth};
+use csi::params::{ChanSpec, Cores, Params, SpatialStreams};
+use macaddr::MacAddr6;
+
+/// Parse an integer like `strtol(s, &endptr, 0)`, i.e. with an optional
+/// `0x` (hexadecimal) or `0` (octal) prefix.
+fn parse_int<T: TryFrom<u64>>(s: &str) -> Result<T, String> {
+    let n = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16)
+    } else if s.len() > 1 && s.starts_with('0') {
+        u64::from_str_radix(&s[1..], 8)
+    } else {
+        s.parse()
+    }
+    .map_err(|e| e.to_string())?;
+
+    T::try_from(n).map_err(|_| format!("{s} is out of range"))
+}
+
+fn parse_cores(s: &str) -> Result<Cores, String> {
+    Cores::from_bits(parse_int(s)?).ok_or_else(|| "invalid core mask".to_owned())
+}
+
+fn parse_spatial_streams(s: &str) -> Result<SpatialStreams, String> {
+    SpatialStreams::from_bits(parse_int(s)?).ok_or_else(|| "invalid nss mask".to_owned())
+}
+
+/// Generate CSI collection parameters for nexutil.
+#[derive(Debug, Parser)]
+#[command(name = "makecsiparams")]
+struct Args {
+    /// Enable/disable CSI collection (0 = disable)
+    #[arg(short = 'e', default_value = "1", value_parser = parse_int::<u8>)]
+    enable: u8,
+    /// Channel specification <channel>/<bandwidth> (required unless
+    /// collection is disabled)
+    #[arg(short = 'c')]
+    chan_spec: Option<ChanSpec>,
+    /// Bitmask with cores where to activate capture
+    /// (e.g., 0x5 = 0b0101 set core 0 and 2)
+    #[arg(short = 'C', value_parser = parse_cores)]
+    cores: Option<Cores>,
+    /// Bitmask with spatial streams to capture
+    /// (e.g., 0x7 = 0b0111 capture first 3 ss)
+    #[arg(short = 'N', value_parser = parse_spatial_streams)]
+    spatial_streams: Option<SpatialStreams>,
+    /// Filter on this source MAC address (up to four, comma separated)
+    #[arg(short = 'm', value_delimiter = ',')]
+    mac_addrs: Vec<MacAddr6>,
+    /// Filter frames starting with byte
+    #[arg(short = 'b', value_parser = parse_int::<u8>)]
+    byte: Option<u8>,
+    /// Delay in µs after each CSI operation (really needed for 3x4, 4x3
+    /// and 4x4 configurations, without it is enforced automatically)
+    #[arg(short = 'd', value_parser = parse_int::<u16>)]
+    delay_us: Option<u16>,
+    /// Generate raw output (no base64)
+    #[arg(short = 'r')]
+    raw: bool,
+}
+
+impl Args {
+    fn params(&self, chan_spec: ChanSpec) -> anyhow::Result<Params> {
+        let mut builder = Params::builder(chan_spec).csi_collect(self.enable != 0);
+
+        if let Some(cores) = self.cores {
+            builder = builder.cores(cores);
+        }
+        if let Some(spatial_streams) = self.spatial_streams {
+            builder = builder.spatial_streams(spatial_streams);
+        }
+        for &mac_addr in &self.mac_addrs {
+            builder = builder.mac_addr(mac_addr);
+        }
+        if let Some(byte) = self.byte {
+            builder = builder.first_pkt_byte(byte);
+        }
+
+        let mut params = builder.build().context("invalid parameters")?;
+        // like the C tool, use an explicit delay as is, even if it is shorter
+        // than default_delay_us
+        if let Some(delay_us) = self.delay_us {
+            params.delay_us = delay_us;
+        }
+
+        Ok(params)
+    }
+
+    /// Encode the parameters like the C tool, which writes the enable value
+    /// as is and leaves the chanspec zero if collection is disabled and no
+    /// channel is given. [`Params`] can represent neither, so the encoded
+    /// bytes are patched.
+    fn to_bytes(&self) -> anyhow::Result<[u8; 34]> {
+        let mut bytes = match self.chan_spec {
+            Some(chan_spec) => self.params(chan_spec)?.to_bytes()?,
+            None if self.enable == 0 => {
+                // any chanspec does, it is overwritten
+                let chan_spec = ChanSpec::new(1, Band::Band2G, Bandwidth::Bw20).unwrap();
+                let mut bytes = self.params(chan_spec)?.to_bytes()?;
+                bytes[0..2].fill(0);
+                bytes
+            }
+            None => bail!("no channel given"),
+        };
+        bytes[2] = self.enable;
+
+        Ok(bytes)
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let bytes = args.to_bytes()?;
+
+    if args.raw {
+        std::io::stdout().write_all(&bytes)?;
+    } else {
+        println!("{}", Base64Display::new(&bytes, &STANDARD));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{display::Base64Display, engine::general_purpose::STANDARD};
+    use clap::Parser;
+    use proptest::prelude::*;
+
+    use super::Args;
+
+    fn rust(args: &[&str]) -> Option<String> {
+        let args =
+            Args::try_parse_from(std::iter::once("makecsiparams").chain(args.iter().copied()))
+                .ok()?;
+        let bytes = args.to_bytes().ok()?;
+        Some(format!("{}\n", Base64Display::new(&bytes, &STANDARD)))
+    }
+
+    #[test]
+    fn invalid() {
+        for args in [
+            &["-c", "36/80", "-C", "0x10", "-N", "1"][..],
+            &["-c", "36/80", "-C", "1", "-N", "0"],
+            &["-c", "36/80", "-C", "1"],
+            &["-C", "1", "-N", "1"],
+            &["-e", "2", "-C", "1", "-N", "1"],
+            &["-c", "36/30", "-C", "1", "-N", "1"],
+            &["-c", "36/80", "-C", "1", "-N", "1", "-b", "256"],
+            &[
+                "-c",
+                "36/80",
+                "-C",
+                "1",
+                "-N",
+                "1",
+                "-m",
+                "0:0:0:0:0:0,1:1:1:1:1:1,2:2:2:2:2:2,3:3:3:3:3:3,4:4:4:4:4:4",
+            ],
+        ] {
+            assert_eq!(rust(args), None, "{args:?}");
+            assert_eq!(nexmon_test::makecsiparams(args), None, "{args:?}");
+        }
+    }
+
+    #[test]
+    fn explicit_delay() {
+        let args = ["-c", "36/80", "-C", "0xf", "-N", "0xf", "-d", "0"];
+        assert!(rust(&args).is_some());
+        assert_eq!(rust(&args), nexmon_test::makecsiparams(&args));
+    }
+
+    #[test]
+    fn enable() {
+        for args in [
+            &["-e", "0"][..],
+            &["-e", "0", "-C", "1", "-N", "0xf"],
+            &["-e", "0", "-c", "36/80"],
+            &["-e", "2", "-c", "36/80", "-C", "1", "-N", "1"],
+            &["-e", "0xff", "-c", "6", "-C", "1", "-N", "1"],
+        ] {
+            assert!(rust(args).is_some(), "{args:?}");
+            assert_eq!(rust(args), nexmon_test::makecsiparams(args), "{args:?}");
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn differential(
+            enable in any::<Option<u8>>(),
+            chan_spec in prop::option::of(prop::sample::select(&[
+                "36", "36/40", "44/80", "100/160", "120/160", "149/80", "165", "5g64/40", "6", "2g11",
+            ][..])),
+            cores in 1..=0xfu8,
+            nss in 1..=0xfu8,
+            byte in any::<Option<u8>>(),
+            mac_addrs in prop::collection::vec(any::<[u8; 6]>(), 0..=4),
+            delay_us in any::<Option<u16>>(),
+        ) {
+            let mut args = vec![
+                "-C".to_owned(),
+                format!("{cores:#x}"),
+                "-N".to_owned(),
+                format!("0{nss:o}"),
+            ];
+            if let Some(enable) = enable {
+                args.extend(["-e".to_owned(), enable.to_string()]);
+            }
+            if let Some(chan_spec) = chan_spec {
+                args.extend(["-c".to_owned(), chan_spec.to_owned()]);
+            }
+            if let Some(byte) = byte {
+                args.extend(["-b".to_owned(), byte.to_string()]);
+            }
+            if !mac_addrs.is_empty() {
+                let macs = mac_addrs
+                    .into_iter()
+                    .map(|mac| macaddr::MacAddr6::from(mac).to_string())
+                    .collect::<Vec<_>>();
+                args.extend(["-m".to_owned(), macs.join(",")]);
+            }
+            if let Some(delay_us) = delay_us {
+                args.extend(["-d".to_owned(), delay_us.to_string()]);
+            }
+
+            let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+            let rust = rust(&args);
+            // a channel is only required if collection is enabled
+            prop_assert_eq!(rust.is_some(), chan_spec.is_some() || enable == Some(0));
+            prop_assert_eq!(rust, nexmon_test::makecsiparams(&args));
+        }
+    }
+}
@@ -9,7 +9,7 @@ void unpack_float_acphy(int nbits, int autoscale, int shft,
 {
 	int e_p, maxbit, e, i, e_zero, sgn;
 	int n_out, e_shift;
-	int8_t He[256];
+	int8_t He[512]; // 256 in upstream, which overflows at 160 MHz
 	int32_t vi, vq, *pOut;
 	uint32_t x, iq_mask, e_mask, sgnr_mask, sgni_mask;
 
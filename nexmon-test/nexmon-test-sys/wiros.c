@@ -1,4 +1,5 @@
 #include <stdint.h>
+#include <string.h>
 
 //111111 - exponent of 
 const uint32_t e_mask = (1<<6)-1;
@@ -70,9 +71,12 @@ void wiros_parse_csi(const int n_sub, const uint32_t *csi, double *r_out, double
 	c_r |= ((uint64_t)r_exp)<<52;
 	c_i |= ((uint64_t)i_exp)<<52;
 
-	//place doubles
-	r_out[i] = c_r;
-	i_out[i] = c_i;
+	//place doubles (c_r and c_i are bit patterns, not integer values)
+	//diverges from upstream, which assigns r_out[i] = c_r. That converts
+	//the integer value of the pattern instead of reinterpreting its bits,
+	//so every output would be a huge integer instead of the encoded value
+	memcpy(&r_out[i], &c_r, sizeof(double));
+	memcpy(&i_out[i], &c_i, sizeof(double));
 
   }
 }
@@ -0,0 +1,102 @@
+//! Compares the Rust CSI decoder against the C decoders on pcap files and
+//! random input.
+
+use std::{path::PathBuf, process::ExitCode};
+
+use anyhow::Context;
+use clap::Parser;
+use csi::{frame::FrameRef, ieee80211::Bandwidth};
+use nexmon_test::diff::{compare, Decoder};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+#[derive(Debug, Parser)]
+struct Args {
+    /// Nexmon CSI captures to compare.
+    pcaps: Vec<PathBuf>,
+    /// C decoder to compare against. Can be repeated (default: acphy, as
+    /// wiros always mismatches on mantissas of 0 and 1).
+    #[arg(short, long)]
+    decoder: Vec<Decoder>,
+    /// Number of random frames to compare for each bandwidth.
+    #[arg(short, long, default_value_t = 0)]
+    random: usize,
+    /// Seed for the random frames.
+    #[arg(short, long, default_value_t = 0)]
+    seed: u64,
+    /// Maximum number of mismatches to print per frame.
+    #[arg(short, long, default_value_t = 1)]
+    max: usize,
+}
+
+fn main() -> anyhow::Result<ExitCode> {
+    let args = Args::parse();
+    let decoders = if args.decoder.is_empty() {
+        vec![Decoder::Acphy]
+    } else {
+        args.decoder.clone()
+    };
+
+    let mut frames = 0;
+    let mut failed = 0;
+    let mut check = |name: &dyn Fn() -> String, csi: &[u8]| {
+        frames += 1;
+        let mut ok = true;
+        for &decoder in &decoders {
+            let mismatches = compare(decoder, csi);
+            if !mismatches.is_empty() {
+                ok = false;
+                println!("{}: {} mismatching words", name(), mismatches.len());
+                for m in mismatches.iter().take(args.max) {
+                    println!("  {m}");
+                }
+            }
+        }
+        if !ok {
+            failed += 1;
+        }
+    };
+
+    for path in &args.pcaps {
+        let b = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        let packets = nexmon_test::pcap::packets(&b)
+            .with_context(|| format!("reading {}", path.display()))?;
+
+        for (i, packet) in packets.into_iter().enumerate() {
+            let frame = match FrameRef::from_slice(packet) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    eprintln!("{} #{i}: skipping: {e}", path.display());
+                    continue;
+                }
+            };
+            let bw = frame.chan_spec.bandwidth().mhz();
+            check(
+                &|| format!("{} #{i} ({bw} MHz)", path.display()),
+                frame.raw_csi(),
+            );
+        }
+    }
+
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    for bw in [
+        Bandwidth::Bw20,
+        Bandwidth::Bw40,
+        Bandwidth::Bw80,
+        Bandwidth::Bw160,
+    ] {
+        for i in 0..args.random {
+            let csi = (0..bw.nsub_pow2())
+                .flat_map(|_| rng.gen::<u32>().to_le_bytes())
+                .collect::<Vec<_>>();
+            check(&|| format!("random #{i} ({} MHz)", bw.mhz()), &csi);
+        }
+    }
+
+    println!("{failed} of {frames} frames mismatched");
+
+    Ok(if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
@@ -0,0 +1,201 @@
+//! Differential testing of [`csi::frame::unpack_csi`] against the C
+//! decoders.
+
+use std::{fmt, str::FromStr};
+
+use num_complex::Complex;
+
+use crate::{acphy, wiros};
+
+/// A C reference decoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decoder {
+    /// `unpack_float_acphy` from the Nexmon CSI utilities. See [`acphy`].
+    Acphy,
+    /// The WiROS CSI parser. See [`wiros`].
+    ///
+    /// WiROS treats the exponent as unsigned and never wraps shifts
+    /// around, so its values are compared after rescaling the Rust output.
+    /// It also decodes mantissas of 0 and 1 as ±1, which will be reported
+    /// as mismatches.
+    Wiros,
+}
+
+impl Decoder {
+    /// All decoders.
+    pub const ALL: [Self; 2] = [Self::Acphy, Self::Wiros];
+
+    fn decode(&self, csi: &[u8]) -> Vec<Complex<f64>> {
+        match self {
+            Self::Acphy => acphy(csi),
+            Self::Wiros => wiros(csi),
+        }
+    }
+
+    /// Converts a value decoded by [`csi::frame::unpack_complex`] to the
+    /// scale of this decoder.
+    fn rescale(&self, word: u32, z: Complex<f64>) -> Complex<f64> {
+        match self {
+            Self::Acphy => z,
+            Self::Wiros => {
+                // acphy: mantissa << ((exp + 42) % 32), WiROS: mantissa * 2^(exp - 41)
+                let exp = (word & 0x3f) as i32;
+                let acphy_shift = (exp + 42) % 32;
+                z * 2f64.powi(exp - 41 - acphy_shift)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Decoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Acphy => "acphy",
+            Self::Wiros => "wiros",
+        })
+    }
+}
+
+impl FromStr for Decoder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "acphy" => Ok(Self::Acphy),
+            "wiros" => Ok(Self::Wiros),
+            _ => Err(format!("unknown decoder {s:?} (expected acphy or wiros)")),
+        }
+    }
+}
+
+/// A word that the Rust and C decoders disagree on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    /// The C decoder.
+    pub decoder: Decoder,
+    /// Index of the word in the CSI buffer.
+    pub index: usize,
+    /// The packed word.
+    pub word: u32,
+    /// The Rust output, rescaled to match the C decoder.
+    pub rust: Complex<f64>,
+    /// The C output.
+    pub c: Complex<f64>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: word {} ({:#010x}): Rust decoded {}, C decoded {}",
+            self.decoder, self.index, self.word, self.rust, self.c
+        )
+    }
+}
+
+/// Decodes the packed CSI words in `csi` with both
+/// [`csi::frame::unpack_csi`] and `decoder`, returning every word they
+/// disagree on.
+pub fn compare(decoder: Decoder, csi: &[u8]) -> Vec<Mismatch> {
+    let words = csi
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()));
+
+    words
+        .zip(csi::frame::unpack_csi(csi))
+        .zip(decoder.decode(csi))
+        .enumerate()
+        .filter_map(|(index, ((word, z), c))| {
+            let rust = decoder.rescale(word, z);
+            (rust != c).then_some(Mismatch {
+                decoder,
+                index,
+                word,
+                rust,
+                c,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use csi::{frame::FrameRef, ieee80211::Bandwidth};
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Words that WiROS and acphy agree on: shifts (as seen by acphy) that
+    /// neither wrap around nor overflow, and mantissas of at least 2.
+    fn wiros_word() -> impl Strategy<Value = u32> {
+        let exp = prop_oneof![0..=10u32, 32..=42u32];
+        (any::<[bool; 2]>(), 2..1u32 << 11, 2..1u32 << 11, exp).prop_map(
+            |([re_neg, im_neg], re, im, exp)| {
+                u32::from(re_neg) << 29 | re << 18 | u32::from(im_neg) << 17 | im << 6 | exp
+            },
+        )
+    }
+
+    fn bandwidth() -> impl Strategy<Value = Bandwidth> {
+        prop_oneof![
+            Just(Bandwidth::Bw20),
+            Just(Bandwidth::Bw40),
+            Just(Bandwidth::Bw80),
+            Just(Bandwidth::Bw160),
+        ]
+    }
+
+    fn bytes(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+
+    proptest! {
+        #[test]
+        fn random_acphy(
+            words in bandwidth()
+                .prop_flat_map(|bw| prop::collection::vec(any::<u32>(), bw.nsub_pow2()))
+        ) {
+            let mismatches = compare(Decoder::Acphy, &bytes(&words));
+            prop_assert!(mismatches.is_empty(), "{}", mismatches[0]);
+        }
+
+        #[test]
+        fn random_wiros(
+            words in bandwidth()
+                .prop_flat_map(|bw| prop::collection::vec(wiros_word(), bw.nsub_pow2()))
+        ) {
+            let mismatches = compare(Decoder::Wiros, &bytes(&words));
+            prop_assert!(mismatches.is_empty(), "{}", mismatches[0]);
+        }
+    }
+
+    #[test]
+    fn wiros_quirks() {
+        // zero mantissas become ±1
+        let mismatches = compare(Decoder::Wiros, &bytes(&[0x21]));
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].c, Complex::new(1., 1.));
+    }
+
+    #[test]
+    #[ignore = "needs the captures in the repository root, which are stored with git-lfs"]
+    fn pcaps() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+
+        for name in ["test.pcap", "0deg-ish.pcap"] {
+            let b = std::fs::read(root.join(name)).unwrap();
+            assert!(
+                !b.starts_with(b"version https://git-lfs"),
+                "{name} is a git-lfs pointer, run `git lfs pull`"
+            );
+
+            for (i, packet) in crate::pcap::packets(&b).unwrap().into_iter().enumerate() {
+                let frame = FrameRef::from_slice(packet).unwrap();
+                let mismatches = compare(Decoder::Acphy, frame.raw_csi());
+                assert!(mismatches.is_empty(), "{name} #{i}: {}", mismatches[0]);
+            }
+        }
+    }
+}
@@ -1,19 +1,38 @@
-use std::ffi::{CStr, CString};
+use std::{
+    ffi::{c_char, c_int, CStr, CString},
+    ptr,
+    sync::Mutex,
+};
 
 use nexmon_test_sys::{unpack_float_acphy, wiros_parse_csi};
 use num_complex::Complex;
 
+pub mod diff;
+pub mod pcap;
+
 pub fn acphy(input: &[u8]) -> Vec<Complex<f64>> {
+    // unpack_float_acphy(10, 0, 0, 1, 12, 6, *nfftp, H, Hout);
+    acphy_with(input, 10, false, 12, 6)
+}
+
+/// Runs `unpack_float_acphy` with the given format parameters. Its `shft`
+/// argument is always overwritten, so it is not exposed.
+pub fn acphy_with(
+    input: &[u8],
+    nbits: i32,
+    autoscale: bool,
+    nman: i32,
+    nexp: i32,
+) -> Vec<Complex<f64>> {
     let nfft = input.len() / 4;
     let mut h_out = vec![0; nfft * 2];
     unsafe {
-        // unpack_float_acphy(10, 0, 0, 1, 12, 6, *nfftp, H, Hout);
         unpack_float_acphy(
-            10,
+            nbits,
+            autoscale.into(),
             0,
-            0,
-            12,
-            6,
+            nman,
+            nexp,
             nfft as _,
             input.as_ptr().cast(),
             h_out.as_mut_ptr(),
@@ -58,12 +77,68 @@ pub fn chanspec_ntoa(chspec: u16) -> String {
     cstr.to_string_lossy().into_owned()
 }
 
+extern "C" {
+    static mut optind: c_int;
+    #[cfg_attr(target_os = "macos", link_name = "__stdoutp")]
+    static mut stdout: *mut libc::FILE;
+}
+
+/// Runs the C `makecsiparams` with the given arguments, returning whatever
+/// it printed to stdout (or `None` if it printed nothing, i.e. failed).
+pub fn makecsiparams(args: &[&str]) -> Option<String> {
+    // getopt and stdout are global state
+    static LOCK: Mutex<()> = Mutex::new(());
+    let _guard = LOCK.lock().unwrap();
+
+    let args = std::iter::once("makecsiparams")
+        .chain(args.iter().copied())
+        .map(|a| CString::new(a).unwrap())
+        .collect::<Vec<_>>();
+    // strtok writes to its input, so hand out mutable copies
+    let mut args = args
+        .into_iter()
+        .map(CString::into_bytes_with_nul)
+        .collect::<Vec<_>>();
+    let mut argv = args
+        .iter_mut()
+        .map(|a| a.as_mut_ptr().cast::<c_char>())
+        .chain(std::iter::once(ptr::null_mut()))
+        .collect::<Vec<_>>();
+
+    let mut buf = ptr::null_mut();
+    let mut len = 0;
+
+    unsafe {
+        let memstream = libc::open_memstream(&mut buf, &mut len);
+        assert!(!memstream.is_null());
+        let old_stdout = std::mem::replace(&mut *ptr::addr_of_mut!(stdout), memstream);
+        optind = 1;
+        makecsiparams_sys::cli((argv.len() - 1) as _, argv.as_mut_ptr());
+        *ptr::addr_of_mut!(stdout) = old_stdout;
+        libc::fclose(memstream);
+    }
+
+    let out = unsafe {
+        let out = CStr::from_ptr(buf).to_string_lossy().into_owned();
+        libc::free(buf.cast());
+        out
+    };
+
+    if out.is_empty() {
+        None
+    } else {
+        Some(out)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use csi::{
         ieee80211::{Band, Bandwidth},
-        params::ChanSpec,
+        params::{default_delay_us, ChanSpec, Cores, Params, SpatialStreams},
     };
+    use macaddr::MacAddr6;
+    use proptest::prelude::*;
 
     use super::*;
 
@@ -141,4 +216,134 @@ mod tests {
 
         assert_eq!(a, b.as_u16());
     }
+
+    fn chan_spec() -> impl Strategy<Value = (u8, Bandwidth)> {
+        const CHANNELS: &[u8] = &[
+            36, 40, 44, 48, 52, 56, 60, 64, 100, 104, 108, 112, 116, 120, 124, 128, 132, 136, 140,
+            144, 149, 153, 157, 161,
+        ];
+
+        (
+            prop::sample::select(CHANNELS),
+            prop_oneof![
+                Just(Bandwidth::Bw20),
+                Just(Bandwidth::Bw40),
+                Just(Bandwidth::Bw80),
+                Just(Bandwidth::Bw160),
+            ],
+        )
+            .prop_filter("invalid chanspec", |&(channel, bw)| {
+                ChanSpec::new(channel, Band::Band5G, bw).is_some()
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn test_makecsiparams(
+            (channel, bw) in chan_spec(),
+            cores in 1..=0xfu8,
+            nss in 1..=0xfu8,
+            first_pkt_byte in any::<Option<u8>>(),
+            mac_addrs in prop::collection::vec(any::<[u8; 6]>(), 0..=4),
+            delay_us in any::<Option<u16>>(),
+        ) {
+            let mac_addrs = mac_addrs.into_iter().map(MacAddr6::from).collect::<Vec<_>>();
+            let cores = Cores::from_bits_truncate(cores);
+            let spatial_streams = SpatialStreams::from_bits_truncate(nss);
+
+            let mut args = vec![
+                "-c".to_owned(),
+                format!("{channel}/{}", bw.mhz()),
+                "-C".to_owned(),
+                format!("{:#x}", cores.bits()),
+                "-N".to_owned(),
+                format!("{:#x}", spatial_streams.bits()),
+            ];
+            if let Some(byte) = first_pkt_byte {
+                args.extend(["-b".to_owned(), byte.to_string()]);
+            }
+            if !mac_addrs.is_empty() {
+                let macs = mac_addrs.iter().map(ToString::to_string).collect::<Vec<_>>();
+                args.extend(["-m".to_owned(), macs.join(",")]);
+            }
+            if let Some(delay) = delay_us {
+                args.extend(["-d".to_owned(), delay.to_string()]);
+            }
+
+            let expected = Params {
+                chan_spec: ChanSpec::new(channel, Band::Band5G, bw).unwrap(),
+                csi_collect: true,
+                cores,
+                spatial_streams,
+                first_pkt_byte,
+                mac_addrs,
+                delay_us: delay_us.unwrap_or(default_delay_us(cores, spatial_streams)),
+            };
+
+            let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+            let c = makecsiparams(&args).unwrap();
+            let parsed = c.parse::<Params>().unwrap();
+
+            prop_assert_eq!(&parsed, &expected);
+            prop_assert_eq!(parsed.to_string(), c.trim());
+        }
+
+        #[test]
+        fn test_unpack_csi_into(
+            (words, bw) in prop_oneof![
+                Just(Bandwidth::Bw20),
+                Just(Bandwidth::Bw40),
+                Just(Bandwidth::Bw80),
+                Just(Bandwidth::Bw160),
+            ]
+            .prop_flat_map(|bw| (prop::collection::vec(any::<u32>(), bw.nsub_pow2()), Just(bw)))
+        ) {
+            let input = words.iter().flat_map(|w| w.to_le_bytes()).collect::<Vec<_>>();
+
+            let mut out = vec![Complex::<i32>::default(); bw.nsub_pow2()];
+            csi::frame::unpack_csi_into(&input, &mut out);
+            let out = out.iter().map(|z| Complex::new(z.re.into(), z.im.into()));
+
+            for ((a, b), word) in acphy(&input).into_iter().zip(out).zip(&words) {
+                prop_assert_eq!(a, b, "{}: word {:#010x}", bw.mhz(), word);
+            }
+        }
+
+        #[test]
+        fn test_wiros(words in prop::collection::vec(any::<u32>(), 1..=512)) {
+            let input = words.iter().flat_map(|w| w.to_le_bytes()).collect::<Vec<_>>();
+            let rust = csi::frame::unpack_csi_wiros(&input);
+
+            for ((a, b), word) in wiros(&input).into_iter().zip(rust).zip(&words) {
+                prop_assert_eq!(a, b, "word {:#010x}", word);
+            }
+        }
+
+        #[test]
+        fn test_float_format(
+            words in prop::collection::vec(any::<u32>(), 1..=512),
+            nbits in 0..=16,
+            autoscale in any::<bool>(),
+            (nman, nexp) in (2..=15u32, 1..=8u32).prop_filter("too wide", |&(nman, nexp)| {
+                nexp + 2 * nman <= 32
+            }),
+        ) {
+            let input = words.iter().flat_map(|w| w.to_le_bytes()).collect::<Vec<_>>();
+            let format = csi::frame::FloatFormat {
+                nbits,
+                autoscale,
+                shift: None,
+                nman,
+                nexp,
+            };
+
+            let mut out = vec![Complex::<f64>::default(); words.len()];
+            format.unpack_into(&input, &mut out);
+            let c = acphy_with(&input, nbits, autoscale, nman as _, nexp as _);
+
+            for ((a, b), word) in c.into_iter().zip(out).zip(&words) {
+                prop_assert_eq!(a, b, "{:?}: word {:#010x}", format, word);
+            }
+        }
+    }
 }
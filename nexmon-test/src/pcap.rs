@@ -0,0 +1,57 @@
+//! Minimal reader for classic (libpcap) capture files.
+
+/// Error returned when reading a pcap file.
+#[derive(Debug, Clone, thiserror::Error, PartialEq, Eq)]
+pub enum Error {
+    /// The file does not start with a pcap magic number.
+    #[error("not a pcap file")]
+    NotAPcap,
+    /// The file ends in the middle of a header or packet.
+    #[error("truncated pcap file")]
+    Truncated,
+}
+
+/// Returns the captured packets in a pcap file, in both byte orders and
+/// with either microsecond or nanosecond timestamps.
+pub fn packets(b: &[u8]) -> Result<Vec<&[u8]>, Error> {
+    let magic = b.get(..4).ok_or(Error::NotAPcap)?;
+    let read_u32: fn([u8; 4]) -> u32 = match magic {
+        [0xd4, 0xc3, 0xb2, 0xa1] | [0x4d, 0x3c, 0xb2, 0xa1] => u32::from_le_bytes,
+        [0xa1, 0xb2, 0xc3, 0xd4] | [0xa1, 0xb2, 0x3c, 0x4d] => u32::from_be_bytes,
+        _ => return Err(Error::NotAPcap),
+    };
+
+    let mut rest = b.get(24..).ok_or(Error::Truncated)?;
+    let mut packets = Vec::new();
+
+    while !rest.is_empty() {
+        let header = rest.get(..16).ok_or(Error::Truncated)?;
+        let incl_len = read_u32(header[8..12].try_into().unwrap()) as usize;
+        let data = rest.get(16..16 + incl_len).ok_or(Error::Truncated)?;
+        packets.push(data);
+        rest = &rest[16 + incl_len..];
+    }
+
+    Ok(packets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_packets() {
+        let mut b = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        b.extend([0; 16]);
+        for data in [&b"abc"[..], b"", b"de"] {
+            b.extend([0; 8]);
+            b.extend((data.len() as u32).to_le_bytes());
+            b.extend((data.len() as u32).to_le_bytes());
+            b.extend(data);
+        }
+
+        assert_eq!(packets(&b), Ok(vec![&b"abc"[..], b"", b"de"]));
+        assert_eq!(packets(&b[..b.len() - 1]), Err(Error::Truncated));
+        assert_eq!(packets(b"version https://git-lfs"), Err(Error::NotAPcap));
+    }
+}
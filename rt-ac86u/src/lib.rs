@@ -31,6 +31,8 @@ impl RtAc86u {
     }
 
     pub async fn configure(&self, params: &csi::params::Params, rmmod: bool) -> anyhow::Result<()> {
+        params.validate()?;
+
         if rmmod {
             self.exec("/sbin/rmmod dhd.ko").await?;
             self.exec("/sbin/insmod /jffs/dhd.ko").await?;
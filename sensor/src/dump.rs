@@ -0,0 +1,293 @@
+//! Recording of the raw capture while it is being processed.
+//!
+//! [`tee_pcap`] writes a pcap stream to disk and passes the same bytes on
+//! for decoding. The recording can be split into several files, each a
+//! valid pcap file of its own, by size or by duration.
+
+use std::{
+    io,
+    path::PathBuf,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use async_stream::try_stream;
+use futures::{stream::BoxStream, Stream, StreamExt};
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt, ReadBuf},
+    time::Instant,
+};
+
+const HEADER_LEN: usize = 24;
+const RECORD_HEADER_LEN: usize = 16;
+/// tcpdump captures at most 262144 bytes of a packet, even with `-s 0`
+/// (libpcap's `MAXIMUM_SNAPLEN`), so larger packets mean the stream is
+/// corrupt.
+const MAX_PACKET_LEN: u32 = 262_144;
+
+/// When to start a new file.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rotation {
+    /// Largest size of a file in bytes, unless it holds a single packet.
+    pub size: Option<u64>,
+    /// Time after which a new file is started, checked when a packet
+    /// arrives.
+    pub duration: Option<Duration>,
+}
+
+impl Rotation {
+    fn enabled(&self) -> bool {
+        self.size.is_some() || self.duration.is_some()
+    }
+}
+
+/// Where to dump a capture.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dump {
+    /// Path of the file. With rotation, the files are numbered by inserting
+    /// a counter before the extension, as in `capture.0000.pcap`.
+    pub path: PathBuf,
+    /// When to start a new file.
+    pub rotation: Rotation,
+}
+
+impl Dump {
+    fn path(&self, index: usize) -> PathBuf {
+        if !self.rotation.enabled() {
+            return self.path.clone();
+        }
+
+        let mut name = self.path.file_stem().unwrap_or_default().to_owned();
+        name.push(format!(".{index:04}"));
+        if let Some(ext) = self.path.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        self.path.with_file_name(name)
+    }
+}
+
+/// The file currently written to.
+struct Writer {
+    dump: Dump,
+    /// Global header, written at the start of every file.
+    header: Vec<u8>,
+    file: File,
+    index: usize,
+    size: u64,
+    opened: Instant,
+}
+
+impl Writer {
+    async fn create(dump: Dump, header: Vec<u8>, index: usize) -> io::Result<Self> {
+        let path = dump.path(index);
+        let mut file = File::create(&path).await?;
+        file.write_all(&header).await?;
+        tracing::info!("dumping to {}", path.display());
+
+        Ok(Self {
+            dump,
+            size: header.len() as u64,
+            header,
+            file,
+            index,
+            opened: Instant::now(),
+        })
+    }
+
+    async fn write(&mut self, record: &[u8]) -> io::Result<()> {
+        let rotation = self.dump.rotation;
+        let empty = self.size == self.header.len() as u64;
+        let full = rotation
+            .size
+            .is_some_and(|size| self.size + record.len() as u64 > size);
+        let expired = rotation
+            .duration
+            .is_some_and(|duration| self.opened.elapsed() >= duration);
+
+        if !empty && (full || expired) {
+            self.file.flush().await?;
+            *self = Self::create(self.dump.clone(), self.header.clone(), self.index + 1).await?;
+        }
+
+        self.file.write_all(record).await?;
+        self.size += record.len() as u64;
+
+        Ok(())
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Reads the global header and the packet records of a pcap stream, writing
+/// them to `dump` as they are yielded.
+fn dump_records(
+    reader: impl AsyncRead + Send + 'static,
+    dump: Dump,
+) -> impl Stream<Item = io::Result<Vec<u8>>> {
+    try_stream! {
+        let mut reader = Box::pin(reader);
+        let mut header = vec![0; HEADER_LEN];
+        reader.read_exact(&mut header).await?;
+
+        let magic = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let read_u32: fn([u8; 4]) -> u32 = match magic {
+            0xa1b2c3d4 | 0xa1b23c4d => u32::from_le_bytes,
+            0xd4c3b2a1 | 0x4d3cb2a1 => u32::from_be_bytes,
+            _ => Err(invalid("not a pcap stream".into()))?,
+        };
+
+        let mut writer = Writer::create(dump, header.clone(), 0).await?;
+        yield header;
+
+        loop {
+            let mut record = vec![0; RECORD_HEADER_LEN];
+            match reader.read_exact(&mut record).await {
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                r => r?,
+            };
+
+            let len = match read_u32(record[8..12].try_into().unwrap()) {
+                len if len <= MAX_PACKET_LEN => len,
+                len => Err(invalid(format!("packet of {len} bytes")))?,
+            };
+            record.resize(RECORD_HEADER_LEN + len as usize, 0);
+            match reader.read_exact(&mut record[RECORD_HEADER_LEN..]).await {
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                r => r?,
+            };
+
+            writer.write(&record).await?;
+            yield record;
+        }
+
+        writer.file.flush().await?;
+    }
+}
+
+/// Reads the chunks yielded by a stream.
+struct StreamReader {
+    chunks: BoxStream<'static, io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl AsyncRead for StreamReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        while self.pos == self.chunk.len() {
+            match ready!(self.chunks.poll_next_unpin(cx)) {
+                Some(chunk) => {
+                    self.chunk = chunk?;
+                    self.pos = 0;
+                }
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+
+        let n = buf.remaining().min(self.chunk.len() - self.pos);
+        buf.put_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Writes the pcap stream read from `reader` to `dump` while returning the
+/// same bytes, such as to decode a live capture and record it at the same
+/// time. Errors writing the dump are returned by the reader.
+///
+/// Only whole packets are passed on, so a packet cut short at the end of the
+/// stream is dropped.
+///
+/// ```no_run
+/// # async fn record(pcap: impl tokio::io::AsyncRead + Send + 'static) {
+/// use std::time::Duration;
+///
+/// use sensor::{
+///     dump::{tee_pcap, Dump, Rotation},
+///     read::read_wifi_csi,
+/// };
+///
+/// let dump = Dump {
+///     path: "capture.pcap".into(),
+///     rotation: Rotation {
+///         duration: Some(Duration::from_secs(3600)),
+///         ..Default::default()
+///     },
+/// };
+/// let csi = read_wifi_csi(tee_pcap(pcap, dump), false);
+/// # }
+/// ```
+pub fn tee_pcap(
+    reader: impl AsyncRead + Send + 'static,
+    dump: Dump,
+) -> impl AsyncRead + Send + Unpin + 'static {
+    StreamReader {
+        chunks: dump_records(reader, dump).boxed(),
+        chunk: vec![],
+        pos: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcap(n: u8) -> Vec<u8> {
+        let mut pcap = vec![0; HEADER_LEN];
+        pcap[..4].copy_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+        for i in 0..n {
+            let mut record = [0; RECORD_HEADER_LEN];
+            record[8..12].copy_from_slice(&100u32.to_le_bytes());
+            pcap.extend(record);
+            pcap.extend([i; 100]);
+        }
+        pcap
+    }
+
+    #[tokio::test]
+    async fn rotate_by_size() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("sensor-dump-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let dump = Dump {
+            path: dir.join("capture.pcap"),
+            rotation: Rotation {
+                size: Some((HEADER_LEN + 2 * (RECORD_HEADER_LEN + 100)) as u64),
+                duration: None,
+            },
+        };
+
+        let input = pcap(5);
+        let mut output = vec![];
+        tee_pcap(std::io::Cursor::new(input.clone()), dump.clone())
+            .read_to_end(&mut output)
+            .await?;
+        assert_eq!(output, input);
+
+        let files: Vec<_> = (0..3)
+            .map(|i| std::fs::read(dump.path(i)))
+            .collect::<Result<_, _>>()?;
+        assert!(!dump.path(3).exists());
+        std::fs::remove_dir_all(&dir)?;
+
+        assert_eq!(dir.join("capture.0001.pcap"), dump.path(1));
+        let packet = RECORD_HEADER_LEN + 100;
+        assert_eq!(files[0], input[..HEADER_LEN + 2 * packet]);
+        assert_eq!(files[1][..HEADER_LEN], input[..HEADER_LEN]);
+        assert_eq!(
+            files[1][HEADER_LEN..],
+            input[HEADER_LEN + 2 * packet..HEADER_LEN + 4 * packet]
+        );
+        assert_eq!(files[2][HEADER_LEN..], input[HEADER_LEN + 4 * packet..]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,316 @@
+//! Export of CSI recordings to [Apache Parquet](https://parquet.apache.org/).
+//!
+//! Each [`CsiRecord`] becomes one row with the columns `timestamp`
+//! (nanoseconds, UTC), `mac`, `seq`, `chanspec` and `rssi`, followed by one
+//! `core{c}_spatial{s}` column per core and spatial stream. These hold a
+//! list of `{re, im}` structs (one per subcarrier) or null if the stream was
+//! not captured.
+
+use std::{io::Write, sync::Arc};
+
+use arrow::{
+    array::{
+        ArrayRef, Float32Builder, Int8Builder, ListBuilder, StringBuilder, StructBuilder,
+        TimestampNanosecondBuilder, UInt16Builder,
+    },
+    datatypes::{DataType, Field, Fields, Schema, SchemaRef, TimeUnit},
+    record_batch::RecordBatch,
+};
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, ZstdLevel},
+    file::properties::WriterProperties,
+};
+
+use crate::read::CsiRecord;
+
+/// Number of rows buffered before they are handed to the Parquet writer.
+const BATCH_SIZE: usize = 1024;
+
+/// Number of rows per row group. The Parquet writer keeps a whole row group
+/// in memory, so this bounds its memory use.
+const ROW_GROUP_SIZE: usize = 16 * BATCH_SIZE;
+
+const TIMEZONE: &str = "UTC";
+
+fn complex_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("re", DataType::Float32, false),
+        Field::new("im", DataType::Float32, false),
+    ])
+}
+
+fn csi_column(core: usize, spatial: usize) -> String {
+    format!("core{core}_spatial{spatial}")
+}
+
+/// Schema of the exported files.
+pub fn schema() -> SchemaRef {
+    let mut fields = vec![
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Nanosecond, Some(TIMEZONE.into())),
+            false,
+        ),
+        Field::new("mac", DataType::Utf8, false),
+        Field::new("seq", DataType::UInt16, false),
+        Field::new("chanspec", DataType::UInt16, false),
+        Field::new("rssi", DataType::Int8, false),
+    ];
+
+    let item = Arc::new(Field::new("item", DataType::Struct(complex_fields()), true));
+    for core in 0..4 {
+        for spatial in 0..4 {
+            fields.push(Field::new(
+                csi_column(core, spatial),
+                DataType::List(item.clone()),
+                true,
+            ));
+        }
+    }
+
+    Arc::new(Schema::new(fields))
+}
+
+struct Builders {
+    timestamp: TimestampNanosecondBuilder,
+    mac: StringBuilder,
+    seq: UInt16Builder,
+    chan_spec: UInt16Builder,
+    rssi: Int8Builder,
+    /// Indexed by `4 * core + spatial`.
+    csi: Vec<ListBuilder<StructBuilder>>,
+}
+
+impl Builders {
+    fn new() -> Self {
+        Self {
+            timestamp: TimestampNanosecondBuilder::new().with_timezone(TIMEZONE),
+            mac: StringBuilder::new(),
+            seq: UInt16Builder::new(),
+            chan_spec: UInt16Builder::new(),
+            rssi: Int8Builder::new(),
+            csi: (0..16)
+                .map(|_| {
+                    ListBuilder::new(StructBuilder::new(
+                        complex_fields(),
+                        vec![
+                            Box::new(Float32Builder::new()),
+                            Box::new(Float32Builder::new()),
+                        ],
+                    ))
+                })
+                .collect(),
+        }
+    }
+
+    fn append(&mut self, record: &CsiRecord) {
+        self.timestamp
+            .append_value(record.timestamp.as_nanos().try_into().unwrap_or(i64::MAX));
+        self.mac.append_value(record.source_mac.to_string());
+        self.seq.append_value(record.seq_cnt);
+        self.chan_spec.append_value(record.csi.chan_spec.into());
+        self.rssi.append_value(record.csi.rssi);
+
+        for (i, list) in self.csi.iter_mut().enumerate() {
+            let Some(values) = record.csi.get(i / 4, i % 4) else {
+                list.append(false);
+                continue;
+            };
+
+            let items = list.values();
+            for z in values {
+                items
+                    .field_builder::<Float32Builder>(0)
+                    .unwrap()
+                    .append_value(z.re as f32);
+                items
+                    .field_builder::<Float32Builder>(1)
+                    .unwrap()
+                    .append_value(z.im as f32);
+                items.append(true);
+            }
+            list.append(true);
+        }
+    }
+
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(self.timestamp.finish()),
+            Arc::new(self.mac.finish()),
+            Arc::new(self.seq.finish()),
+            Arc::new(self.chan_spec.finish()),
+            Arc::new(self.rssi.finish()),
+        ];
+        columns.extend(
+            self.csi
+                .iter_mut()
+                .map(|list| Arc::new(list.finish()) as ArrayRef),
+        );
+        columns
+    }
+}
+
+/// Writes [`CsiRecord`]s to a Parquet file as they arrive, so that
+/// recordings of any length can be exported with bounded memory.
+///
+/// ```no_run
+/// # async fn export() -> anyhow::Result<()> {
+/// use futures::TryStreamExt;
+/// use sensor::{export::ParquetWriter, read::read_csi_records};
+///
+/// let pcap = tokio::fs::File::open("capture.pcap").await?;
+/// let mut records = std::pin::pin!(read_csi_records(pcap, false));
+/// let mut writer = ParquetWriter::new(std::fs::File::create("capture.parquet")?)?;
+///
+/// while let Some(record) = records.try_next().await? {
+///     writer.push(&record)?;
+/// }
+/// writer.finish()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ParquetWriter<W: Write + Send> {
+    writer: ArrowWriter<W>,
+    schema: SchemaRef,
+    builders: Builders,
+    rows: usize,
+}
+
+impl<W: Write + Send> ParquetWriter<W> {
+    /// Creates a writer with zstd compression.
+    pub fn new(w: W) -> anyhow::Result<Self> {
+        let props = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .set_max_row_group_size(ROW_GROUP_SIZE)
+            .build();
+        let schema = schema();
+
+        Ok(Self {
+            writer: ArrowWriter::try_new(w, schema.clone(), Some(props))?,
+            schema,
+            builders: Builders::new(),
+            rows: 0,
+        })
+    }
+
+    /// Appends a row.
+    pub fn push(&mut self, record: &CsiRecord) -> anyhow::Result<()> {
+        self.builders.append(record);
+        self.rows += 1;
+
+        if self.rows >= BATCH_SIZE {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+
+        let batch = RecordBatch::try_new(self.schema.clone(), self.builders.finish())?;
+        self.writer.write(&batch)?;
+        self.rows = 0;
+
+        Ok(())
+    }
+
+    /// Writes the remaining rows and the file footer, returning the
+    /// underlying writer.
+    pub fn finish(mut self) -> anyhow::Result<W> {
+        self.flush()?;
+        Ok(self.writer.into_inner()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use arrow::{
+        array::{Array, AsArray},
+        datatypes::{Float32Type, TimestampNanosecondType, UInt16Type},
+    };
+    use csi::{
+        ieee80211::{Band, Bandwidth},
+        params::ChanSpec,
+        proc::WifiCsi,
+    };
+    use macaddr::MacAddr6;
+    use ndarray::array;
+    use num_complex::Complex;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use super::*;
+
+    fn record(i: u16) -> CsiRecord {
+        let chan_spec = ChanSpec::new(36, Band::Band5G, Bandwidth::Bw20).unwrap();
+        let mut csi = WifiCsi::new(chan_spec, -50);
+        let x = f64::from(i);
+        csi.insert(0, 0, array![Complex::new(x, -x), Complex::new(0.5, 2.)]);
+        if i.is_multiple_of(2) {
+            csi.insert(1, 2, array![Complex::new(1., x)]);
+        }
+
+        CsiRecord {
+            timestamp: Duration::from_millis(u64::from(i)),
+            source_mac: MacAddr6::new(0, 1, 2, 3, 4, 5),
+            seq_cnt: i,
+            comments: vec![],
+            csi,
+        }
+    }
+
+    #[test]
+    fn read_back() -> anyhow::Result<()> {
+        let n = ROW_GROUP_SIZE as u16 + 10;
+        let mut writer = ParquetWriter::new(vec![])?;
+        for i in 0..n {
+            writer.push(&record(i))?;
+        }
+        let file = bytes::Bytes::from(writer.finish()?);
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+        assert_eq!(builder.schema(), &schema());
+        assert_eq!(builder.metadata().num_row_groups(), 2);
+
+        let mut i = 0;
+        for batch in builder.build()? {
+            let batch = batch?;
+            let column = |name| batch.column_by_name(name).unwrap();
+            let timestamp = column("timestamp").as_primitive::<TimestampNanosecondType>();
+            let seq = column("seq").as_primitive::<UInt16Type>();
+            let chan_spec = column("chanspec").as_primitive::<UInt16Type>();
+            let stream = column("core0_spatial0").as_list::<i32>();
+            let odd = column("core1_spatial2").as_list::<i32>();
+            assert_eq!(
+                column("mac").as_string::<i32>().value(0),
+                "00:01:02:03:04:05"
+            );
+            assert_eq!(column("core3_spatial3").null_count(), batch.num_rows());
+
+            for row in 0..batch.num_rows() {
+                let expected = record(i);
+                assert_eq!(timestamp.value(row), i64::from(i) * 1_000_000);
+                assert_eq!(seq.value(row), i);
+                assert_eq!(chan_spec.value(row), u16::from(expected.csi.chan_spec));
+
+                let values = stream.value(row);
+                let values = values.as_struct();
+                let re = values.column(0).as_primitive::<Float32Type>();
+                let im = values.column(1).as_primitive::<Float32Type>();
+                assert_eq!(re.values(), &[f32::from(i), 0.5]);
+                assert_eq!(im.values(), &[-f32::from(i), 2.]);
+                assert_eq!(odd.is_null(row), !i.is_multiple_of(2));
+
+                i += 1;
+            }
+        }
+        assert_eq!(i, n);
+
+        Ok(())
+    }
+}
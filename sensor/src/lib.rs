@@ -1 +1,5 @@
+pub mod dump;
+pub mod export;
 pub mod read;
+pub mod replay;
+pub mod source;
@@ -2,20 +2,20 @@ use std::{
     path::{Path, PathBuf},
     pin::pin,
     sync::Arc,
-    time::Instant,
+    time::{Duration, Instant},
 };
 
-use async_ssh2_tokio::client::{AuthMethod, Client, ServerCheckMethod};
 use atomic_counter::{AtomicCounter, RelaxedCounter};
 use clap::{Args, Parser, Subcommand};
 use csi::{
+    archive::{ArchiveWriter, Record},
     ieee80211::{Band, Bandwidth},
     params::{ChanSpec, Cores, Params, SpatialStreams},
     proc::{aoa, WifiCsi},
 };
 use egui::Vec2;
 use egui_plot::{Line, Plot, PlotPoints};
-use futures::{Stream, TryStreamExt};
+use futures::{stream::BoxStream, TryStreamExt};
 use macaddr::MacAddr6;
 
 use ndhistogram::{
@@ -24,8 +24,13 @@ use ndhistogram::{
 };
 use num_complex::{Complex, ComplexFloat};
 use rand::{rngs::StdRng, Rng, SeedableRng};
-use rt_ac86u::RtAc86u;
-use sensor::read::{read_wifi_csi, PcapSource};
+use sensor::{
+    dump::{Dump, Rotation},
+    export::ParquetWriter,
+    read::read_csi_records,
+    replay::{ReplayControl, ReplayOptions, Seek},
+    source::{Router, RouterSource, SourceOptions, SourceSpec},
+};
 use tokio::sync::mpsc;
 use uom::si::f64::Length;
 
@@ -48,6 +53,10 @@ struct App {
     antenna_spacing: f64,
     aoas: Vec<Vec<f64>>,
     distances: Vec<Length>,
+    /// Set when replaying a file.
+    replay: Option<ReplayControl>,
+    seek_time: f64,
+    seek_index: usize,
 }
 
 impl App {
@@ -60,11 +69,18 @@ impl App {
             .unwrap();
 
         let cnt = Arc::new(RelaxedCounter::new(0));
+        let control = ReplayControl::new(if args.replay_quick {
+            f64::INFINITY
+        } else {
+            args.speed
+        });
+        let replaying = args.replay.is_some() || matches!(args.source, SourceSpec::File(_));
 
         rt.spawn({
             let cnt = cnt.clone();
+            let control = control.clone();
             async move {
-                run(args, tx, &cnt).await.unwrap();
+                run(args, control, tx, &cnt).await.unwrap();
             }
         });
 
@@ -81,6 +97,9 @@ impl App {
             antenna_spacing: 0.088,
             aoas: vec![],
             distances: vec![],
+            replay: replaying.then_some(control),
+            seek_time: 0.,
+            seek_index: 0,
         }
     }
 }
@@ -134,6 +153,52 @@ impl eframe::App for App {
                 egui::Slider::new(&mut self.antenna_spacing, 0.01..=0.2).text("antenna spacing"),
             );
             ui.label(format!("{} packets", self.cnt.get()));
+
+            if let Some(control) = &self.replay {
+                ui.separator();
+
+                let mut paused = control.is_paused();
+                if ui.checkbox(&mut paused, "paused").changed() {
+                    if paused {
+                        control.pause();
+                    } else {
+                        control.resume();
+                    }
+                }
+
+                let mut speed = control.speed();
+                if speed.is_finite()
+                    && ui
+                        .add(
+                            egui::Slider::new(&mut speed, 0.1..=10.)
+                                .logarithmic(true)
+                                .text("speed"),
+                        )
+                        .changed()
+                {
+                    control.set_speed(speed);
+                }
+
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::DragValue::new(&mut self.seek_time)
+                            .clamp_range(0. ..=f64::MAX)
+                            .suffix(" s"),
+                    );
+                    if ui.button("seek").clicked() {
+                        // times beyond Duration::MAX are past the end anyway
+                        let time =
+                            Duration::try_from_secs_f64(self.seek_time).unwrap_or(Duration::MAX);
+                        control.seek(Seek::Time(time));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut self.seek_index).suffix(" packets"));
+                    if ui.button("seek").clicked() {
+                        control.seek(Seek::Index(self.seek_index));
+                    }
+                });
+            }
         });
 
         let data = if self.last {
@@ -280,21 +345,13 @@ impl eframe::App for App {
     }
 }
 
-async fn connect() -> anyhow::Result<RtAc86u> {
-    tracing::info!("connecting");
-
-    let client = Client::connect(
-        ("192.168.0.84", 22),
-        "admin",
-        AuthMethod::with_password("password"),
-        ServerCheckMethod::NoCheck,
-    )
-    .await?;
-    let client = RtAc86u::new(client);
-
-    tracing::info!("connected!");
-
-    Ok(client)
+fn router() -> Router {
+    Router {
+        host: "192.168.0.84".into(),
+        port: 22,
+        username: "admin".into(),
+        password: "password".into(),
+    }
 }
 
 const LOW: f64 = -50.;
@@ -377,11 +434,16 @@ fn std_deviation(data: &[f64]) -> Option<f64> {
     }
 }
 
-async fn run(args: RunArgs, tx: mpsc::Sender<Values>, cnt: &RelaxedCounter) -> anyhow::Result<()> {
+async fn run(
+    args: RunArgs,
+    control: ReplayControl,
+    tx: mpsc::Sender<Values>,
+    cnt: &RelaxedCounter,
+) -> anyhow::Result<()> {
     use ndhistogram::ndhistogram;
     use plotters::prelude::*;
 
-    let mut stream = pin!(get_input(&args).await?);
+    let mut stream = pin!(get_input(&args, control).await?);
     // let mut stream = stream.take(args.samples.unwrap_or(usize::MAX));
     let mut writer = args.aoa.as_ref().map(csv::Writer::from_path).transpose()?;
     let t0 = Instant::now();
@@ -503,6 +565,16 @@ enum Command {
         #[clap(short, long)]
         input: PathBuf,
     },
+    /// Convert a PCAP or pcapng recording to Parquet, or to a CSI archive
+    /// if the output has the extension `.csia`
+    Export {
+        /// PCAP or pcapng file to read
+        #[clap(short, long)]
+        input: PathBuf,
+        /// Parquet or CSI archive file to write
+        #[clap(short, long)]
+        output: PathBuf,
+    },
 }
 
 #[derive(Debug, Args)]
@@ -513,11 +585,22 @@ struct RunArgs {
     /// Remove and reinsert the dhd kernel module
     #[clap(short, long, default_value = "false")]
     rmmod: bool,
-    /// Dump PCAP data
-    #[clap(short, long)]
+    /// Also write the PCAP data captured on the router to this file
+    #[clap(short, long, conflicts_with = "replay")]
     dump: Option<PathBuf>,
-    /// PCAP input file to replay
-    #[clap(long)]
+    /// Start a new dump file when the current one would exceed this many
+    /// bytes
+    #[clap(long, value_name = "BYTES", requires = "dump")]
+    rotate_size: Option<u64>,
+    /// Start a new dump file every SECONDS
+    #[clap(long, value_name = "SECONDS", value_parser = parse_seconds, requires = "dump")]
+    rotate_time: Option<Duration>,
+    /// Where to read CSI from: router, udp[:ADDR], udp-configure[:ADDR],
+    /// file:PATH, stdin, esp32:PATH, picoscenes:PATH or synthetic[:DEGREES]
+    #[clap(long, default_value = "router")]
+    source: SourceSpec,
+    /// PCAP or pcapng input file to replay, short for --source file:PATH
+    #[clap(long, conflicts_with = "source")]
     replay: Option<PathBuf>,
     /// Dump AOA data
     #[clap(long)]
@@ -525,6 +608,18 @@ struct RunArgs {
     /// Don't add delay to replay
     #[clap(long, default_value = "false")]
     replay_quick: bool,
+    /// Replay speed factor
+    #[clap(long, default_value = "1", value_parser = parse_speed)]
+    speed: f64,
+    /// Skip the first SECONDS of the replay
+    #[clap(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    start: Option<Duration>,
+    /// End the replay SECONDS after the start of the recording
+    #[clap(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    end: Option<Duration>,
+    /// Start the replay over when it ends
+    #[clap(long = "loop")]
+    looping: bool,
     /// Number of samples to collect. If not specified, will collect indefinitely
     #[clap(short, long)]
     samples: Option<usize>,
@@ -536,42 +631,93 @@ struct RunArgs {
     skip: usize,
 }
 
+fn parse_speed(s: &str) -> anyhow::Result<f64> {
+    let speed = s.parse()?;
+    if speed > 0. {
+        Ok(speed)
+    } else {
+        anyhow::bail!("speed must be positive")
+    }
+}
+
+fn parse_seconds(s: &str) -> anyhow::Result<Duration> {
+    Ok(Duration::try_from_secs_f64(s.parse()?)?)
+}
+
 const RT_AC86U_EXTERNAL: Cores =
     Cores::from_bits_truncate(Cores::CORE0.bits() | Cores::CORE1.bits() | Cores::CORE3.bits());
 
-async fn get_input(args: &RunArgs) -> anyhow::Result<impl Stream<Item = anyhow::Result<WifiCsi>>> {
-    let (mut pcap, add_delay) = if let Some(path) = &args.replay {
-        (
-            PcapSource::File(tokio::fs::File::open(path).await?),
-            !args.replay_quick,
-        )
-    } else {
-        let client = connect().await?;
-        client
-            .configure(
-                &Params {
-                    chan_spec: ChanSpec::new(args.channel, Band::Band5G, BANDWIDTH).unwrap(),
-                    csi_collect: true,
-                    cores: RT_AC86U_EXTERNAL,
-                    // spatial_streams: SpatialStreams::all(),
-                    spatial_streams: SpatialStreams::S0,
-                    first_pkt_byte: None,
-                    mac_addrs: vec![MACBOOK],
-                    delay_us: 0,
+fn source_options(args: &RunArgs, control: ReplayControl) -> anyhow::Result<SourceOptions> {
+    Ok(SourceOptions {
+        router: RouterSource {
+            router: router(),
+            params: Params::builder(ChanSpec::new(args.channel, Band::Band5G, BANDWIDTH).unwrap())
+                .cores(RT_AC86U_EXTERNAL)
+                // .spatial_streams(SpatialStreams::all())
+                .spatial_streams(SpatialStreams::S0)
+                .mac_addr(MACBOOK)
+                .build()?,
+            rmmod: args.rmmod,
+            dump: args.dump.clone().map(|path| Dump {
+                path,
+                rotation: Rotation {
+                    size: args.rotate_size,
+                    duration: args.rotate_time,
                 },
-                args.rmmod,
-            )
-            .await?;
-        (PcapSource::Router(client.tcpdump().await?), false)
+            }),
+        },
+        add_delay: !args.replay_quick,
+        replay: ReplayOptions {
+            start: args.start,
+            end: args.end,
+            looping: args.looping,
+        },
+        control,
+    })
+}
+
+async fn get_input(
+    args: &RunArgs,
+    control: ReplayControl,
+) -> anyhow::Result<BoxStream<'static, anyhow::Result<WifiCsi>>> {
+    let options = source_options(args, control)?;
+    let spec = match &args.replay {
+        Some(path) => SourceSpec::File(path.clone()),
+        None => args.source.clone(),
     };
 
-    if let Some(path) = &args.dump {
-        let mut file = tokio::fs::File::create(path).await?;
-        tokio::io::copy(&mut pcap, &mut file).await?;
-        unreachable!()
+    if args.dump.is_some() && spec != SourceSpec::Router {
+        anyhow::bail!("--dump requires the router source");
+    }
+
+    Ok(spec.into_source(&options).into_stream())
+}
+
+async fn export(input: &Path, output: &Path) -> anyhow::Result<()> {
+    let pcap = tokio::fs::File::open(input).await?;
+    let mut records = pin!(read_csi_records(pcap, false));
+    let file = std::fs::File::create(output)?;
+    let mut rows = 0;
+
+    if output.extension().is_some_and(|ext| ext == "csia") {
+        let mut writer = ArchiveWriter::new(std::io::BufWriter::new(file), &Default::default())?;
+        while let Some(record) = records.try_next().await? {
+            writer.push(&Record::from(record))?;
+            rows += 1;
+        }
+        writer.finish()?;
     } else {
-        Ok(read_wifi_csi(pcap, add_delay))
+        let mut writer = ParquetWriter::new(file)?;
+        while let Some(record) = records.try_next().await? {
+            writer.push(&record)?;
+            rows += 1;
+        }
+        writer.finish()?;
     }
+
+    tracing::info!("exported {rows} rows to {}", output.display());
+
+    Ok(())
 }
 
 fn main() -> anyhow::Result<()> {
@@ -591,10 +737,17 @@ fn main() -> anyhow::Result<()> {
                 .build()
                 .unwrap()
                 .block_on(async {
-                    let client = connect().await.unwrap();
+                    let client = router().connect().await.unwrap();
                     client.exec("/sbin/reboot").await.unwrap();
                 });
         }
+        Command::Export { input, output } => {
+            tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(export(&input, &output))?;
+        }
         _ => unimplemented!(),
     }
 
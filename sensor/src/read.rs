@@ -1,59 +1,340 @@
-use std::pin::{pin, Pin};
+use std::{
+    pin::pin,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
+use anyhow::anyhow;
 use async_stream::try_stream;
 use csi::{
-    frame::Frame,
+    frame::{Frame, FrameRef},
+    import::{esp32, pcapng, picoscenes},
     proc::{FrameGrouper, WifiCsi},
 };
-use futures::Stream;
-use tokio::{io::AsyncRead, time::Instant};
+use futures::{Stream, TryStreamExt};
+use macaddr::MacAddr6;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt},
+    net::UdpSocket,
+    time::Instant,
+};
+
+/// A [`WifiCsi`] together with metadata from the frames it was assembled from.
+#[derive(Debug, Clone)]
+pub struct CsiRecord {
+    /// Capture time of the first frame, relative to the Unix epoch.
+    pub timestamp: Duration,
+    /// MAC address of the transmitter.
+    pub source_mac: MacAddr6,
+    /// 802.11 sequence number shared by the frames.
+    pub seq_cnt: u16,
+    /// Comments of the frames in a pcapng capture, preceded by those of the
+    /// section if this is its first record.
+    pub comments: Vec<String>,
+    pub csi: WifiCsi,
+}
+
+impl From<CsiRecord> for csi::archive::Record {
+    /// Drops the comments, which are not stored in archives.
+    fn from(record: CsiRecord) -> Self {
+        Self {
+            timestamp: record.timestamp,
+            source_mac: record.source_mac,
+            seq_cnt: record.seq_cnt,
+            csi: record.csi,
+        }
+    }
+}
+
+/// A captured packet, from a pcap or pcapng file or a UDP socket.
+struct Packet {
+    timestamp: Duration,
+    data: Vec<u8>,
+    comments: Vec<String>,
+}
+
+/// Read the packets of a pcap or pcapng stream, detecting the format from
+/// the magic number.
+fn read_packets(reader: impl AsyncRead) -> impl Stream<Item = anyhow::Result<Packet>> {
+    try_stream! {
+        let mut reader = pin!(reader);
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic).await?;
+        let mut reader = (&magic[..]).chain(reader);
+
+        if magic == pcapng::SECTION_HEADER.to_le_bytes() {
+            let mut parser = pcapng::Parser::new();
+            let mut block = vec![];
+            let mut section_comments = vec![];
+
+            loop {
+                let mut head = [0; pcapng::BLOCK_HEAD_LEN];
+                match reader.read_exact(&mut head).await {
+                    Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                    r => r?,
+                };
+                reader.read_exact(parser.start_block(&head, &mut block)?).await?;
+
+                match parser.parse(&block)? {
+                    pcapng::Block::SectionHeader { comments } => section_comments = comments,
+                    pcapng::Block::Packet(packet) => {
+                        let link_type = parser.interfaces()[packet.interface as usize].link_type;
+                        if link_type != pcapng::LINKTYPE_ETHERNET {
+                            Err(anyhow!(
+                                "interface {} has link type {link_type}, not Ethernet",
+                                packet.interface
+                            ))?;
+                        }
+                        let mut comments = std::mem::take(&mut section_comments);
+                        comments.extend(packet.comments);
+                        yield Packet {
+                            timestamp: packet.timestamp,
+                            data: packet.data,
+                            comments,
+                        };
+                    }
+                    _ => {}
+                }
+            }
+        } else {
+            let mut packets = pcap_file_tokio::pcap::PcapReader::new(reader).await?;
+            while let Some(pkt) = packets.next_packet().await.transpose()? {
+                yield Packet {
+                    timestamp: pkt.timestamp,
+                    data: pkt.data.into_owned(),
+                    comments: vec![],
+                };
+            }
+        }
+    }
+}
 
-/// Read CSI from a pcap stream.
+/// Read CSI from a pcap or pcapng stream.
 pub fn read_wifi_csi(
     reader: impl AsyncRead,
     add_delay: bool,
 ) -> impl Stream<Item = anyhow::Result<WifiCsi>> {
+    read_csi_records(reader, add_delay).map_ok(|record| record.csi)
+}
+
+/// Like [`read_wifi_csi`], but keeps the capture metadata of each group.
+pub fn read_csi_records(
+    reader: impl AsyncRead,
+    add_delay: bool,
+) -> impl Stream<Item = anyhow::Result<CsiRecord>> {
+    group_packets(read_packets(reader), add_delay, Frame::from_slice)
+}
+
+/// Receive CSI sent by the firmware to a UDP socket, usually bound to
+/// [`UDP_PORT`](csi::frame::UDP_PORT) and with the router forwarding the packets to it. This
+/// bypasses tcpdump and the SSH connection.
+///
+/// Packets that are not CSI frames are ignored.
+pub fn read_udp_records(socket: UdpSocket) -> impl Stream<Item = anyhow::Result<CsiRecord>> {
+    let packets = try_stream! {
+        let mut buf = vec![0; 65536];
+
+        loop {
+            let (len, addr) = socket.recv_from(&mut buf).await?;
+            let data = &buf[..len];
+            if let Err(e) = FrameRef::from_udp_payload(data) {
+                tracing::debug!("ignoring packet from {addr}: {e}");
+                continue;
+            }
+
+            yield Packet {
+                timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?,
+                data: data.to_vec(),
+                comments: vec![],
+            };
+        }
+    };
+
+    group_packets(packets, false, Frame::from_udp_payload)
+}
+
+/// Group the CSI frames in `packets`, which are parsed by `parse`.
+fn group_packets(
+    packets: impl Stream<Item = anyhow::Result<Packet>>,
+    add_delay: bool,
+    parse: fn(&[u8]) -> Result<Frame, csi::frame::Error>,
+) -> impl Stream<Item = anyhow::Result<CsiRecord>> {
     try_stream! {
-        let reader = pin!(reader);
-        let mut frames = pcap_file_tokio::pcap::PcapReader::new(reader).await?;
+        let mut packets = pin!(packets);
         let mut grouper = FrameGrouper::new();
+        // metadata of the group currently held by the grouper
+        let mut meta = None;
+        let mut comments = vec![];
         let mut t_off = None;
         let start = Instant::now();
 
-        while let Some(pkt) = frames.next_packet().await.transpose()? {
-            let t_off = *t_off.get_or_insert(pkt.timestamp);
+        while let Some(packet) = packets.try_next().await? {
+            let t_off = *t_off.get_or_insert(packet.timestamp);
 
             if add_delay {
-                tokio::time::sleep_until(start + pkt.timestamp - t_off).await;
+                tokio::time::sleep_until(start + packet.timestamp.saturating_sub(t_off)).await;
             }
 
-            let frame = Frame::from_slice(&pkt.data)?;
+            let frame = parse(&packet.data)?;
+            let next = (packet.timestamp, frame.source_mac, frame.seq_cnt);
 
-            if let Some(group) = grouper.add(frame) {
-                yield group;
+            if let Some(csi) = grouper.add(frame) {
+                let (timestamp, source_mac, seq_cnt) = meta.unwrap_or(next);
+                let comments = std::mem::take(&mut comments);
+                yield CsiRecord { timestamp, source_mac, seq_cnt, comments, csi };
+                meta = Some(next);
+            } else if meta.is_none() {
+                meta = Some(next);
             }
+            comments.extend(packet.comments);
         }
 
-        if let Some(group) = grouper.take() {
-            yield group;
+        if let (Some(csi), Some((timestamp, source_mac, seq_cnt))) = (grouper.take(), meta) {
+            yield CsiRecord { timestamp, source_mac, seq_cnt, comments, csi };
         }
     }
 }
 
-pub enum PcapSource {
-    Router(rt_ac86u::Tcpdump),
-    File(tokio::fs::File),
+/// Read CSI from the output of the [esp-csi](https://github.com/espressif/esp-csi)
+/// examples, such as a saved log or a serial port. Lines other than
+/// `CSI_DATA` are ignored.
+pub fn read_esp32_csi(
+    reader: impl AsyncBufRead,
+    add_delay: bool,
+) -> impl Stream<Item = anyhow::Result<WifiCsi>> {
+    try_stream! {
+        let reader = pin!(reader);
+        let mut lines = reader.lines();
+        let mut clock = esp32::LocalClock::default();
+        let mut t_off = None;
+        let start = Instant::now();
+
+        while let Some(line) = lines.next_line().await? {
+            let record = match line.parse::<esp32::Record>() {
+                Err(esp32::Error::NotCsi) => continue,
+                record => record?,
+            };
+            let timestamp = clock.time(record.local_timestamp);
+            let t_off = *t_off.get_or_insert(timestamp);
+
+            if add_delay {
+                tokio::time::sleep_until(start + timestamp.saturating_sub(t_off)).await;
+            }
+
+            let chan_spec = record
+                .chan_spec()
+                .ok_or_else(|| anyhow!("invalid channel {}", record.channel))?;
+
+            yield record.to_wifi_csi(chan_spec)?;
+        }
+    }
 }
 
-impl AsyncRead for PcapSource {
-    fn poll_read(
-        self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-        buf: &mut tokio::io::ReadBuf<'_>,
-    ) -> std::task::Poll<std::io::Result<()>> {
-        match self.get_mut() {
-            PcapSource::Router(r) => Pin::new(r).poll_read(cx, buf),
-            PcapSource::File(f) => Pin::new(f).poll_read(cx, buf),
+/// Read CSI from a [PicoScenes](https://ps.zpj.io/) `.csi` file. Frames
+/// without CSI are skipped.
+pub fn read_picoscenes_csi(
+    reader: impl AsyncRead,
+    add_delay: bool,
+) -> impl Stream<Item = anyhow::Result<WifiCsi>> {
+    try_stream! {
+        let mut reader = pin!(reader);
+        let mut buf = vec![];
+        let mut t_off = None;
+        let start = Instant::now();
+
+        // like `picoscenes::FrameReader`, a truncated last frame ends the file
+        loop {
+            let mut head = [0; picoscenes::FRAME_HEAD_LEN];
+            match reader.read_exact(&mut head).await {
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                r => r?,
+            };
+            buf.resize(picoscenes::frame_len(&head)?, 0);
+            match reader.read_exact(&mut buf).await {
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                r => r?,
+            };
+
+            let frame = picoscenes::RxFrame::from_slice(&buf)?;
+            let Some(csi) = frame.to_wifi_csi() else {
+                continue;
+            };
+
+            if let Some(rx_s_basic) = &frame.rx_s_basic {
+                let timestamp = Duration::from_micros(rx_s_basic.timestamp);
+                let t_off = *t_off.get_or_insert(timestamp);
+
+                if add_delay {
+                    tokio::time::sleep_until(start + timestamp.saturating_sub(t_off)).await;
+                }
+            }
+
+            yield csi;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use csi::{
+        ieee80211::{Band, Bandwidth},
+        params::ChanSpec,
+    };
+    use ndarray::Array1;
+    use num_complex::Complex;
+
+    use super::*;
+
+    fn frame(seq_cnt: u16, core: u8) -> Frame {
+        Frame {
+            rssi: -50,
+            source_mac: MacAddr6::new(0x50, 0xed, 0x3c, 0x2e, 0x04, 0x00),
+            seq_cnt,
+            core,
+            spatial: 0,
+            chan_spec: ChanSpec::new(36, Band::Band5G, Bandwidth::Bw20).unwrap(),
+            chip: csi::frame::Chip::Bcm4366c0,
+            csi: Array1::from_elem(64, Complex::new(1., -1.)),
         }
     }
+
+    fn esp32_line(local_timestamp: u32) -> String {
+        let data = vec!["1"; 128].join(",");
+        format!("CSI_DATA,7,aa:bb:cc:dd:ee:ff,-42,11,0,7,0,1,1,0,0,0,0,-95,0,6,0,{local_timestamp},0,76,0,128,0,\"[{data}]\"\n")
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn esp32_timestamp_wrap() -> anyhow::Result<()> {
+        let log = [u32::MAX - 1000, 1000, 1_000_000].map(esp32_line).concat();
+        let start = Instant::now();
+        let csi: Vec<_> = read_esp32_csi(log.as_bytes(), true).try_collect().await?;
+        assert_eq!(csi.len(), 3);
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_micros(1_001_001), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(1100), "{elapsed:?}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn udp_loopback() -> anyhow::Result<()> {
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = socket.local_addr()?;
+        let mut records = pin!(read_udp_records(socket));
+
+        let sender = UdpSocket::bind("127.0.0.1:0").await?;
+        sender.send_to(b"not csi", addr).await?;
+        for (seq_cnt, core) in [(1, 0), (1, 1), (2, 0)] {
+            let bytes = frame(seq_cnt, core).to_bytes().unwrap();
+            sender.send_to(&bytes[42..], addr).await?;
+        }
+
+        let record = records.try_next().await?.unwrap();
+        assert_eq!(record.seq_cnt, 1);
+        assert_eq!(record.source_mac, frame(1, 0).source_mac);
+        assert_eq!(record.csi.get(0, 0), Some(&frame(1, 0).csi));
+        assert_eq!(record.csi.get(1, 0), Some(&frame(1, 1).csi));
+        assert_eq!(record.csi.get(2, 0), None);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,466 @@
+//! Controlled replay of recordings.
+//!
+//! [`replay`] plays back [`CsiRecord`]s at their original pace, scaled by a
+//! speed factor, and optionally trimmed to a time range and looped. A
+//! [`ReplayControl`] pauses, resumes, changes the speed and seeks while the
+//! replay is running.
+//!
+//! ```no_run
+//! # async fn play() -> anyhow::Result<()> {
+//! use std::time::Duration;
+//!
+//! use futures::{stream, TryStreamExt};
+//! use sensor::{
+//!     read::read_csi_records,
+//!     replay::{replay, ReplayControl, ReplayOptions, Seek},
+//! };
+//!
+//! let open = || {
+//!     stream::once(tokio::fs::File::open("capture.pcap"))
+//!         .err_into::<anyhow::Error>()
+//!         .map_ok(|pcap| read_csi_records(pcap, false))
+//!         .try_flatten()
+//! };
+//! let control = ReplayControl::new(2.);
+//! let options = ReplayOptions {
+//!     start: Some(Duration::from_secs(10)),
+//!     looping: true,
+//!     ..Default::default()
+//! };
+//! let mut records = std::pin::pin!(replay(open, options, &control));
+//!
+//! while let Some(record) = records.try_next().await? {
+//!     if record.comments.iter().any(|c| c == "interesting") {
+//!         control.pause();
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{pin::Pin, sync::Arc, time::Duration};
+
+use async_stream::try_stream;
+use futures::{Stream, TryStreamExt};
+use tokio::{sync::watch, time::Instant};
+
+use crate::read::CsiRecord;
+
+/// Position to seek to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Seek {
+    /// Time since the first record of the recording, before trimming.
+    Time(Duration),
+    /// Index of a record, counting from the first record after trimming.
+    Index(usize),
+}
+
+/// Trimming and looping of a replay.
+#[derive(Debug, Clone, Default)]
+pub struct ReplayOptions {
+    /// Skip the records captured earlier than this after the first record.
+    pub start: Option<Duration>,
+    /// Stop at the first record captured later than this after the first
+    /// record.
+    pub end: Option<Duration>,
+    /// Start over after the last record.
+    pub looping: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct State {
+    paused: bool,
+    speed: f64,
+    /// The latest seek, numbered so that repeated seeks to the same
+    /// position are not lost.
+    seek: Option<(u64, Seek)>,
+}
+
+/// Handle controlling a running [`replay`]. Clones control the same replays.
+#[derive(Debug, Clone)]
+pub struct ReplayControl {
+    state: Arc<watch::Sender<State>>,
+}
+
+impl ReplayControl {
+    /// Creates a control that replays at `speed` times the original pace.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `speed` is not positive. See [`set_speed`](Self::set_speed).
+    pub fn new(speed: f64) -> Self {
+        assert!(speed > 0., "invalid replay speed {speed}");
+
+        Self {
+            state: Arc::new(watch::Sender::new(State {
+                paused: false,
+                speed,
+                seek: None,
+            })),
+        }
+    }
+
+    /// Stops yielding records until [`resume`](Self::resume) is called.
+    pub fn pause(&self) {
+        self.state.send_modify(|state| state.paused = true);
+    }
+
+    /// Continues after [`pause`](Self::pause), from the next record.
+    pub fn resume(&self) {
+        self.state.send_modify(|state| state.paused = false);
+    }
+
+    /// Whether the replay is paused.
+    pub fn is_paused(&self) -> bool {
+        self.state.borrow().paused
+    }
+
+    /// Replays at `speed` times the original pace. [`f64::INFINITY`]
+    /// replays as fast as possible.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `speed` is not positive.
+    pub fn set_speed(&self, speed: f64) {
+        assert!(speed > 0., "invalid replay speed {speed}");
+        self.state.send_modify(|state| state.speed = speed);
+    }
+
+    /// The current speed factor.
+    pub fn speed(&self) -> f64 {
+        self.state.borrow().speed
+    }
+
+    /// Continues the replay from `to`. Seeking past the end ends the replay,
+    /// or starts it over if it is looping.
+    pub fn seek(&self, to: Seek) {
+        self.state.send_modify(|state| {
+            let n = state.seek.map_or(0, |(n, _)| n + 1);
+            state.seek = Some((n, to));
+        });
+    }
+}
+
+/// The source of a replay, read one record at a time within the trimmed
+/// range.
+struct Recording<F, S> {
+    open: F,
+    source: Pin<Box<S>>,
+    options: ReplayOptions,
+    /// Timestamp of the first record of the source.
+    t0: Option<Duration>,
+    /// The next record, if it has been read.
+    next: Option<CsiRecord>,
+    /// Index of the next record.
+    index: usize,
+    /// Time of the record before the next one.
+    prev: Option<Duration>,
+    done: bool,
+}
+
+impl<F: FnMut() -> S, S: Stream<Item = anyhow::Result<CsiRecord>>> Recording<F, S> {
+    fn new(mut open: F, options: ReplayOptions) -> Self {
+        Self {
+            source: Box::pin(open()),
+            open,
+            options,
+            t0: None,
+            next: None,
+            index: 0,
+            prev: None,
+            done: false,
+        }
+    }
+
+    /// Time of `record` since the first record.
+    fn time(&self, record: &CsiRecord) -> Duration {
+        record.timestamp.saturating_sub(self.t0.unwrap_or_default())
+    }
+
+    /// Opens the source again, starting over from the first record.
+    fn rewind(&mut self) {
+        self.source = Box::pin((self.open)());
+        self.next = None;
+        self.index = 0;
+        self.prev = None;
+        self.done = false;
+    }
+
+    /// Reads the next record if needed, returning its time, or `None` at the
+    /// end.
+    async fn peek(&mut self) -> anyhow::Result<Option<Duration>> {
+        while self.next.is_none() && !self.done {
+            let Some(record) = self.source.try_next().await? else {
+                self.done = true;
+                break;
+            };
+
+            self.t0.get_or_insert(record.timestamp);
+            let t = self.time(&record);
+            if self.options.end.is_some_and(|end| t > end) {
+                self.done = true;
+            } else if self.options.start.is_none_or(|start| t >= start) {
+                self.next = Some(record);
+            }
+        }
+
+        Ok(self.next.as_ref().map(|record| self.time(record)))
+    }
+
+    /// Takes the record read by [`peek`](Self::peek).
+    fn take(&mut self) -> Option<CsiRecord> {
+        let record = self.next.take()?;
+        self.prev = Some(self.time(&record));
+        self.index += 1;
+        Some(record)
+    }
+
+    /// Skips to the first record at or after `to`, rewinding if it may have
+    /// been read already.
+    async fn seek(&mut self, to: Seek) -> anyhow::Result<()> {
+        let behind = match to {
+            Seek::Index(i) => i < self.index,
+            Seek::Time(t) => self.prev.is_some_and(|prev| prev >= t),
+        };
+        if behind {
+            self.rewind();
+        }
+
+        while let Some(t) = self.peek().await? {
+            let reached = match to {
+                Seek::Index(i) => self.index >= i,
+                Seek::Time(to) => t >= to,
+            };
+            if reached {
+                break;
+            }
+            self.take();
+        }
+
+        Ok(())
+    }
+}
+
+/// Maps the wall clock to the time of the recording.
+struct Clock {
+    /// Recording time at an instant, set when the first record after a start,
+    /// seek or resume is yielded.
+    anchor: Option<(Instant, Duration)>,
+    paused: bool,
+    speed: f64,
+}
+
+impl Clock {
+    /// Recording time at `now`, no later than `next`.
+    fn position(&self, now: Instant, next: Duration) -> Option<Duration> {
+        let (at, pos) = self.anchor?;
+        let elapsed = if self.paused {
+            Duration::ZERO
+        } else {
+            Duration::try_from_secs_f64((now - at).as_secs_f64() * self.speed)
+                .unwrap_or(Duration::MAX)
+        };
+        Some(pos.saturating_add(elapsed).min(next))
+    }
+
+    /// When a record at recording time `t` is due.
+    fn deadline(&self, t: Duration) -> Instant {
+        match self.anchor {
+            Some((at, pos)) => at + t.saturating_sub(pos).div_f64(self.speed),
+            None => Instant::now(),
+        }
+    }
+}
+
+/// Replays the records of the stream returned by `open` at the pace at
+/// which they were captured, under the control of `control`.
+///
+/// The records are read as they are replayed, so only the next one is kept
+/// in memory. Seeking backwards and looping call `open` again and skip to
+/// the requested record, so it should return a stream of the same records
+/// every time, such as by reopening a file. Read the records without delay,
+/// such as with `add_delay` set to false in
+/// [`read_csi_records`](crate::read::read_csi_records).
+pub fn replay<S>(
+    open: impl FnMut() -> S,
+    options: ReplayOptions,
+    control: &ReplayControl,
+) -> impl Stream<Item = anyhow::Result<CsiRecord>>
+where
+    S: Stream<Item = anyhow::Result<CsiRecord>>,
+{
+    let mut state = control.state.subscribe();
+
+    try_stream! {
+        let looping = options.looping;
+        let mut recording = Recording::new(open, options);
+        let initial = *state.borrow_and_update();
+        let mut clock = Clock { anchor: None, paused: initial.paused, speed: initial.speed };
+        let mut seek = initial.seek.map(|(n, _)| n);
+        // whether all controls have been dropped
+        let mut detached = false;
+
+        loop {
+            let Some(t) = recording.peek().await? else {
+                if looping && recording.index > 0 {
+                    recording.rewind();
+                    clock.anchor = None;
+                    continue;
+                }
+                break;
+            };
+
+            if !detached {
+                let current = *state.borrow_and_update();
+
+                if current.paused != clock.paused || current.speed != clock.speed {
+                    let now = Instant::now();
+                    clock.anchor = clock.position(now, t).map(|pos| (now, pos));
+                    clock.paused = current.paused;
+                    clock.speed = current.speed;
+                }
+
+                if let Some((n, to)) = current.seek.filter(|&(n, _)| Some(n) != seek) {
+                    seek = Some(n);
+                    recording.seek(to).await?;
+                    clock.anchor = None;
+                    continue;
+                }
+            }
+
+            if clock.paused {
+                if state.changed().await.is_err() {
+                    detached = true;
+                    clock.paused = false;
+                }
+                continue;
+            }
+
+            tokio::select! {
+                changed = state.changed(), if !detached => {
+                    detached = changed.is_err();
+                    continue;
+                }
+                _ = tokio::time::sleep_until(clock.deadline(t)) => {}
+            }
+
+            if let Some(record) = recording.take() {
+                yield record;
+            }
+            clock.anchor.get_or_insert((Instant::now(), t));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use csi::{
+        ieee80211::{Band, Bandwidth},
+        params::ChanSpec,
+        proc::WifiCsi,
+    };
+    use futures::{stream, StreamExt};
+    use macaddr::MacAddr6;
+
+    use std::cell::Cell;
+
+    use super::*;
+
+    fn recording(n: u16) -> impl Stream<Item = anyhow::Result<CsiRecord>> {
+        let chan_spec = ChanSpec::new(36, Band::Band5G, Bandwidth::Bw20).unwrap();
+        stream::iter((0..n).map(move |seq_cnt| {
+            Ok(CsiRecord {
+                timestamp: Duration::from_secs(100 + u64::from(seq_cnt)),
+                source_mac: MacAddr6::nil(),
+                seq_cnt,
+                comments: vec![],
+                csi: WifiCsi::new(chan_spec, -50),
+            })
+        }))
+    }
+
+    async fn next_seq(
+        records: &mut (impl Stream<Item = anyhow::Result<CsiRecord>> + Unpin),
+    ) -> u16 {
+        records.next().await.unwrap().unwrap().seq_cnt
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pace() -> anyhow::Result<()> {
+        let control = ReplayControl::new(2.);
+        let mut records = Box::pin(replay(|| recording(10), Default::default(), &control));
+        let start = Instant::now();
+
+        assert_eq!(next_seq(&mut records).await, 0);
+        assert_eq!(next_seq(&mut records).await, 1);
+        assert_eq!(start.elapsed(), Duration::from_millis(500));
+
+        control.set_speed(0.5);
+        assert_eq!(next_seq(&mut records).await, 2);
+        assert_eq!(start.elapsed(), Duration::from_millis(2500));
+
+        control.set_speed(f64::INFINITY);
+        let rest: Vec<_> = records.map_ok(|r| r.seq_cnt).try_collect().await?;
+        assert_eq!(rest, (3..10).collect::<Vec<_>>());
+        assert_eq!(start.elapsed(), Duration::from_millis(2500));
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pause_and_seek() {
+        let control = ReplayControl::new(1.);
+        let opens = Cell::new(0);
+        let open = || {
+            opens.set(opens.get() + 1);
+            recording(10)
+        };
+        let mut records = Box::pin(replay(open, Default::default(), &control));
+
+        assert_eq!(next_seq(&mut records).await, 0);
+        control.pause();
+        assert!(
+            tokio::time::timeout(Duration::from_secs(60), records.next())
+                .await
+                .is_err()
+        );
+
+        control.seek(Seek::Time(Duration::from_millis(6500)));
+        control.resume();
+        let start = Instant::now();
+        assert_eq!(next_seq(&mut records).await, 7);
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        assert_eq!(next_seq(&mut records).await, 8);
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+        assert_eq!(opens.get(), 1);
+
+        control.seek(Seek::Index(2));
+        assert_eq!(next_seq(&mut records).await, 2);
+        control.seek(Seek::Index(2));
+        assert_eq!(next_seq(&mut records).await, 2);
+        assert_eq!(opens.get(), 3);
+
+        control.seek(Seek::Index(5));
+        assert_eq!(next_seq(&mut records).await, 5);
+        assert_eq!(opens.get(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn trim_and_loop() {
+        let control = ReplayControl::new(f64::INFINITY);
+        let options = ReplayOptions {
+            start: Some(Duration::from_secs(2)),
+            end: Some(Duration::from_secs(4)),
+            looping: true,
+        };
+        let opens = Cell::new(0);
+        let open = || {
+            opens.set(opens.get() + 1);
+            recording(10)
+        };
+        let records = replay(open, options, &control);
+        let seq: Vec<_> = records.take(7).map(|r| r.unwrap().seq_cnt).collect().await;
+        assert_eq!(seq, [2, 3, 4, 2, 3, 4, 2]);
+        assert_eq!(opens.get(), 3);
+    }
+}
@@ -0,0 +1,460 @@
+//! Sources of CSI.
+//!
+//! Every source implements [`CsiSource`], so the rest of the sensor does not
+//! care where the CSI comes from. [`SourceSpec`] selects a source from a
+//! command line argument such as `file:capture.pcapng` or `udp:0.0.0.0:5500`.
+
+use std::{
+    f64::consts::TAU,
+    future::Future,
+    net::{Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail};
+use async_ssh2_tokio::client::{AuthMethod, Client, ServerCheckMethod};
+use async_stream::stream;
+use csi::{
+    frame::UDP_PORT,
+    ieee80211::subcarrier_lambda,
+    params::{ChanSpec, Params},
+    proc::WifiCsi,
+};
+use futures::{
+    stream::{self, BoxStream},
+    Stream, StreamExt, TryStreamExt,
+};
+use ndarray::Array1;
+use num_complex::Complex;
+use rand::{rngs::StdRng, SeedableRng};
+use rand_distr::{Distribution, Normal};
+use rt_ac86u::RtAc86u;
+use tokio::{io::BufReader, net::UdpSocket};
+
+use crate::{
+    dump::{tee_pcap, Dump},
+    read::{
+        read_csi_records, read_esp32_csi, read_picoscenes_csi, read_udp_records, read_wifi_csi,
+    },
+    replay::{replay, ReplayControl, ReplayOptions},
+};
+
+/// A source of CSI, such as a capture file or a live router.
+pub trait CsiSource: Send {
+    /// Starts reading CSI. Any setup, such as connecting to the router,
+    /// happens when the stream is first polled and its errors are yielded
+    /// by the stream.
+    fn into_stream(self: Box<Self>) -> BoxStream<'static, anyhow::Result<WifiCsi>>;
+}
+
+/// Creates a stream from the stream returned by an asynchronous setup.
+fn after<S>(
+    setup: impl Future<Output = anyhow::Result<S>> + Send + 'static,
+) -> BoxStream<'static, anyhow::Result<WifiCsi>>
+where
+    S: Stream<Item = anyhow::Result<WifiCsi>> + Send + 'static,
+{
+    stream::once(setup).try_flatten().boxed()
+}
+
+/// SSH connection details of an RT-AC86U.
+#[derive(Debug, Clone)]
+pub struct Router {
+    /// Host name or IP address.
+    pub host: String,
+    /// SSH port.
+    pub port: u16,
+    /// Login name.
+    pub username: String,
+    /// Password.
+    pub password: String,
+}
+
+impl Router {
+    /// Connects to the router.
+    pub async fn connect(&self) -> anyhow::Result<RtAc86u> {
+        tracing::info!("connecting");
+
+        let client = Client::connect(
+            (self.host.as_str(), self.port),
+            &self.username,
+            AuthMethod::with_password(&self.password),
+            ServerCheckMethod::NoCheck,
+        )
+        .await?;
+        let client = RtAc86u::new(client);
+
+        tracing::info!("connected!");
+
+        Ok(client)
+    }
+}
+
+/// Captures CSI on the router with tcpdump, streamed over SSH.
+#[derive(Debug, Clone)]
+pub struct RouterSource {
+    /// The router.
+    pub router: Router,
+    /// Parameters to configure the firmware with.
+    pub params: Params,
+    /// Remove and reinsert the dhd kernel module before configuring.
+    pub rmmod: bool,
+    /// Also write the captured pcap to disk. See [`tee_pcap`].
+    pub dump: Option<Dump>,
+}
+
+impl RouterSource {
+    /// Connects to the router and configures the firmware.
+    pub async fn configure(&self) -> anyhow::Result<RtAc86u> {
+        let client = self.router.connect().await?;
+        client.configure(&self.params, self.rmmod).await?;
+        Ok(client)
+    }
+}
+
+impl CsiSource for RouterSource {
+    fn into_stream(self: Box<Self>) -> BoxStream<'static, anyhow::Result<WifiCsi>> {
+        after(async move {
+            let client = self.configure().await?;
+            let pcap = client.tcpdump().await?;
+
+            Ok(match self.dump {
+                Some(dump) => read_wifi_csi(tee_pcap(pcap, dump), false).boxed(),
+                None => read_wifi_csi(pcap, false).boxed(),
+            })
+        })
+    }
+}
+
+/// Receives CSI that the router forwards to a UDP socket. See
+/// [`read_udp_records`].
+#[derive(Debug, Clone)]
+pub struct UdpSource {
+    /// Address to bind to.
+    pub addr: SocketAddr,
+    /// Router to configure before listening, if any.
+    pub configure: Option<RouterSource>,
+}
+
+impl CsiSource for UdpSource {
+    fn into_stream(self: Box<Self>) -> BoxStream<'static, anyhow::Result<WifiCsi>> {
+        after(async move {
+            if let Some(router) = &self.configure {
+                router.configure().await?;
+            }
+
+            let socket = UdpSocket::bind(self.addr).await?;
+            tracing::info!("listening on {}", socket.local_addr()?);
+            Ok(read_udp_records(socket).map_ok(|record| record.csi))
+        })
+    }
+}
+
+/// Replays a pcap or pcapng file. See [`replay`].
+#[derive(Debug, Clone)]
+pub struct FileSource {
+    /// Path of the capture.
+    pub path: PathBuf,
+    /// Trimming and looping.
+    pub options: ReplayOptions,
+    /// Controls the pace of the replay.
+    pub control: ReplayControl,
+}
+
+impl CsiSource for FileSource {
+    fn into_stream(self: Box<Self>) -> BoxStream<'static, anyhow::Result<WifiCsi>> {
+        let path = self.path;
+        let open = move || {
+            stream::once(tokio::fs::File::open(path.clone()))
+                .err_into::<anyhow::Error>()
+                .map_ok(|file| read_csi_records(file, false))
+                .try_flatten()
+        };
+        replay(open, self.options, &self.control)
+            .map_ok(|record| record.csi)
+            .boxed()
+    }
+}
+
+/// Reads a pcap or pcapng stream from standard input, such as the output of
+/// `tcpdump -w -`.
+#[derive(Debug, Clone, Default)]
+pub struct StdinSource;
+
+impl CsiSource for StdinSource {
+    fn into_stream(self: Box<Self>) -> BoxStream<'static, anyhow::Result<WifiCsi>> {
+        read_wifi_csi(tokio::io::stdin(), false).boxed()
+    }
+}
+
+/// Reads the output of the [esp-csi](https://github.com/espressif/esp-csi)
+/// examples. See [`read_esp32_csi`].
+#[derive(Debug, Clone)]
+pub struct Esp32Source {
+    /// Path of the log or serial port.
+    pub path: PathBuf,
+    /// Wait between records as long as during the capture.
+    pub add_delay: bool,
+}
+
+impl CsiSource for Esp32Source {
+    fn into_stream(self: Box<Self>) -> BoxStream<'static, anyhow::Result<WifiCsi>> {
+        after(async move {
+            let file = BufReader::new(tokio::fs::File::open(&self.path).await?);
+            Ok(read_esp32_csi(file, self.add_delay))
+        })
+    }
+}
+
+/// Reads a [PicoScenes](https://ps.zpj.io/) `.csi` file. See
+/// [`read_picoscenes_csi`].
+#[derive(Debug, Clone)]
+pub struct PicoScenesSource {
+    /// Path of the file.
+    pub path: PathBuf,
+    /// Wait between frames as long as during the capture.
+    pub add_delay: bool,
+}
+
+impl CsiSource for PicoScenesSource {
+    fn into_stream(self: Box<Self>) -> BoxStream<'static, anyhow::Result<WifiCsi>> {
+        after(async move {
+            let file = BufReader::new(tokio::fs::File::open(&self.path).await?);
+            Ok(read_picoscenes_csi(file, self.add_delay))
+        })
+    }
+}
+
+/// Generates the CSI of a plane wave arriving at the RT-AC86U's external
+/// antennas (cores 0, 3 and 1 from left to right, as in
+/// [`aoa`](csi::proc::aoa)), plus complex Gaussian noise.
+#[derive(Debug, Clone)]
+pub struct SyntheticSource {
+    /// Channel of the generated CSI.
+    pub chan_spec: ChanSpec,
+    /// Angle of arrival (rad).
+    pub angle: f64,
+    /// Antenna spacing (m).
+    pub spacing: f64,
+    /// Standard deviation of the noise, relative to the signal amplitude.
+    pub noise: f64,
+    /// Time between generated samples.
+    pub interval: Duration,
+    /// Seed of the noise generator.
+    pub seed: u64,
+}
+
+impl SyntheticSource {
+    /// Amplitude of the generated signal, similar to that of real captures.
+    const AMPLITUDE: f64 = 1000.;
+
+    fn generate(&self, rng: &mut StdRng) -> WifiCsi {
+        let noise = Normal::new(0., self.noise * Self::AMPLITUDE / 2f64.sqrt()).unwrap();
+        let wavelengths = subcarrier_lambda(self.chan_spec.center(), self.chan_spec.bandwidth());
+        let mut csi = WifiCsi::new(self.chan_spec, -50);
+
+        for (core, position) in [(1, 0.), (3, 1.), (0, 2.)] {
+            let path = position * self.spacing * self.angle.sin();
+            let frame: Array1<_> = wavelengths
+                .iter()
+                .map(|lambda| {
+                    Complex::from_polar(Self::AMPLITUDE, TAU * path / lambda)
+                        + Complex::new(noise.sample(rng), noise.sample(rng))
+                })
+                .collect();
+            csi.insert(core, 0, frame);
+        }
+
+        csi
+    }
+}
+
+impl CsiSource for SyntheticSource {
+    fn into_stream(self: Box<Self>) -> BoxStream<'static, anyhow::Result<WifiCsi>> {
+        stream! {
+            let mut rng = StdRng::seed_from_u64(self.seed);
+            let mut interval = tokio::time::interval(self.interval);
+
+            loop {
+                interval.tick().await;
+                yield Ok(self.generate(&mut rng));
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Selects a [`CsiSource`] from a string of the form `kind[:argument]`.
+///
+/// | Source | Argument |
+/// |--------|----------|
+/// | `router` | none |
+/// | `udp` | address to bind to, default `0.0.0.0:5500` |
+/// | `udp-configure` | like `udp`, but configures the router first |
+/// | `file` | path of a pcap or pcapng file |
+/// | `stdin` | none |
+/// | `esp32` | path of an esp-csi log or serial port |
+/// | `picoscenes` | path of a PicoScenes `.csi` file |
+/// | `synthetic` | angle of arrival (degrees), default 0 |
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceSpec {
+    /// [`RouterSource`].
+    Router,
+    /// [`UdpSource`].
+    Udp(SocketAddr),
+    /// [`UdpSource`], which configures the router first.
+    UdpConfigure(SocketAddr),
+    /// [`FileSource`].
+    File(PathBuf),
+    /// [`StdinSource`].
+    Stdin,
+    /// [`Esp32Source`].
+    Esp32(PathBuf),
+    /// [`PicoScenesSource`].
+    PicoScenes(PathBuf),
+    /// [`SyntheticSource`] with the given angle of arrival (degrees).
+    Synthetic(f64),
+}
+
+impl FromStr for SourceSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, arg) = match s.split_once(':') {
+            Some((kind, arg)) => (kind, Some(arg)),
+            None => (s, None),
+        };
+        let path = || {
+            arg.map(PathBuf::from)
+                .ok_or_else(|| anyhow!("{kind} source requires a path"))
+        };
+
+        let addr = || -> anyhow::Result<SocketAddr> {
+            Ok(match arg {
+                Some(addr) => addr.parse()?,
+                None => (Ipv4Addr::UNSPECIFIED, UDP_PORT).into(),
+            })
+        };
+
+        Ok(match kind {
+            "router" => Self::Router,
+            "udp" => Self::Udp(addr()?),
+            "udp-configure" => Self::UdpConfigure(addr()?),
+            "file" => Self::File(path()?),
+            "stdin" => Self::Stdin,
+            "esp32" => Self::Esp32(path()?),
+            "picoscenes" => Self::PicoScenes(path()?),
+            "synthetic" => Self::Synthetic(arg.map_or(Ok(0.), str::parse)?),
+            _ => bail!("unknown source {kind:?}"),
+        })
+    }
+}
+
+/// Settings shared by the sources created by [`SourceSpec::into_source`].
+#[derive(Debug, Clone)]
+pub struct SourceOptions {
+    /// Router to capture on or configure.
+    pub router: RouterSource,
+    /// Wait between samples read from ESP32 and PicoScenes files as long as
+    /// during the capture.
+    pub add_delay: bool,
+    /// Trimming and looping of replayed pcap and pcapng files.
+    pub replay: ReplayOptions,
+    /// Controls the replay of pcap and pcapng files.
+    pub control: ReplayControl,
+}
+
+impl SourceSpec {
+    /// Creates the source.
+    pub fn into_source(self, options: &SourceOptions) -> Box<dyn CsiSource> {
+        let add_delay = options.add_delay;
+
+        match self {
+            Self::Router => Box::new(options.router.clone()),
+            Self::Udp(addr) => Box::new(UdpSource {
+                addr,
+                configure: None,
+            }),
+            Self::UdpConfigure(addr) => Box::new(UdpSource {
+                addr,
+                configure: Some(options.router.clone()),
+            }),
+            Self::File(path) => Box::new(FileSource {
+                path,
+                options: options.replay.clone(),
+                control: options.control.clone(),
+            }),
+            Self::Stdin => Box::new(StdinSource),
+            Self::Esp32(path) => Box::new(Esp32Source { path, add_delay }),
+            Self::PicoScenes(path) => Box::new(PicoScenesSource { path, add_delay }),
+            Self::Synthetic(angle) => Box::new(SyntheticSource {
+                chan_spec: options.router.params.chan_spec,
+                angle: angle.to_radians(),
+                spacing: 0.088,
+                noise: 0.05,
+                interval: Duration::from_millis(10),
+                seed: 0,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use csi::{
+        ieee80211::{Band, Bandwidth},
+        proc::aoa,
+    };
+
+    use super::*;
+
+    #[test]
+    fn parse_spec() {
+        assert_eq!("router".parse::<SourceSpec>().unwrap(), SourceSpec::Router);
+        assert_eq!(
+            "udp".parse::<SourceSpec>().unwrap(),
+            SourceSpec::Udp("0.0.0.0:5500".parse().unwrap())
+        );
+        assert_eq!(
+            "udp:127.0.0.1:6000".parse::<SourceSpec>().unwrap(),
+            SourceSpec::Udp("127.0.0.1:6000".parse().unwrap())
+        );
+        assert_eq!(
+            "udp-configure".parse::<SourceSpec>().unwrap(),
+            SourceSpec::UdpConfigure("0.0.0.0:5500".parse().unwrap())
+        );
+        assert_eq!(
+            "file:a:b.pcap".parse::<SourceSpec>().unwrap(),
+            SourceSpec::File("a:b.pcap".into())
+        );
+        assert_eq!(
+            "synthetic:-20".parse::<SourceSpec>().unwrap(),
+            SourceSpec::Synthetic(-20.)
+        );
+        assert!("file".parse::<SourceSpec>().is_err());
+        assert!("carrier-pigeon".parse::<SourceSpec>().is_err());
+    }
+
+    #[tokio::test]
+    async fn synthetic() -> anyhow::Result<()> {
+        let source = Box::new(SyntheticSource {
+            chan_spec: ChanSpec::new(36, Band::Band5G, Bandwidth::Bw20).unwrap(),
+            angle: 5f64.to_radians(),
+            spacing: 0.088,
+            noise: 0.,
+            interval: Duration::from_millis(1),
+            seed: 0,
+        });
+        let csi: Vec<_> = source.into_stream().take(3).try_collect().await?;
+        assert_eq!(csi.len(), 3);
+
+        let [near, far] = aoa(&csi[0], 0.088).unwrap();
+        for angle in near.iter().chain(&far) {
+            assert!((angle.to_degrees() - 5.).abs() < 1e-6);
+        }
+
+        Ok(())
+    }
+}